@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+
+use anyhow::Context;
+
+use serde_json::json;
+
+use sdam::{duration_to_frame_offset, parse_time, MarkManager, Notes, SplitPoint};
+
+mod marks;
+
+use marks::{format_timestamp, MarksFormat};
+
+const USAGE: &str="Usage: sdam-cli <command> [arguments]
+
+Commands:
+  info <document> [--json]                                   Show the duration, frame count, marks and notes size
+  export-audio <document> <output.wav> [--from T] [--to T]   Export the audio, or its part, to a WAV file
+  export-marks <document> [output] [--format csv|json]       Export the marks to a file, or print them
+  import <audio.wav> <output.sdam> [--marks F] [--notes F]   Create a document of a WAV file, with marks and notes
+  merge <output.sdam> <document> <document>...              Join documents in order
+  split <document> <directory> (--at T | --at-mark ID)...    Split a document at times or marks
+  validate <document>...                                     Check documents for problems
+
+Times are given in seconds, minutes:seconds or hours:minutes:seconds.
+
+Exit codes:
+  0  Success
+  1  The operation failed
+  2  Invalid usage
+  3  A validated document has problems";
+
+const FAILURE: u8=1;
+const USAGE_ERROR: u8=2;
+const INVALID_DOCUMENT: u8=3;
+
+enum CliError {
+    Usage(String),
+    Failed(anyhow::Error),
+    /// A validated document has problems. They were already reported.
+    InvalidDocument,
+    }
+impl From<anyhow::Error> for CliError {
+
+    fn from(error: anyhow::Error) -> CliError {
+        CliError::Failed(error)
+        }
+    }
+
+fn usage_error(message: &str) -> CliError {
+    CliError::Usage(message.to_string())
+    }
+
+/// Positional arguments and options of a command.
+#[derive(Debug, Default, PartialEq)]
+struct Arguments {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+    }
+impl Arguments {
+
+    /// Parses the arguments, given the options of the command taking a value and the ones without it.
+    fn parse(arguments: &[String], valued: &[&str], flags: &[&str]) -> Result<Arguments, CliError> {
+        let mut result=Arguments::default();
+        let mut arguments=arguments.iter();
+
+        while let Some(argument)=arguments.next() {
+            if valued.contains(&argument.as_str()) {
+                let value=arguments.next().ok_or_else(|| usage_error(&format!("{} needs a value", argument)))?;
+                result.options.push((argument.clone(), Some(value.clone())));
+                }
+            else if flags.contains(&argument.as_str()) {
+                result.options.push((argument.clone(), None));
+                }
+            else if argument.starts_with("--") {
+                return Err(usage_error(&format!("Unknown option {}", argument)));
+                }
+            else {
+                result.positional.push(argument.clone());
+                }
+            }
+
+        Ok(result)
+        }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).into_iter().last()
+        }
+    fn values(&self, name: &str) -> Vec<&str> {
+        self.options.iter()
+        .filter(|(option, _)| option==name)
+        .filter_map(|(_, value)| value.as_deref())
+        .collect()
+        }
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option==name)
+        }
+    /// The positional arguments, checked to be between the given counts.
+    fn positional(&self, min: usize, max: usize) -> Result<&[String], CliError> {
+        if self.positional.len()<min || self.positional.len()>max {
+            return Err(usage_error("Wrong number of arguments"));
+            }
+
+        Ok(&self.positional)
+        }
+    }
+
+fn main() -> ExitCode {
+    let arguments: Vec<String>=std::env::args().skip(1).collect();
+
+    match run(&arguments) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(message)) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            ExitCode::from(USAGE_ERROR)
+            },
+        Err(CliError::Failed(error)) => {
+            eprintln!("Error: {:#}", error);
+            ExitCode::from(FAILURE)
+            },
+        Err(CliError::InvalidDocument) => ExitCode::from(INVALID_DOCUMENT),
+        }
+    }
+
+fn run(arguments: &[String]) -> Result<(), CliError> {
+    let (command, arguments)=match arguments.split_first() {
+        Some((command, arguments)) => (command.as_str(), arguments),
+        None => return Err(usage_error("No command given")),
+        };
+
+    match command {
+        "info" => info(&Arguments::parse(arguments, &[], &["--json"])?),
+        "export-audio" => export_audio(&Arguments::parse(arguments, &["--from", "--to"], &[])?),
+        "export-marks" => export_marks(&Arguments::parse(arguments, &["--format"], &[])?),
+        "import" => import(&Arguments::parse(arguments, &["--marks", "--notes"], &[])?),
+        "merge" => merge(&Arguments::parse(arguments, &[], &[])?),
+        "split" => split(&Arguments::parse(arguments, &["--at", "--at-mark"], &[])?),
+        "validate" => validate(&Arguments::parse(arguments, &[], &[])?),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+            },
+        _ => Err(usage_error(&format!("Unknown command {}", command))),
+        }
+    }
+
+fn info(arguments: &Arguments) -> Result<(), CliError> {
+    let path=&arguments.positional(1, 1)?[0];
+    let info=sdam::document_info(path).with_context(|| format!("Can't read {}", path))?;
+
+    let mut categories: BTreeMap<usize, usize>=BTreeMap::new();
+    for mark in info.marks() {
+        *categories.entry(*mark.category()).or_default()+=1;
+        }
+
+    let text=info.notes().text();
+    let note_lines=info.notes().lines().iter().filter(|line| !line.text().trim().is_empty()).count();
+
+    if arguments.flag("--json") {
+        let categories: BTreeMap<String, usize>=categories.into_iter()
+        .map(|(category, count)| (category.to_string(), count))
+        .collect();
+
+        let info=json!({
+            "format": if *info.legacy() { "legacy" } else { "indexed" },
+            "duration_seconds": info.duration().as_secs_f64(),
+            "frame_count": info.frame_count(),
+            "marks": info.marks().len(),
+            "marks_by_category": categories,
+            "note_lines": note_lines,
+            "note_characters": text.chars().count(),
+            "transcript_segments": info.transcript_segments(),
+            "speaker_segments": info.speaker_segments(),
+            });
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+
+        return Ok(());
+        }
+
+    let categories: Vec<String>=categories.iter()
+    .map(|(category, count)| format!("category {}: {}", category, count))
+    .collect();
+
+    println!("Format: {}", if *info.legacy() { "legacy" } else { "indexed" });
+    println!("Duration: {} ({} frames)", format_timestamp(info.duration()), info.frame_count());
+    if categories.is_empty() {
+        println!("Marks: 0");
+        }
+    else {
+        println!("Marks: {} ({})", info.marks().len(), categories.join(", "));
+        }
+    println!("Notes: {} lines, {} characters", note_lines, text.chars().count());
+    println!("Transcript: {} segments", info.transcript_segments());
+    println!("Speakers: {} segments", info.speaker_segments());
+
+    Ok(())
+    }
+
+fn export_audio(arguments: &Arguments) -> Result<(), CliError> {
+    let positional=arguments.positional(2, 2)?;
+    let start_frame=match arguments.value("--from") {
+        Some(time) => duration_to_frame_offset(parse_time(time).map_err(|message| usage_error(&message))?),
+        None => 0,
+        };
+    let end_frame=match arguments.value("--to") {
+        Some(time) => duration_to_frame_offset(parse_time(time).map_err(|message| usage_error(&message))?),
+        None => usize::MAX,
+        };
+
+    if start_frame>=end_frame {
+        return Err(usage_error("The start must be before the end"));
+        }
+
+    sdam::export_document_audio(&positional[0], &positional[1], start_frame, end_frame).with_context(|| format!("Can't export the audio of {}", positional[0]))?;
+
+    Ok(())
+    }
+
+fn export_marks(arguments: &Arguments) -> Result<(), CliError> {
+    let positional=arguments.positional(1, 2)?;
+    let output=positional.get(1);
+
+    let format=match (arguments.value("--format"), output) {
+        (Some(name), _) => MarksFormat::parse(name).ok_or_else(|| usage_error(&format!("Unknown format {}", name)))?,
+        (None, Some(output)) => MarksFormat::of_path(output),
+        (None, None) => MarksFormat::Csv,
+        };
+
+    let info=sdam::document_info(&positional[0]).with_context(|| format!("Can't read {}", positional[0]))?;
+    let rendered=marks::render(info.marks(), format);
+
+    match output {
+        Some(output) => std::fs::write(output, rendered).with_context(|| format!("Can't write {}", output))?,
+        None => print!("{}", rendered),
+        }
+
+    Ok(())
+    }
+
+fn import(arguments: &Arguments) -> Result<(), CliError> {
+    let positional=arguments.positional(2, 2)?;
+
+    let marks=match arguments.value("--marks") {
+        Some(path) => {
+            let text=std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path))?;
+
+            marks::parse(&text, MarksFormat::of_path(path)).map_err(|message| CliError::Failed(anyhow::anyhow!("{}: {}", path, message)))?
+            },
+        None => MarkManager::new(),
+        };
+    let notes=match arguments.value("--notes") {
+        Some(path) => Notes::from_text(&std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path))?),
+        None => Notes::new(),
+        };
+
+    sdam::import_wav(&positional[0], &positional[1], marks, notes).with_context(|| format!("Can't import {}", positional[0]))?;
+
+    Ok(())
+    }
+
+fn merge(arguments: &Arguments) -> Result<(), CliError> {
+    let positional=arguments.positional(3, usize::MAX)?;
+    let documents: Vec<&str>=positional[1..].iter().map(|path| path.as_str()).collect();
+
+    sdam::merge_documents(&documents, &positional[0]).context("Can't merge the documents")?;
+
+    Ok(())
+    }
+
+fn split(arguments: &Arguments) -> Result<(), CliError> {
+    let positional=arguments.positional(2, 2)?;
+
+    let mut points: Vec<SplitPoint>=Vec::new();
+    for time in arguments.values("--at") {
+        points.push(SplitPoint::Frame(duration_to_frame_offset(parse_time(time).map_err(|message| usage_error(&message))?)));
+        }
+    for id in arguments.values("--at-mark") {
+        points.push(SplitPoint::Mark(id.parse().map_err(|_| usage_error(&format!("'{}' is not a mark id", id)))?));
+        }
+
+    if points.is_empty() {
+        return Err(usage_error("No split points given"));
+        }
+
+    for path in sdam::split_document(&positional[0], &points, &positional[1]).with_context(|| format!("Can't split {}", positional[0]))? {
+        println!("{}", path.display());
+        }
+
+    Ok(())
+    }
+
+/// Validates every given document, reporting each. Documents which can't be opened count as invalid too.
+fn validate(arguments: &Arguments) -> Result<(), CliError> {
+    let mut valid=true;
+
+    for path in arguments.positional(1, usize::MAX)? {
+        match sdam::validate_document(path) {
+            Ok(problems) if problems.is_empty() => println!("{}: OK", path),
+            Ok(problems) => {
+                valid=false;
+
+                println!("{}:", path);
+                for problem in problems {
+                    println!("  {}", problem);
+                    }
+                },
+            Err(error) => {
+                valid=false;
+                println!("{}: {:#}", path, error);
+                },
+            }
+        }
+
+    if valid { Ok(()) } else { Err(CliError::InvalidDocument) }
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn strings(arguments: &[&str]) -> Vec<String> {
+        arguments.iter().map(|argument| argument.to_string()).collect()
+        }
+
+    #[test]
+    fn arguments_test() {
+        let arguments=Arguments::parse(&strings(&["in.sdam", "--at", "1:00", "out", "--at", "90", "--json"]), &["--at"], &["--json"]).ok().unwrap();
+        assert_eq!(arguments.positional, strings(&["in.sdam", "out"]));
+        assert_eq!(arguments.values("--at"), vec!["1:00", "90"]);
+        assert_eq!(arguments.value("--at"), Some("90"));
+        assert!(arguments.flag("--json"));
+        assert!(arguments.positional(2, 2).is_ok());
+        assert!(arguments.positional(3, usize::MAX).is_err());
+
+        assert!(matches!(Arguments::parse(&strings(&["--at"]), &["--at"], &[]), Err(CliError::Usage(_))));
+        assert!(matches!(Arguments::parse(&strings(&["--unknown"]), &[], &[]), Err(CliError::Usage(_))));
+        assert!(matches!(run(&strings(&["frobnicate"])), Err(CliError::Usage(_))));
+        assert!(matches!(run(&strings(&["info", "/nonexistent/document.sdam"])), Err(CliError::Failed(_))));
+        }
+    }
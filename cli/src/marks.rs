@@ -0,0 +1,189 @@
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use sdam::{parse_time, Mark, MarkManager};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MarksFormat {
+    Csv,
+    Json,
+    }
+impl MarksFormat {
+
+    pub fn parse(name: &str) -> Option<MarksFormat> {
+        match name {
+            "csv" => Some(MarksFormat::Csv),
+            "json" => Some(MarksFormat::Json),
+            _ => None,
+            }
+        }
+    /// The format of the file by its extension, CSV unless it's a .json file.
+    pub fn of_path(path: &str) -> MarksFormat {
+        match Path::new(path).extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => MarksFormat::Json,
+            _ => MarksFormat::Csv,
+            }
+        }
+    }
+
+/// A mark as exported, with its time written out, so the files stay readable without knowing the frame length.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct MarkRecord {
+    #[serde(default)]
+    id: Option<u64>,
+    time: String,
+    category: usize,
+    #[serde(default)]
+    label: Option<String>,
+    }
+
+/// Formats the time as hours:minutes:seconds with milliseconds, a form parse_time reads back.
+pub fn format_timestamp(time: Duration) -> String {
+    let seconds=time.as_secs();
+
+    format!("{:0>2}:{:0>2}:{:0>2}.{:0>3}", seconds/3600, seconds/60%60, seconds%60, time.subsec_millis())
+    }
+
+/// Renders the marks in the given format, ordered by their position.
+pub fn render(marks: &[Mark], format: MarksFormat) -> String {
+    let mut marks: Vec<&Mark>=marks.iter().collect();
+    marks.sort_by_key(|mark| *mark.frame_offset());
+
+    let records: Vec<MarkRecord>=marks.iter()
+    .map(|mark| MarkRecord {
+        id: *mark.id(),
+        time: format_timestamp(mark.time()),
+        category: *mark.category(),
+        label: mark.label().clone(),
+        })
+    .collect();
+
+    match format {
+        MarksFormat::Json => serde_json::to_string_pretty(&records).unwrap()+"\n",
+        MarksFormat::Csv => {
+            let mut result="id,time,category,label\n".to_string();
+
+            for record in records {
+                let id=record.id.map(|id| id.to_string()).unwrap_or_default();
+                let label=record.label.as_deref().map(csv_field).unwrap_or_default();
+
+                result+=&format!("{},{},{},{}\n", id, record.time, record.category, label);
+                }
+
+            result
+            },
+        }
+    }
+
+/// Parses marks written by render. The ids of the records are not kept, the marks are numbered anew.
+pub fn parse(text: &str, format: MarksFormat) -> Result<MarkManager, String> {
+    let records: Vec<MarkRecord>=match format {
+        MarksFormat::Json => serde_json::from_str(text).map_err(|err| err.to_string())?,
+        MarksFormat::Csv => {
+            let mut rows=csv_rows(text)?.into_iter();
+
+            if rows.next().is_some_and(|header| header!=["id", "time", "category", "label"]) {
+                return Err("The header must be id,time,category,label".to_string());
+                }
+
+            rows.enumerate()
+            .map(|(index, row)| match &row[..] {
+                [_, time, category, label] => Ok(MarkRecord {
+                    id: None,
+                    time: time.clone(),
+                    category: category.parse().map_err(|_| format!("Invalid category on row {}", index+2))?,
+                    label: if label.is_empty() { None } else { Some(label.clone()) },
+                    }),
+                _ => Err(format!("Row {} doesn't have 4 fields", index+2)),
+                })
+            .collect::<Result<Vec<MarkRecord>, String>>()?
+            },
+        };
+
+    let mut marks=MarkManager::new();
+    for record in records {
+        marks.add(Mark::at(parse_time(&record.time)?, record.category, record.label));
+        }
+
+    Ok(marks)
+    }
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+        }
+    else {
+        text.to_string()
+        }
+    }
+/// Splits the text into rows of fields. Quoted fields may contain commas, line breaks and doubled quotes.
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows: Vec<Vec<String>>=Vec::new();
+    let mut row: Vec<String>=Vec::new();
+    let mut field=String::new();
+    let mut quoted=false;
+    let mut characters=text.chars().peekable();
+
+    while let Some(character)=characters.next() {
+        match (character, quoted) {
+            ('"', true) if characters.peek()==Some(&'"') => {
+                characters.next();
+                field.push('"');
+                },
+            ('"', true) => quoted=false,
+            ('"', false) if field.is_empty() => quoted=true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {},
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                },
+            (character, _) => field.push(character),
+            }
+        }
+
+    if quoted {
+        return Err("A quoted field is not closed".to_string());
+        }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+        }
+
+    Ok(rows)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn marks_round_trip_test() {
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(1500, 2, Some("Hilbert, \"spaces\"\nsecond line".to_string())));
+        marks.add(Mark::new(25, 1, None));
+
+        let csv=render(marks.get_mark_list(), MarksFormat::Csv);
+        assert!(csv.starts_with("id,time,category,label\n1,00:00:01.000,1,\n0,00:01:00.000,2,\"Hilbert, \"\"spaces\"\"\n"));
+
+        for format in [MarksFormat::Csv, MarksFormat::Json] {
+            let parsed=parse(&render(marks.get_mark_list(), format), format).unwrap();
+            let parsed: Vec<(usize, usize, Option<String>)>=parsed.get_mark_list().iter()
+            .map(|mark| (*mark.frame_offset(), *mark.category(), mark.label().clone()))
+            .collect();
+
+            assert_eq!(parsed, vec![
+                (25, 1, None),
+                (1500, 2, Some("Hilbert, \"spaces\"\nsecond line".to_string())),
+                ]);
+            }
+
+        assert!(parse("id,time,category,label\n,1:00,x,\n", MarksFormat::Csv).is_err());
+        assert!(parse("time,label\n", MarksFormat::Csv).is_err());
+        assert_eq!(MarksFormat::of_path("marks.JSON"), MarksFormat::Json);
+        assert_eq!(format_timestamp(Duration::from_millis(3723040)), "01:02:03.040");
+        }
+    }
@@ -1,12 +1,15 @@
+use std::path::Path;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
 use pyo3::prelude::*;
 
-use sdam::{Mark, Sdam};
+use sdam::{merge_documents as sdam_merge_documents, CatchUpPolicy, ClipFormat, ClipSource, CompressorSettings, ControlServer, LoopRegion, Mark, NoteLine, PlaybackLoop, Sdam, SearchOptions, SearchSource, SplitPoint, StudySheetFormat, StudySheetOptions, SuggestionReason, TimeTravelOptions, WhisperCli};
 
 lazy_static! {
     static ref SDAM: Mutex<Sdam>=Mutex::new(Sdam::new());
+    static ref CONTROL_SERVER: Mutex<Option<ControlServer>>=Mutex::new(None);
     }
 
 #[pyclass]
@@ -67,6 +70,23 @@ impl PyMark {
         }
     }
 
+#[pyclass]
+struct PyNoteLine {
+    #[pyo3(get)]
+    text: String,
+    #[pyo3(get)]
+    frame_offset: Option<usize>,
+    }
+impl PyNoteLine {
+
+    fn from_note_line(line: &NoteLine) -> PyNoteLine {
+        PyNoteLine {
+            text: line.text().to_string(),
+            frame_offset: *line.frame_offset(),
+            }
+        }
+    }
+
 #[pyfunction]
 fn load(path: &str) -> PyResult<String> {
     let mut sdam=SDAM.lock().unwrap();
@@ -151,6 +171,21 @@ fn jump_to_frame(frame: usize) {
     let mut sdam=SDAM.lock().unwrap();
     sdam.jump_to_frame(frame);
     }
+#[pyfunction]
+fn seek_millis(position: u64) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.seek(Duration::from_millis(position));
+    }
+#[pyfunction]
+fn forward_millis(delta: u64) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.forward_by(Duration::from_millis(delta));
+    }
+#[pyfunction]
+fn backward_millis(delta: u64) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.backward_by(Duration::from_millis(delta));
+    }
 
 // Getters
 
@@ -183,11 +218,137 @@ fn audio_duration() -> PyResult<usize> {
     Ok(sdam.audio_duration())
     }
 #[pyfunction]
+fn duration_millis() -> PyResult<u64> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.duration().as_millis() as u64)
+    }
+#[pyfunction]
 fn current_position() -> PyResult<Option<usize>> {
     let mut sdam=SDAM.lock().unwrap();
     Ok(sdam.current_position())
     }
 #[pyfunction]
+fn current_position_millis() -> PyResult<Option<u64>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.current_position_millis())
+    }
+#[pyfunction]
+fn integrated_loudness() -> PyResult<Option<f32>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.integrated_loudness())
+    }
+#[pyfunction]
+fn loudness_normalization() -> PyResult<Option<f32>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.loudness_normalization())
+    }
+/// A search hit as (frame offset, mark id or None for the transcript, text, score).
+type SearchHitTuple=(usize, Option<u64>, String, f64);
+
+#[pyfunction]
+fn search(query: String, fuzzy: bool) -> PyResult<Vec<SearchHitTuple>> {
+    let mut options=SearchOptions::new();
+    if fuzzy {
+        options=options.with_fuzzy_matching();
+        }
+
+    let mut sdam=SDAM.lock().unwrap();
+    let hits: Vec<SearchHitTuple>=sdam.search(&query, options)
+    .into_iter()
+    .map(|hit| {
+        let mark_id=match hit.source() {
+            SearchSource::Mark(id) => Some(*id),
+            SearchSource::Transcript => None,
+            };
+
+        (*hit.frame_offset(), mark_id, hit.text().clone(), *hit.score())
+        })
+    .collect();
+
+    Ok(hits)
+    }
+/// A mark suggestion as (id, frame offset, reason, label), the reason being "pause", "emphasis" or "phrase".
+type MarkSuggestionTuple=(u64, usize, String, Option<String>);
+
+#[pyfunction]
+fn suggest_marks() -> PyResult<Vec<MarkSuggestionTuple>> {
+    let mut sdam=SDAM.lock().unwrap();
+    let suggestions: Vec<MarkSuggestionTuple>=sdam.suggest_marks()
+    .into_iter()
+    .map(|suggestion| {
+        let reason=match suggestion.reason() {
+            SuggestionReason::Pause => "pause",
+            SuggestionReason::Emphasis => "emphasis",
+            SuggestionReason::Phrase(_) => "phrase",
+            };
+
+        (*suggestion.id(), *suggestion.frame_offset(), reason.to_string(), suggestion.label().clone())
+        })
+    .collect();
+
+    Ok(suggestions)
+    }
+#[pyfunction]
+fn transcript(start_frame: usize, end_frame: usize) -> PyResult<Vec<(usize, usize, String)>> {
+    let mut sdam=SDAM.lock().unwrap();
+    let segments: Vec<(usize, usize, String)>=sdam.transcript_between(start_frame, end_frame)
+    .into_iter()
+    .map(|segment| (*segment.start_frame(), *segment.end_frame(), segment.text().clone()))
+    .collect();
+
+    Ok(segments)
+    }
+#[pyfunction]
+fn is_transcribing() -> PyResult<bool> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.is_transcribing())
+    }
+#[pyfunction]
+fn speaker_segments() -> PyResult<Vec<(usize, usize, usize)>> {
+    let mut sdam=SDAM.lock().unwrap();
+    let segments: Vec<(usize, usize, usize)>=sdam.speaker_segments()
+    .into_iter()
+    .map(|segment| (*segment.start_frame(), *segment.end_frame(), *segment.speaker()))
+    .collect();
+
+    Ok(segments)
+    }
+#[pyfunction]
+fn next_question(frame: usize) -> PyResult<Option<(usize, usize, usize)>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.next_question(frame).map(|segment| (*segment.start_frame(), *segment.end_frame(), *segment.speaker())))
+    }
+#[pyfunction]
+fn is_detecting_speakers() -> PyResult<bool> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.is_detecting_speakers())
+    }
+#[pyfunction]
+fn noise_suppression() -> PyResult<bool> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.noise_suppression())
+    }
+#[pyfunction]
+fn is_monitoring_live() -> PyResult<bool> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.is_monitoring_live())
+    }
+#[pyfunction]
+fn is_time_travelling() -> PyResult<bool> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.is_time_travelling())
+    }
+#[pyfunction]
+fn live_lag_millis() -> PyResult<Option<u64>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.live_lag().map(|lag| lag.as_millis() as u64))
+    }
+#[pyfunction]
+fn loop_region() -> PyResult<Option<(usize, usize)>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.loop_region())
+    }
+#[pyfunction]
 fn is_playing() -> PyResult<bool> {
     let mut sdam=SDAM.lock().unwrap();
     Ok(sdam.is_playing())
@@ -203,6 +364,16 @@ fn is_recording() -> PyResult<bool> {
     Ok(sdam.is_recording())
     }
 #[pyfunction]
+fn is_modified() -> PyResult<bool> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.is_modified())
+    }
+#[pyfunction]
+fn change_count() -> PyResult<u64> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.change_count())
+    }
+#[pyfunction]
 fn get_mark(id: u64) -> PyResult<Option<PyMark>> {
     let mut sdam=SDAM.lock().unwrap();
     if let Some(mark)=sdam.get_mark(id) {
@@ -244,6 +415,30 @@ fn user_text() -> PyResult<String> {
     let mut sdam=SDAM.lock().unwrap();
     Ok(sdam.user_text())
     }
+#[pyfunction]
+fn note_lines() -> PyResult<Vec<PyNoteLine>> {
+    let mut sdam=SDAM.lock().unwrap();
+    let lines=sdam.note_lines();
+    let pylines: Vec<PyNoteLine>=lines.iter()
+    .map(PyNoteLine::from_note_line)
+    .collect();
+
+    Ok(pylines)
+    }
+#[pyfunction]
+fn note_line_index_at(frame: usize) -> PyResult<Option<usize>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.note_line_index_at(frame))
+    }
+#[pyfunction]
+fn envelope(start_frame: usize, end_frame: usize, points: usize) -> PyResult<Vec<(f32, f32)>> {
+    let mut sdam=SDAM.lock().unwrap();
+    let envelope: Vec<(f32, f32)>=sdam.envelope(start_frame, end_frame, points).iter()
+    .map(|point| (*point.peak(), *point.rms()))
+    .collect();
+
+    Ok(envelope)
+    }
 
 // Setters
 
@@ -264,20 +459,302 @@ fn delete_mark(id: u64) {
     sdam.delete_mark(id);
     }
 #[pyfunction]
+fn accept_suggestion(id: u64, category: usize) -> PyResult<String> {
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.accept_suggestion(id, category) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+#[pyfunction]
+fn dismiss_suggestion(id: u64) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.dismiss_suggestion(id);
+    }
+#[pyfunction]
 fn set_rate(rate: f64) {
     let mut sdam=SDAM.lock().unwrap();
     sdam.set_rate(rate);
     }
 #[pyfunction]
+fn set_loop(start_frame: usize, end_frame: usize, repeat_count: Option<usize>, slowdown: Option<f64>) -> PyResult<String> {
+    Ok(start_loop(LoopRegion::Frames(start_frame, end_frame), repeat_count, slowdown))
+    }
+#[pyfunction]
+fn set_loop_between_marks(first_mark_id: u64, second_mark_id: u64, repeat_count: Option<usize>, slowdown: Option<f64>) -> PyResult<String> {
+    Ok(start_loop(LoopRegion::Marks(first_mark_id, second_mark_id), repeat_count, slowdown))
+    }
+#[pyfunction]
+fn clear_loop() {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.clear_loop();
+    }
+#[pyfunction]
+fn set_playback_gain(gain: f32) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_playback_gain(gain);
+    }
+#[pyfunction]
+fn set_compressor(enabled: bool) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_compressor(enabled.then(CompressorSettings::speech));
+    }
+#[pyfunction]
+fn set_loudness_normalization(target: Option<f32>) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_loudness_normalization(target);
+    }
+#[pyfunction]
+fn set_whisper_transcription(executable: String, model: String, language: Option<String>) {
+    let mut engine=WhisperCli::new(Path::new(&executable), Path::new(&model));
+    if let Some(language)=language {
+        engine=engine.with_language(&language);
+        }
+
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_transcription_engine(Some(Box::new(engine)));
+    }
+#[pyfunction]
+fn disable_transcription() {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_transcription_engine(None);
+    }
+#[pyfunction]
+fn clear_transcript() {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.clear_transcript();
+    }
+#[pyfunction]
+fn detect_speakers() {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.detect_speakers();
+    }
+#[pyfunction]
+fn set_noise_suppression(enabled: bool) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_noise_suppression(enabled);
+    }
+#[pyfunction]
+fn set_monitoring(enabled: bool) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_monitoring(enabled);
+    }
+#[pyfunction]
+fn set_monitoring_gain(gain: f32) {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.set_monitoring_gain(gain);
+    }
+#[pyfunction]
+fn start_time_travel(skip_silence: bool, live_latency_millis: Option<u64>, max_catch_up_rate: Option<f64>) {
+    let mut options=TimeTravelOptions::new();
+    if let Some(live_latency_millis)=live_latency_millis {
+        options=options.with_live_latency(Duration::from_millis(live_latency_millis));
+        }
+    if let Some(max_catch_up_rate)=max_catch_up_rate {
+        let mut catch_up=CatchUpPolicy::new(max_catch_up_rate);
+        if skip_silence {
+            catch_up=catch_up.with_silence_skipping();
+            }
+
+        options=options.with_catch_up(catch_up);
+        }
+
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.start_time_travel(options);
+    }
+#[pyfunction]
+fn stop_time_travel() {
+    let mut sdam=SDAM.lock().unwrap();
+    sdam.stop_time_travel();
+    }
+#[pyfunction]
 fn set_user_text(text: &str) {
     let mut sdam=SDAM.lock().unwrap();
     sdam.set_user_text(text);
     }
 
+#[pyfunction]
+fn export_study_sheet(path: &str, format: &str, snippet_padding: Option<(usize, usize)>) -> PyResult<String> {
+    let format=match format {
+        "markdown" | "md" => StudySheetFormat::Markdown,
+        "html" => StudySheetFormat::Html,
+        _ => return Ok(format!("Unknown study sheet format {format}")),
+        };
+
+    let mut options=StudySheetOptions::new(format);
+    if let Some((seconds_before, seconds_after))=snippet_padding {
+        options=options.with_snippets(seconds_before, seconds_after);
+        }
+
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.export_study_sheet(path, options) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+
+fn start_loop(region: LoopRegion, repeat_count: Option<usize>, slowdown: Option<f64>) -> String {
+    let mut playback_loop=PlaybackLoop::new(region);
+    if let Some(repeat_count)=repeat_count {
+        playback_loop=playback_loop.with_repeat_count(repeat_count);
+        }
+    if let Some(slowdown)=slowdown {
+        playback_loop=playback_loop.with_slowdown(slowdown);
+        }
+
+    let mut sdam=SDAM.lock().unwrap();
+    match sdam.set_loop(playback_loop) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        }
+    }
+
+#[pyfunction]
+fn export_clip(path: &str, mark_id: u64, seconds_before: usize, seconds_after: usize) -> PyResult<String> {
+    let format=if path.to_lowercase().ends_with(".wav") {
+        ClipFormat::Wav
+        }
+    else {
+        ClipFormat::Sdam
+        };
+
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.export_clip(path, ClipSource::Mark(mark_id), seconds_before, seconds_after, format) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+
+#[pyfunction]
+fn delete_audio(start_frame: usize, end_frame: usize) -> PyResult<String> {
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.delete_audio(start_frame, end_frame) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+#[pyfunction]
+fn trim_start(frame: usize) -> PyResult<String> {
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.trim_start(frame) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+#[pyfunction]
+fn trim_end(frame: usize) -> PyResult<String> {
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.trim_end(frame) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+#[pyfunction]
+fn insert_document(path: &str, frame: usize) -> PyResult<String> {
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.insert_document(path, frame) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+
+#[pyfunction]
+fn merge_documents(paths: Vec<String>, output_path: &str) -> PyResult<String> {
+    let paths: Vec<&str>=paths.iter().map(|path| &path[..]).collect();
+
+    let result=match sdam_merge_documents(&paths, output_path) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+
+#[pyfunction]
+fn split_at_marks(mark_ids: Vec<u64>, output_directory: &str) -> PyResult<String> {
+    let points: Vec<SplitPoint>=mark_ids.into_iter().map(SplitPoint::Mark).collect();
+
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.split(points, output_directory) {
+        Ok(_) => String::new(),
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+
+// History
+
+#[pyfunction]
+fn undo() -> PyResult<Option<String>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.undo())
+    }
+#[pyfunction]
+fn redo() -> PyResult<Option<String>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.redo())
+    }
+#[pyfunction]
+fn undo_history() -> PyResult<Vec<String>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.undo_history())
+    }
+#[pyfunction]
+fn redo_history() -> PyResult<Vec<String>> {
+    let mut sdam=SDAM.lock().unwrap();
+    Ok(sdam.redo_history())
+    }
+
+// Control server
+
+#[pyfunction]
+fn start_control_server(address: &str, allow_remote: bool) -> PyResult<String> {
+    let mut control_server=CONTROL_SERVER.lock().unwrap();
+
+    //Only one server runs at a time, the previous one is stopped first
+    *control_server=None;
+
+    let mut sdam=SDAM.lock().unwrap();
+    let result=match sdam.start_control_server(address, allow_remote) {
+        Ok(server) => {
+            *control_server=Some(server);
+            String::new()
+            },
+        Err(msg) => msg.to_string(),
+        };
+
+    Ok(result)
+    }
+#[pyfunction]
+fn stop_control_server() {
+    *CONTROL_SERVER.lock().unwrap()=None;
+    }
+#[pyfunction]
+fn control_server_address() -> PyResult<Option<String>> {
+    let control_server=CONTROL_SERVER.lock().unwrap();
+    Ok(control_server.as_ref().map(|server| server.address().to_string()))
+    }
+
 #[pymodule]
 fn backend(_py: Python, m: &PyModule) -> PyResult<()> {
 
     m.add_class::<PyMark>()?;
+    m.add_class::<PyNoteLine>()?;
 
     m.add_function(wrap_pyfunction!(load, m)?)?;
     m.add_function(wrap_pyfunction!(save, m)?)?;
@@ -297,6 +774,9 @@ fn backend(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(jump_to_percentage, m)?)?;
     m.add_function(wrap_pyfunction!(jump_to_time, m)?)?;
     m.add_function(wrap_pyfunction!(jump_to_frame, m)?)?;
+    m.add_function(wrap_pyfunction!(seek_millis, m)?)?;
+    m.add_function(wrap_pyfunction!(forward_millis, m)?)?;
+    m.add_function(wrap_pyfunction!(backward_millis, m)?)?;
 
     // Getters
 
@@ -304,24 +784,84 @@ fn backend(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(file_path, m)?)?;
     m.add_function(wrap_pyfunction!(audio_len, m)?)?;
     m.add_function(wrap_pyfunction!(audio_duration, m)?)?;
+    m.add_function(wrap_pyfunction!(duration_millis, m)?)?;
     m.add_function(wrap_pyfunction!(current_position, m)?)?;
+    m.add_function(wrap_pyfunction!(current_position_millis, m)?)?;
+    m.add_function(wrap_pyfunction!(integrated_loudness, m)?)?;
+    m.add_function(wrap_pyfunction!(loudness_normalization, m)?)?;
+    m.add_function(wrap_pyfunction!(search, m)?)?;
+    m.add_function(wrap_pyfunction!(suggest_marks, m)?)?;
+    m.add_function(wrap_pyfunction!(transcript, m)?)?;
+    m.add_function(wrap_pyfunction!(is_transcribing, m)?)?;
+    m.add_function(wrap_pyfunction!(speaker_segments, m)?)?;
+    m.add_function(wrap_pyfunction!(next_question, m)?)?;
+    m.add_function(wrap_pyfunction!(is_detecting_speakers, m)?)?;
+    m.add_function(wrap_pyfunction!(noise_suppression, m)?)?;
+    m.add_function(wrap_pyfunction!(is_monitoring_live, m)?)?;
+    m.add_function(wrap_pyfunction!(is_time_travelling, m)?)?;
+    m.add_function(wrap_pyfunction!(live_lag_millis, m)?)?;
+    m.add_function(wrap_pyfunction!(loop_region, m)?)?;
     m.add_function(wrap_pyfunction!(is_playing, m)?)?;
     m.add_function(wrap_pyfunction!(is_paused, m)?)?;
     m.add_function(wrap_pyfunction!(is_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(is_modified, m)?)?;
+    m.add_function(wrap_pyfunction!(change_count, m)?)?;
     m.add_function(wrap_pyfunction!(get_mark, m)?)?;
     m.add_function(wrap_pyfunction!(marks, m)?)?;
     m.add_function(wrap_pyfunction!(next_closest_mark, m)?)?;
     m.add_function(wrap_pyfunction!(previous_closest_mark, m)?)?;
     m.add_function(wrap_pyfunction!(user_text, m)?)?;
+    m.add_function(wrap_pyfunction!(note_lines, m)?)?;
+    m.add_function(wrap_pyfunction!(note_line_index_at, m)?)?;
+    m.add_function(wrap_pyfunction!(envelope, m)?)?;
 
     // Setters
 
     m.add_function(wrap_pyfunction!(add_mark, m)?)?;
     m.add_function(wrap_pyfunction!(edit_mark, m)?)?;
     m.add_function(wrap_pyfunction!(delete_mark, m)?)?;
+    m.add_function(wrap_pyfunction!(accept_suggestion, m)?)?;
+    m.add_function(wrap_pyfunction!(dismiss_suggestion, m)?)?;
     m.add_function(wrap_pyfunction!(set_rate, m)?)?;
+    m.add_function(wrap_pyfunction!(set_loop, m)?)?;
+    m.add_function(wrap_pyfunction!(set_loop_between_marks, m)?)?;
+    m.add_function(wrap_pyfunction!(clear_loop, m)?)?;
+    m.add_function(wrap_pyfunction!(set_playback_gain, m)?)?;
+    m.add_function(wrap_pyfunction!(set_compressor, m)?)?;
+    m.add_function(wrap_pyfunction!(set_loudness_normalization, m)?)?;
+    m.add_function(wrap_pyfunction!(set_whisper_transcription, m)?)?;
+    m.add_function(wrap_pyfunction!(disable_transcription, m)?)?;
+    m.add_function(wrap_pyfunction!(clear_transcript, m)?)?;
+    m.add_function(wrap_pyfunction!(detect_speakers, m)?)?;
+    m.add_function(wrap_pyfunction!(set_noise_suppression, m)?)?;
+    m.add_function(wrap_pyfunction!(set_monitoring, m)?)?;
+    m.add_function(wrap_pyfunction!(set_monitoring_gain, m)?)?;
+    m.add_function(wrap_pyfunction!(start_time_travel, m)?)?;
+    m.add_function(wrap_pyfunction!(stop_time_travel, m)?)?;
     m.add_function(wrap_pyfunction!(set_user_text, m)?)?;
 
+    m.add_function(wrap_pyfunction!(export_study_sheet, m)?)?;
+    m.add_function(wrap_pyfunction!(export_clip, m)?)?;
+    m.add_function(wrap_pyfunction!(delete_audio, m)?)?;
+    m.add_function(wrap_pyfunction!(trim_start, m)?)?;
+    m.add_function(wrap_pyfunction!(trim_end, m)?)?;
+    m.add_function(wrap_pyfunction!(insert_document, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_documents, m)?)?;
+    m.add_function(wrap_pyfunction!(split_at_marks, m)?)?;
+
+    // History
+
+    m.add_function(wrap_pyfunction!(undo, m)?)?;
+    m.add_function(wrap_pyfunction!(redo, m)?)?;
+    m.add_function(wrap_pyfunction!(undo_history, m)?)?;
+    m.add_function(wrap_pyfunction!(redo_history, m)?)?;
+
+    // Control server
+
+    m.add_function(wrap_pyfunction!(start_control_server, m)?)?;
+    m.add_function(wrap_pyfunction!(stop_control_server, m)?)?;
+    m.add_function(wrap_pyfunction!(control_server_address, m)?)?;
+
     //m.add_function(wrap_pyfunction!(, m)?)?;
 
     Ok(())
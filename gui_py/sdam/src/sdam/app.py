@@ -20,6 +20,8 @@ import sdam.util
 from sdam.util import frame_offset_to_time, input_dialog, Toaster
 
 TIME_REGEX=re.compile(r"^(\d*:){,2}\d+$")
+# Seconds of no typing after which the text is passed to the core
+TEXT_SYNC_DELAY=0.5
 
 class ViewMarksWindow(Window):
 
@@ -154,8 +156,10 @@ class SdamWindow(MainWindow):
 
         self._rate=1.0
         self._time_travel=False
-        self._recording_before_timetravel=False
         self._focused_mark=None
+        self._search_hits=[]
+        self._search_hit_index=0
+        self._text_sync_handle=None
 
         recording_group=Group("Recording")
         playback_group=Group("Playback")
@@ -373,6 +377,24 @@ class SdamWindow(MainWindow):
             group=jump_to_group,
             order=4,
             )
+        playback_jump_to_search_hit=Command(self.playback_jump_to_search_hit,
+            text="Search",
+            shortcut=Key.MOD_1+Key.F,
+            group=jump_to_group,
+            order=5,
+            )
+        playback_jump_to_next_search_hit=Command(self.playback_jump_to_next_search_hit,
+            text="Next search result",
+            shortcut=Key.MOD_1+Key.G,
+            group=jump_to_group,
+            order=6,
+            )
+        playback_jump_to_next_question=Command(self.playback_jump_to_next_question,
+            text="Next question",
+            shortcut=Key.MOD_1+Key.E,
+            group=jump_to_group,
+            order=7,
+            )
 
         time_travel_activate=Command(self.time_travel_activate,
             text="Activate",
@@ -525,6 +547,9 @@ class SdamWindow(MainWindow):
             playback_jump_to_percentage_90,
             playback_jump_to_percentage_100,
             playback_jump_to_time,
+            playback_jump_to_search_hit,
+            playback_jump_to_next_search_hit,
+            playback_jump_to_next_question,
             time_travel_activate,
             time_travel_deactivate,
             marks_add_category_1_mark,
@@ -550,7 +575,7 @@ class SdamWindow(MainWindow):
 
         content=Box()
 
-        text_input=MultilineTextInput()
+        text_input=MultilineTextInput(on_change=self.text_input_change_handler)
         self._text_input=text_input
 
         status_bar=Box()
@@ -571,6 +596,20 @@ class SdamWindow(MainWindow):
 
         self._text_input.focus()
 
+    def text_input_change_handler(self, sender):
+        # Keeping the core updated while typing lets it anchor the notes to the current position
+        # It compares the whole text on every update, so it's only done once the typing pauses
+        if self._text_sync_handle is not None:
+            self._text_sync_handle.cancel()
+
+        self._text_sync_handle=asyncio.get_event_loop().call_later(TEXT_SYNC_DELAY, self.sync_user_text)
+    def sync_user_text(self):
+        if self._text_sync_handle is not None:
+            self._text_sync_handle.cancel()
+            self._text_sync_handle=None
+
+        backend.set_user_text(self._text_input.value)
+
     async def load(self, sender):
         result=await self.open_file_dialog("LOad a file", file_types=["sdam"], multiple_select=False)
 
@@ -601,8 +640,8 @@ class SdamWindow(MainWindow):
             if not backend.is_recording():
                 self._toaster.toast("Recording stopped")
         else:
-            self._recording_before_time_travel=False
             self.time_travel_deactivate(None)
+            backend.stop_recording()
 
             if not backend.is_recording():
                 self._toaster.toast("Recording stopped, Timetravel stopped")
@@ -701,25 +740,69 @@ class SdamWindow(MainWindow):
 
         backend.jump_to_time(seconds)
 
+    async def playback_jump_to_search_hit(self, sender):
+        query=await input_dialog("Search", "Search the transcript and mark labels for:")
+
+        if query is None or query=="":
+            return
+
+        self._search_hits=backend.search(query, True)
+        self._search_hit_index=0
+
+        if len(self._search_hits)==0:
+            self._toaster.toast(f"'{query}' not found")
+            return
+
+        self.jump_to_search_hit()
+    def playback_jump_to_next_search_hit(self, sender):
+        if len(self._search_hits)==0:
+            return
+
+        self._search_hit_index=(self._search_hit_index+1)%len(self._search_hits)
+        self.jump_to_search_hit()
+    def playback_jump_to_next_question(self, sender):
+        if backend.is_detecting_speakers():
+            self._toaster.toast("Detecting speakers, please wait")
+            return
+
+        if len(backend.speaker_segments())==0:
+            backend.detect_speakers()
+            self._toaster.toast("Detecting speakers, try again when done")
+            return
+
+        current_position=backend.current_position()
+
+        if current_position is None:
+            current_position=0
+
+        question=backend.next_question(current_position)
+
+        if question is None:
+            self._toaster.toast("No next question")
+            return
+
+        start_frame, end_frame, speaker=question
+        backend.jump_to_frame(start_frame)
+        self._toaster.toast(f"Speaker {speaker} at {frame_offset_to_time(start_frame)}")
+    def jump_to_search_hit(self):
+        frame_offset, mark_id, text, score=self._search_hits[self._search_hit_index]
+        source="mark" if mark_id is not None else "transcript"
+
+        backend.jump_to_frame(frame_offset)
+        self._toaster.toast(f"{self._search_hit_index+1} of {len(self._search_hits)}, {source} at {frame_offset_to_time(frame_offset)}: {text}")
+
     def time_travel_activate(self, sender):
         if not self._time_travel:
-            recording=backend.is_recording()
+            backend.set_monitoring(True)
+            backend.start_time_travel(True, None, 1.5)
 
-            backend.pause_playback()
-            if not recording:
-                backend.start_recording()
-            backend.jump_to_end()
-            backend.start_playback()
-
-            self._recording_before_time_travel=recording
             self._time_travel=True
 
             self._toaster.toast("Timetravel activated")
     def time_travel_deactivate(self, sender):
         if self._time_travel:
-            backend.pause_playback()
-            if not self._recording_before_time_travel:
-                backend.stop_recording()
+            backend.stop_time_travel()
+            backend.set_monitoring(False)
             self._time_travel=False
 
             self._toaster.toast("Timetravel deactivated")
@@ -865,6 +948,10 @@ class SdamWindow(MainWindow):
         self._toaster.release()
 
     def load_from_file(self, path):
+        if self._text_sync_handle is not None:
+            self._text_sync_handle.cancel()
+            self._text_sync_handle=None
+
         result=backend.load(path)
 
         if result!="":
@@ -875,7 +962,7 @@ class SdamWindow(MainWindow):
 
         self.title=f"{backend.file_name()} - SDAM"
     def save_to_file(self, path):
-        backend.set_user_text(self._text_input.value)
+        self.sync_user_text()
         backend.save(path)
         self.title=f"{backend.file_name()} - SDAM"
 
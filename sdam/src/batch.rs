@@ -0,0 +1,246 @@
+use std::path::Path;
+use std::time::Duration;
+
+use derive_getters::Getters;
+
+use opus::{Encoder, Decoder};
+
+use crate::{frame_offset_to_duration, frame_offset_to_time, open_document, storage, AudioContainer, Mark, MarkManager, Notes, FRAME_SIZE, SAMPLING_RATE};
+
+/// Summary of a document file, read without decoding its audio.
+#[derive(Clone, Debug, Getters)]
+pub struct DocumentInfo {
+    frame_count: usize,
+    marks: Vec<Mark>,
+    notes: Notes,
+    transcript_segments: usize,
+    speaker_segments: usize,
+    /// Whether the document is stored in the format of older versions, which is loaded in full.
+    legacy: bool,
+    }
+impl DocumentInfo {
+
+    pub fn duration(&self) -> Duration {
+        frame_offset_to_duration(self.frame_count)
+        }
+    }
+
+pub fn document_info(path: &str) -> Result<DocumentInfo, anyhow::Error> {
+    let (frame_count, metadata, legacy)=match storage::open(Path::new(path))? {
+        storage::StoredDocument::Legacy(model) => {
+            let (audio, metadata)=model.into_stored();
+
+            (audio.len(), metadata, true)
+            },
+        storage::StoredDocument::Indexed {metadata, frames} => (frames.len(), metadata, false),
+        };
+
+    Ok(DocumentInfo {
+        frame_count,
+        marks: metadata.marks.get_mark_list().clone(),
+        notes: metadata.notes,
+        transcript_segments: metadata.transcript.segments().len(),
+        speaker_segments: metadata.speakers.segments().len(),
+        legacy,
+        })
+    }
+
+/// Checks the document for frames which can't be decoded and for marks, notes, transcript and speakers pointing past the end of the audio.
+/// Documents which can't be opened at all are an error, other problems are returned as their descriptions.
+pub fn validate_document(path: &str) -> Result<Vec<String>, anyhow::Error> {
+    let (audio, metadata)=open_document(Path::new(path))?;
+    let audio_len=audio.len();
+    let mut problems: Vec<String>=Vec::new();
+
+    //Stored frames are never empty, empty data means the frame didn't fit into the file
+    let mut decoder=Decoder::new(SAMPLING_RATE, opus::Channels::Mono)?;
+    let mut decoding_buffer=vec![0_i16; 2*FRAME_SIZE];
+    let broken_frames: Vec<usize>=(0..audio_len)
+    .filter(|id| {
+        let data=audio.frame_data(*id).unwrap();
+
+        data.is_empty() || decoder.decode(data, &mut decoding_buffer, false).is_err()
+        })
+    .collect();
+
+    if let Some(first)=broken_frames.first() {
+        problems.push(format!("{} undecodable frames, the first one at {}", broken_frames.len(), frame_offset_to_time(*first)));
+        }
+
+    for mark in metadata.marks.get_mark_list() {
+        if mark.id().is_none() {
+            problems.push(format!("The mark at {} has no id", frame_offset_to_time(*mark.frame_offset())));
+            }
+        if *mark.frame_offset()>audio_len {
+            problems.push(format!("The mark {} at {} is past the end of the audio", mark.id().unwrap_or_default(), frame_offset_to_time(*mark.frame_offset())));
+            }
+        }
+
+    for (index, line) in metadata.notes.lines().iter().enumerate() {
+        if line.frame_offset().is_some_and(|frame_offset| frame_offset>audio_len) {
+            problems.push(format!("The note line {} is anchored past the end of the audio", index+1));
+            }
+        }
+
+    if metadata.transcript.segments().iter().any(|segment| *segment.end_frame()>audio_len) {
+        problems.push("The transcript reaches past the end of the audio".to_string());
+        }
+    if metadata.speakers.segments().iter().any(|segment| *segment.end_frame()>audio_len) {
+        problems.push("The speaker segments reach past the end of the audio".to_string());
+        }
+
+    Ok(problems)
+    }
+
+/// Writes the audio of the document between the given frames to a WAV file. The end is clamped to the length of the audio.
+pub fn export_document_audio(path: &str, output_path: &str, start_frame: usize, end_frame: usize) -> Result<(), anyhow::Error> {
+    let (audio, _)=open_document(Path::new(path))?;
+
+    audio.write_wav(start_frame, end_frame, Path::new(output_path))
+    }
+
+/// Creates a document of the audio of a WAV file, with the given marks and notes. The audio is mixed down to mono and resampled to the sampling rate of documents.
+pub fn import_wav(wav_path: &str, output_path: &str, marks: MarkManager, notes: Notes) -> Result<(), anyhow::Error> {
+    let samples=read_wav(Path::new(wav_path))?;
+    let audio=AudioContainer::from_vec(encode(&samples)?);
+
+    let mut metadata=storage::Metadata::new(marks, notes);
+    metadata.envelope.update(&audio);
+
+    storage::write(Path::new(output_path), &metadata, audio.len(), |id| audio.frame_data(id).unwrap())
+    }
+
+/// Reads the samples of a WAV file as mono audio at the sampling rate of documents, scaled to -1.0 to 1.0.
+fn read_wav(path: &Path) -> Result<Vec<f32>, anyhow::Error> {
+    let mut reader=hound::WavReader::open(path)?;
+    let spec=reader.spec();
+
+    let samples: Vec<f32>=match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<f32>, hound::Error>>()?,
+        hound::SampleFormat::Int => {
+            let scale=(1_i64 << (spec.bits_per_sample-1)) as f32;
+
+            reader.samples::<i32>()
+            .map(|sample| sample.map(|sample| sample as f32/scale))
+            .collect::<Result<Vec<f32>, hound::Error>>()?
+            },
+        };
+
+    let channels=spec.channels as usize;
+    let mono: Vec<f32>=samples.chunks(channels)
+    .map(|chunk| chunk.iter().sum::<f32>()/channels as f32)
+    .collect();
+
+    Ok(resample(&mono, spec.sample_rate))
+    }
+/// Resamples the audio to the sampling rate of documents by linear interpolation, which is enough for speech.
+fn resample(samples: &[f32], sample_rate: u32) -> Vec<f32> {
+    if sample_rate==SAMPLING_RATE || samples.is_empty() {
+        return samples.to_vec();
+        }
+
+    let step=sample_rate as f64/SAMPLING_RATE as f64;
+    let len=(samples.len() as f64/step) as usize;
+
+    (0..len)
+    .map(|index| {
+        let position=index as f64*step;
+        let source_index=position as usize;
+        let fraction=(position-source_index as f64) as f32;
+        let next=samples.get(source_index+1).copied().unwrap_or(samples[source_index]);
+
+        samples[source_index]*(1.0-fraction)+next*fraction
+        })
+    .collect()
+    }
+/// Encodes the audio into frames, the last one padded with silence.
+fn encode(samples: &[f32]) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    let mut encoder=Encoder::new(SAMPLING_RATE, opus::Channels::Mono, opus::Application::Audio)?;
+    let mut frames: Vec<Vec<u8>>=Vec::new();
+
+    for chunk in samples.chunks(FRAME_SIZE) {
+        let mut frame: Vec<i16>=chunk.iter()
+        .map(|sample| (sample.clamp(-1.0, 1.0)*i16::MAX as f32) as i16)
+        .collect();
+        frame.resize(FRAME_SIZE, 0);
+
+        frames.push(encoder.encode_vec(&frame, FRAME_SIZE)?);
+        }
+
+    Ok(frames)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::SdamFileModel;
+
+    #[test]
+    fn import_export_test() {
+        let directory=std::env::temp_dir();
+        let wav_path=directory.join("sdam_import_export_test.wav");
+        let document_path=directory.join("sdam_import_export_test.sdam");
+        let exported_path=directory.join("sdam_import_export_test_exported.wav");
+
+        //One second of a stereo tone at 16 kHz
+        let spec=hound::WavSpec {
+            channels: 2,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+            };
+        let mut writer=hound::WavWriter::create(&wav_path, spec).unwrap();
+        for index in 0..16000 {
+            let sample=((index as f32*0.2).sin()*8000.0) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+            }
+        writer.finalize().unwrap();
+
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(10, 2, Some("Tone".to_string())));
+        import_wav(wav_path.to_str().unwrap(), document_path.to_str().unwrap(), marks, Notes::from_text("Imported")).unwrap();
+
+        let info=document_info(document_path.to_str().unwrap()).unwrap();
+        assert_eq!(*info.frame_count(), 25);
+        assert_eq!(info.duration(), Duration::from_secs(1));
+        assert_eq!(info.marks().len(), 1);
+        assert_eq!(info.notes().text(), "Imported");
+        assert!(!info.legacy());
+
+        assert!(validate_document(document_path.to_str().unwrap()).unwrap().is_empty());
+
+        export_document_audio(document_path.to_str().unwrap(), exported_path.to_str().unwrap(), 5, usize::MAX).unwrap();
+        let reader=hound::WavReader::open(&exported_path).unwrap();
+        assert_eq!(reader.len() as usize, 20*FRAME_SIZE);
+
+        for path in [wav_path, document_path, exported_path] {
+            std::fs::remove_file(&path).unwrap();
+            }
+        }
+
+    #[test]
+    fn validate_document_test() {
+        let mut encoder=Encoder::new(SAMPLING_RATE, opus::Channels::Mono, opus::Application::Audio).unwrap();
+        let frame=encoder.encode_vec(&[0_i16; FRAME_SIZE], FRAME_SIZE).unwrap();
+
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(1, 1, None));
+        marks.add(Mark::new(50, 1, None));
+        let model=SdamFileModel::new(vec![frame.clone(), Vec::new(), frame], marks, Notes::new());
+
+        let path=std::env::temp_dir().join("sdam_validate_document_test.sdam");
+        model.write(&path).unwrap();
+
+        let problems=validate_document(path.to_str().unwrap()).unwrap();
+        assert_eq!(problems, vec![
+            "1 undecodable frames, the first one at 00:00".to_string(),
+            "The mark 1 at 00:02 is past the end of the audio".to_string(),
+            ]);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(validate_document(path.to_str().unwrap()).is_err());
+        }
+    }
@@ -0,0 +1,128 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::SdamEvent;
+
+/// How often the growing recording is announced at most. Frames arrive every 40 ms, an event for each of them would flood the subscribers.
+const RECORDING_ANNOUNCEMENT_INTERVAL: Duration=Duration::from_secs(1);
+
+/// Tracks whether the document has unsaved changes and tells the subscribers about them.
+pub(crate) struct ChangeTracker {
+    modified: bool,
+    change_count: u64,
+    event_senders: Vec<mpsc::Sender<SdamEvent>>,
+    /// When recorded audio was last announced.
+    last_recording_announcement: Option<Instant>,
+    /// Whether some recorded audio wasn't announced yet.
+    recording_pending: bool,
+    }
+impl ChangeTracker {
+
+    pub(crate) fn new() -> ChangeTracker {
+        ChangeTracker {
+            modified: false,
+            change_count: 0,
+            event_senders: Vec::new(),
+            last_recording_announcement: None,
+            recording_pending: false,
+            }
+        }
+
+    pub(crate) fn is_modified(&self) -> bool {
+        self.modified
+        }
+    pub(crate) fn change_count(&self) -> u64 {
+        self.change_count
+        }
+
+    pub(crate) fn subscribe(&mut self, event_sender: mpsc::Sender<SdamEvent>) {
+        self.event_senders.push(event_sender);
+        }
+    pub(crate) fn emit(&mut self, event: SdamEvent) {
+        //Subscribers which dropped their receivers are forgotten
+        self.event_senders.retain(|sender| sender.send(event.clone()).is_ok());
+        }
+
+    /// Records a change of the document and announces it.
+    pub(crate) fn mark_modified(&mut self) {
+        self.modified=true;
+        self.change_count+=1;
+        self.recording_pending=false;
+
+        self.emit(SdamEvent::DocumentChanged {change_count: self.change_count});
+        }
+    /// Records a newly recorded frame. It's announced only if the recording wasn't announced for a while, the rest is left for flush_recording.
+    pub(crate) fn mark_recorded(&mut self, time: Instant) {
+        self.modified=true;
+        self.change_count+=1;
+
+        if self.last_recording_announcement.is_some_and(|last| time.saturating_duration_since(last)<RECORDING_ANNOUNCEMENT_INTERVAL) {
+            self.recording_pending=true;
+            return;
+            }
+
+        self.last_recording_announcement=Some(time);
+        self.recording_pending=false;
+        self.emit(SdamEvent::DocumentChanged {change_count: self.change_count});
+        }
+    /// Announces the recorded audio which wasn't announced yet, e.g. when the recording stops.
+    pub(crate) fn flush_recording(&mut self) {
+        if self.recording_pending {
+            self.recording_pending=false;
+            self.emit(SdamEvent::DocumentChanged {change_count: self.change_count});
+            }
+        }
+    /// Records that the document matches its file, after it was loaded or saved.
+    pub(crate) fn mark_saved(&mut self) {
+        self.modified=false;
+        }
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn announced_counts(events: &mpsc::Receiver<SdamEvent>) -> Vec<u64> {
+        events.try_iter()
+        .filter_map(|event| match event {
+            SdamEvent::DocumentChanged {change_count} => Some(change_count),
+            _ => None,
+            })
+        .collect()
+        }
+
+    #[test]
+    fn change_tracker_test() {
+        let mut changes=ChangeTracker::new();
+        let (event_sender, events)=mpsc::channel::<SdamEvent>();
+        changes.subscribe(event_sender);
+        assert!(!changes.is_modified());
+
+        changes.mark_modified();
+        assert!(changes.is_modified());
+        assert_eq!(changes.change_count(), 1);
+        assert_eq!(announced_counts(&events), vec![1]);
+
+        //Two seconds of recording are announced twice, not for each of the 50 frames
+        let start=Instant::now();
+        for index in 0..50 {
+            changes.mark_recorded(start+Duration::from_millis(40*index));
+            }
+        assert_eq!(changes.change_count(), 51);
+        assert_eq!(announced_counts(&events), vec![2, 27]);
+
+        //Stopping announces the rest, but only once
+        changes.flush_recording();
+        changes.flush_recording();
+        assert_eq!(announced_counts(&events), vec![51]);
+
+        changes.mark_saved();
+        assert!(!changes.is_modified());
+        assert_eq!(changes.change_count(), 51);
+
+        drop(events);
+        changes.mark_modified();
+        assert!(changes.event_senders.is_empty());
+        }
+    }
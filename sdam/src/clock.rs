@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Frame samples waiting in the output buffer.
+struct BufferedFrame {
+    start_millis: f64,
+    duration_millis: f64,
+    start_sample: u64,
+    samples: u64,
+    }
+
+/// Tracks which part of the audio is actually being heard.
+/// The playback loop decodes frames ahead into the output buffer, so the frame last decoded is not the frame the user hears. The output callback counts the samples it plays, and the clock maps the count back to the buffered frames.
+pub(crate) struct PlaybackClock {
+    consumed_samples: Arc<AtomicU64>,
+    pushed_samples: u64,
+    buffered_frames: VecDeque<BufferedFrame>,
+    resting_position: Option<f64>,
+    }
+impl PlaybackClock {
+
+    pub(crate) fn new() -> PlaybackClock {
+        PlaybackClock {
+            consumed_samples: Arc::new(AtomicU64::new(0)),
+            pushed_samples: 0,
+            buffered_frames: VecDeque::new(),
+            resting_position: None,
+            }
+        }
+
+    /// The counter to be increased by the output callback with every sample it plays from the buffer.
+    pub(crate) fn consumed_samples(&self) -> Arc<AtomicU64> {
+        self.consumed_samples.clone()
+        }
+
+    /// Records that the given number of samples, playing the given part of the audio, was pushed into the output buffer. With a changed playback rate, the number differs from the number of samples of the part.
+    pub(crate) fn push(&mut self, start_millis: f64, duration_millis: f64, samples: usize) {
+        if samples==0 {
+            return;
+            }
+
+        self.buffered_frames.push_back(BufferedFrame {
+            start_millis,
+            duration_millis,
+            start_sample: self.pushed_samples,
+            samples: samples as u64,
+            });
+        self.pushed_samples+=samples as u64;
+        self.resting_position=None;
+        }
+    /// Sets the position reported once the buffered samples are played, i.e. where the playback will continue from.
+    pub(crate) fn seek(&mut self, position_millis: f64) {
+        self.resting_position=Some(position_millis);
+        }
+
+    /// The position of the sample being played in milliseconds, or the position where the playback rests if the buffer is empty.
+    pub(crate) fn position_millis(&mut self) -> Option<f64> {
+        let consumed_samples=self.consumed_samples.load(Ordering::Relaxed);
+
+        //Frames played completely are forgotten, except for the last one, which marks the position the playback has stopped at
+        while self.buffered_frames.len()>1 && self.buffered_frames[0].start_sample+self.buffered_frames[0].samples<=consumed_samples {
+            self.buffered_frames.pop_front();
+            }
+
+        if consumed_samples>=self.pushed_samples {
+            if let Some(resting_position)=self.resting_position {
+                return Some(resting_position);
+                }
+            }
+
+        let buffered_frame=self.buffered_frames.front()?;
+        let played_samples=consumed_samples.saturating_sub(buffered_frame.start_sample).min(buffered_frame.samples);
+
+        Some(buffered_frame.start_millis+buffered_frame.duration_millis*played_samples as f64/buffered_frame.samples as f64)
+        }
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn playback_clock_test() {
+        let mut clock=PlaybackClock::new();
+        let consumed_samples=clock.consumed_samples();
+
+        assert_eq!(clock.position_millis(), None);
+
+        clock.seek(400.0);
+        assert_eq!(clock.position_millis(), Some(400.0));
+
+        clock.push(400.0, 40.0, 1000);
+        clock.push(440.0, 40.0, 1000);
+        assert_eq!(clock.position_millis(), Some(400.0));
+
+        consumed_samples.fetch_add(500, Ordering::Relaxed);
+        assert_eq!(clock.position_millis(), Some(420.0));
+
+        consumed_samples.fetch_add(1250, Ordering::Relaxed);
+        assert_eq!(clock.position_millis(), Some(470.0));
+
+        //A seek during playback takes effect once the buffered samples are played
+        clock.seek(2000.0);
+        assert_eq!(clock.position_millis(), Some(470.0));
+
+        consumed_samples.fetch_add(250, Ordering::Relaxed);
+        assert_eq!(clock.position_millis(), Some(2000.0));
+
+        //Slowed down frames take more samples to play
+        clock.push(2000.0, 40.0, 4000);
+        consumed_samples.fetch_add(1000, Ordering::Relaxed);
+        assert_eq!(clock.position_millis(), Some(2010.0));
+
+        //Without new samples, the position stays at the end of the last played frame
+        consumed_samples.fetch_add(3000, Ordering::Relaxed);
+        assert_eq!(clock.position_millis(), Some(2040.0));
+
+        //Playback started in the middle of a frame
+        clock.seek(2110.0);
+        clock.push(2110.0, 10.0, 480);
+        consumed_samples.fetch_add(240, Ordering::Relaxed);
+        assert_eq!(clock.position_millis(), Some(2115.0));
+        }
+    }
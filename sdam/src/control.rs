@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{duration_to_frame_offset, frame_offset_to_duration, Mark, Sdam, SdamEvent};
+
+// The protocol is line-based, every line is a JSON object.
+// Requests carry the command and its arguments, e.g. {"id": 1, "command": "add_mark", "category": 2}, the optional id is repeated in the response.
+// Responses are {"id": 1, "ok": true, "result": ...} or {"id": 1, "ok": false, "error": "..."}.
+// Document events are sent to every client as they happen, e.g. {"event": "document_changed", "change_count": 5}.
+// Marks are referred to by mark_id, e.g. {"command": "delete_mark", "mark_id": 3}.
+// Times are in seconds.
+
+/// How often the accepting and event forwarding threads check whether they should stop.
+const POLL_INTERVAL: Duration=Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag="command", rename_all="snake_case")]
+pub(crate) enum Request {
+    Status,
+    Play,
+    Pause,
+    TogglePlayback,
+    StartRecording,
+    StopRecording,
+    Seek {position: f64},
+    Forward {seconds: f64},
+    Backward {seconds: f64},
+    SetRate {rate: f64},
+    Marks,
+    /// Adds a mark at the given time, or where the GUI would, i.e. at the live edge while recording, otherwise at the playback position.
+    AddMark {category: usize, #[serde(default)] label: Option<String>, #[serde(default)] time: Option<f64>},
+    EditMark {mark_id: u64, #[serde(default)] category: Option<usize>, #[serde(default)] label: Option<String>},
+    DeleteMark {mark_id: u64},
+    JumpToMark {mark_id: u64},
+    Notes,
+    AppendNote {text: String},
+    Undo,
+    Redo,
+    /// Saves the document to its own file, clients can't write anywhere else.
+    Save,
+    }
+
+/// What the control server operates, a Sdam document, or a stand-in in tests.
+pub(crate) trait ControlTarget: Send + 'static {
+
+    fn execute(&mut self, request: Request) -> Result<Value, anyhow::Error>;
+    fn subscribe(&mut self) -> mpsc::Receiver<SdamEvent>;
+    }
+impl ControlTarget for Sdam {
+
+    fn execute(&mut self, request: Request) -> Result<Value, anyhow::Error> {
+        match request {
+            Request::Status => {
+                return Ok(json!({
+                    "position": self.position().map(|position| position.as_secs_f64()),
+                    "duration": self.duration().as_secs_f64(),
+                    "rate": self.rate(),
+                    "playing": self.is_playing(),
+                    "recording": self.is_recording(),
+                    "time_travelling": self.is_time_travelling(),
+                    "modified": self.is_modified(),
+                    "file_name": self.file_name(),
+                    "change_count": self.change_count(),
+                    }));
+                },
+            Request::Play => self.play(),
+            Request::Pause => self.pause(),
+            Request::TogglePlayback => self.toggle_playback(),
+            Request::StartRecording => self.start_recording(),
+            Request::StopRecording => self.stop_recording(),
+            Request::Seek {position} => self.seek(seconds_to_duration(position)?),
+            Request::Forward {seconds} => self.forward_by(seconds_to_duration(seconds)?),
+            Request::Backward {seconds} => self.backward_by(seconds_to_duration(seconds)?),
+            Request::SetRate {rate} => {
+                if !(rate>0.0 && rate.is_finite()) {
+                    anyhow::bail!("The rate must be a positive number");
+                    }
+
+                self.set_rate(rate);
+                },
+            Request::Marks => {
+                let mut marks=self.marks();
+                marks.sort_by_key(|mark| *mark.frame_offset());
+
+                return Ok(Value::Array(marks.iter().map(mark_json).collect()));
+                },
+            Request::AddMark {category, label, time} => {
+                let frame=match time {
+                    Some(time) => duration_to_frame_offset(seconds_to_duration(time)?),
+                    None if self.is_recording() && !self.is_time_travelling() => self.audio_len(),
+                    None => self.current_position().ok_or_else(|| anyhow::anyhow!("There's nothing to mark yet"))?,
+                    };
+
+                return Ok(mark_json(&self.add_mark(Mark::new(frame, category, label))));
+                },
+            Request::EditMark {mark_id, category, label} => {
+                let mark=existing_mark(self.get_mark(mark_id), mark_id)?;
+                let category=category.unwrap_or(*mark.category());
+                let label=label.or_else(|| mark.label().clone()).filter(|label| !label.is_empty());
+
+                self.edit_mark(mark_id, Mark::new(*mark.frame_offset(), category, label));
+                },
+            Request::DeleteMark {mark_id} => {
+                existing_mark(self.get_mark(mark_id), mark_id)?;
+                self.delete_mark(mark_id);
+                },
+            Request::JumpToMark {mark_id} => {
+                let mark=existing_mark(self.get_mark(mark_id), mark_id)?;
+                self.jump_to_frame(*mark.frame_offset());
+                },
+            Request::Notes => return Ok(Value::String(self.user_text())),
+            Request::AppendNote {text} => {
+                let mut notes=self.user_text();
+                if !notes.is_empty() && !notes.ends_with('\n') {
+                    notes.push('\n');
+                    }
+                notes+=&text;
+
+                self.set_user_text(&notes);
+                },
+            Request::Undo => return Ok(json!(self.undo())),
+            Request::Redo => return Ok(json!(self.redo())),
+            Request::Save => self.save(None)?,
+            }
+
+        Ok(Value::Null)
+        }
+    fn subscribe(&mut self) -> mpsc::Receiver<SdamEvent> {
+        Sdam::subscribe(self)
+        }
+    }
+
+/// The looked up mark, or an error for marks which don't exist.
+fn existing_mark(mark: Option<Mark>, mark_id: u64) -> Result<Mark, anyhow::Error> {
+    mark.ok_or_else(|| anyhow::anyhow!("There's no mark {mark_id}"))
+    }
+fn seconds_to_duration(seconds: f64) -> Result<Duration, anyhow::Error> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| anyhow::anyhow!("{seconds} is not a valid time"))
+    }
+fn mark_json(mark: &Mark) -> Value {
+    json!({
+        "id": mark.id(),
+        "time": frame_offset_to_duration(*mark.frame_offset()).as_secs_f64(),
+        "frame_offset": mark.frame_offset(),
+        "category": mark.category(),
+        "label": mark.label(),
+        })
+    }
+fn event_json(event: &SdamEvent) -> Value {
+    match event {
+        SdamEvent::DocumentChanged {change_count} => json!({"event": "document_changed", "change_count": change_count}),
+        SdamEvent::Loaded {path} => json!({"event": "loaded", "path": path}),
+        SdamEvent::Saved {path} => json!({"event": "saved", "path": path}),
+        SdamEvent::RejoinedLive => json!({"event": "rejoined_live"}),
+        SdamEvent::TranscriptionFailed {message} => json!({"event": "transcription_failed", "message": message}),
+        SdamEvent::SpeakersDetected {speaker_count} => json!({"event": "speakers_detected", "speaker_count": speaker_count}),
+        SdamEvent::SpeakerDetectionFailed {message} => json!({"event": "speaker_detection_failed", "message": message}),
+        SdamEvent::MarksSuggested {suggestion_count} => json!({"event": "marks_suggested", "suggestion_count": suggestion_count}),
+        }
+    }
+
+/// Answers a line of the protocol.
+fn respond(target: &mut impl ControlTarget, line: &str) -> Value {
+    let request: Value=match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(error) => return json!({"ok": false, "error": format!("Invalid JSON: {error}")}),
+        };
+    let id=request.get("id").cloned().unwrap_or(Value::Null);
+
+    let result=Request::deserialize(&request)
+    .map_err(|error| anyhow::anyhow!("Invalid request: {error}"))
+    .and_then(|request| target.execute(request));
+
+    match result {
+        Ok(Value::Null) => json!({"id": id, "ok": true}),
+        Ok(result) => json!({"id": id, "ok": true, "result": result}),
+        Err(error) => json!({"id": id, "ok": false, "error": error.to_string()}),
+        }
+    }
+
+/// Lets other programs, e.g. a phone on the local network, control the document over TCP, with JSON messages.
+/// Clients aren't authenticated, so the server listens only on loopback addresses unless remote clients are explicitly allowed.
+/// The server runs in background threads until it's stopped or dropped. It should be stopped before the Sdam it controls is dropped.
+pub struct ControlServer {
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    /// Handles of the connected clients by their number, so stopping can disconnect them.
+    connections: Arc<Mutex<HashMap<usize, TcpStream>>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    }
+impl ControlServer {
+
+    /// Starts serving on the given address, which must be a loopback one unless remote clients are allowed. Each client is served by its own target.
+    pub(crate) fn start<T: ControlTarget>(address: &str, allow_remote: bool, new_target: impl Fn() -> T + Send + 'static) -> Result<ControlServer, anyhow::Error> {
+        let listener=TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        let address=listener.local_addr()?;
+        if !address.ip().is_loopback() && !allow_remote {
+            anyhow::bail!("{address} is reachable from other computers, remote clients need to be allowed explicitly");
+            }
+        let stopped=Arc::new(AtomicBool::new(false));
+        let connections: Arc<Mutex<HashMap<usize, TcpStream>>>=Arc::new(Mutex::new(HashMap::new()));
+
+        let thread={
+            let stopped=stopped.clone();
+            let connections=connections.clone();
+
+            std::thread::spawn(move || {
+                let mut next_connection_id: usize=0;
+
+                while !stopped.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if stream.set_nonblocking(false).is_err() {
+                                continue;
+                                }
+
+                            let connection_id=next_connection_id;
+                            next_connection_id+=1;
+                            if let Ok(stream_clone)=stream.try_clone() {
+                                connections.lock().unwrap().insert(connection_id, stream_clone);
+                                }
+
+                            let target=new_target();
+                            let connections=connections.clone();
+                            std::thread::spawn(move || {
+                                serve_client(stream, target);
+
+                                //The client is gone, its handle would only keep the socket open
+                                connections.lock().unwrap().remove(&connection_id);
+                                });
+                            },
+                        Err(_) => std::thread::sleep(POLL_INTERVAL),
+                        }
+                    }
+                })
+            };
+
+        Ok(ControlServer {
+            address,
+            stopped,
+            connections,
+            thread: Some(thread),
+            })
+        }
+
+    /// The address the server listens on, with the actual port if it was chosen by the system.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+        }
+
+    /// Stops accepting clients and disconnects the connected ones.
+    pub fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        if let Some(thread)=self.thread.take() {
+            let _=thread.join();
+            }
+
+        for (_, connection) in self.connections.lock().unwrap().drain() {
+            let _=connection.shutdown(std::net::Shutdown::Both);
+            }
+        }
+    }
+impl Drop for ControlServer {
+
+    fn drop(&mut self) {
+        self.stop();
+        }
+    }
+
+/// Answers the requests of a client and sends it the document events, until it disconnects.
+fn serve_client(stream: TcpStream, mut target: impl ControlTarget) {
+    let writer=match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(_) => return,
+        };
+    let disconnected=Arc::new(AtomicBool::new(false));
+
+    let events=target.subscribe();
+    let event_thread={
+        let writer=writer.clone();
+        let disconnected=disconnected.clone();
+
+        std::thread::spawn(move || {
+            while !disconnected.load(Ordering::SeqCst) {
+                match events.recv_timeout(POLL_INTERVAL) {
+                    Ok(event) => {
+                        let queued: Vec<SdamEvent>=std::iter::once(event).chain(events.try_iter()).collect();
+
+                        if coalesce_changes(queued).iter().any(|event| write_line(&writer, &event_json(event)).is_err()) {
+                            break;
+                            }
+                        },
+                    Err(mpsc::RecvTimeoutError::Timeout) => {},
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })
+        };
+
+    for line in BufReader::new(stream).lines() {
+        let line=match line {
+            Ok(line) => line,
+            Err(_) => break,
+            };
+
+        if line.trim().is_empty() {
+            continue;
+            }
+
+        if write_line(&writer, &respond(&mut target, &line)).is_err() {
+            break;
+            }
+        }
+
+    disconnected.store(true, Ordering::SeqCst);
+    let _=event_thread.join();
+    }
+/// Drops the document changes followed by a later one, so a slow client isn't sent every change made while recording.
+fn coalesce_changes(events: Vec<SdamEvent>) -> Vec<SdamEvent> {
+    let mut later_change=false;
+    let mut result: Vec<SdamEvent>=events.into_iter().rev()
+    .filter(|event| match event {
+        SdamEvent::DocumentChanged {..} => !std::mem::replace(&mut later_change, true),
+        _ => true,
+        })
+    .collect();
+    result.reverse();
+
+    result
+    }
+fn write_line(writer: &Mutex<TcpStream>, message: &Value) -> std::io::Result<()> {
+    let mut writer=writer.lock().unwrap();
+
+    writeln!(writer, "{}", message)?;
+    writer.flush()
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::MarkManager;
+
+    /// Keeps marks the way the audio handler does and lets the test send events to the clients.
+    struct TestTarget {
+        marks: Arc<Mutex<MarkManager>>,
+        event_senders: Arc<Mutex<Vec<mpsc::Sender<SdamEvent>>>>,
+        }
+    impl ControlTarget for TestTarget {
+
+        fn execute(&mut self, request: Request) -> Result<Value, anyhow::Error> {
+            match request {
+                Request::AddMark {category, label, time} => {
+                    let frame=duration_to_frame_offset(seconds_to_duration(time.unwrap_or(0.0))?);
+                    let mut marks=self.marks.lock().unwrap();
+
+                    Ok(mark_json(marks.add(Mark::new(frame, category, label))))
+                    },
+                Request::Marks => Ok(Value::Array(self.marks.lock().unwrap().get_mark_list().iter().map(mark_json).collect())),
+                Request::DeleteMark {mark_id} => {
+                    let mut marks=self.marks.lock().unwrap();
+
+                    //The same lookup as the GetMark handler does
+                    existing_mark(marks.get(mark_id).ok().cloned(), mark_id)?;
+                    marks.remove(mark_id);
+
+                    Ok(Value::Null)
+                    },
+                Request::Pause => Ok(Value::Null),
+                _ => anyhow::bail!("Not supported"),
+                }
+            }
+        fn subscribe(&mut self) -> mpsc::Receiver<SdamEvent> {
+            let (event_sender, event_receiver)=mpsc::channel::<SdamEvent>();
+            self.event_senders.lock().unwrap().push(event_sender);
+
+            event_receiver
+            }
+        }
+
+    fn request(client: &mut TcpStream, reader: &mut BufReader<TcpStream>, line: &str) -> Value {
+        writeln!(client, "{}", line).unwrap();
+
+        read_message(reader)
+        }
+    fn read_message(reader: &mut BufReader<TcpStream>) -> Value {
+        let mut response=String::new();
+        reader.read_line(&mut response).unwrap();
+
+        serde_json::from_str(&response).unwrap()
+        }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        for _ in 0..50 {
+            if condition() {
+                return;
+                }
+
+            std::thread::sleep(POLL_INTERVAL);
+            }
+
+        panic!("The condition wasn't met in time");
+        }
+
+    #[test]
+    fn request_parsing_test() {
+        let request: Request=serde_json::from_str(r#"{"id": 3, "command": "add_mark", "category": 2}"#).unwrap();
+        assert_eq!(request, Request::AddMark {category: 2, label: None, time: None});
+
+        let request: Request=serde_json::from_str(r#"{"command": "seek", "position": 75.5}"#).unwrap();
+        assert_eq!(request, Request::Seek {position: 75.5});
+
+        assert!(serde_json::from_str::<Request>(r#"{"command": "seek"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"command": "explode"}"#).is_err());
+        assert!(seconds_to_duration(-1.0).is_err());
+
+        //Clients can't choose where the document is written
+        assert_eq!(serde_json::from_str::<Request>(r#"{"command": "save", "path": "/etc/profile"}"#).unwrap(), Request::Save);
+        }
+
+    #[test]
+    fn coalesce_changes_test() {
+        let events=coalesce_changes(vec![
+            SdamEvent::DocumentChanged {change_count: 1},
+            SdamEvent::RejoinedLive,
+            SdamEvent::DocumentChanged {change_count: 2},
+            SdamEvent::DocumentChanged {change_count: 3},
+            ]);
+        let events: Vec<Value>=events.iter().map(event_json).collect();
+
+        assert_eq!(events, vec![json!({"event": "rejoined_live"}), json!({"event": "document_changed", "change_count": 3})]);
+        }
+
+    #[test]
+    fn remote_address_test() {
+        let new_target=|| TestTarget {
+            marks: Arc::new(Mutex::new(MarkManager::new())),
+            event_senders: Arc::new(Mutex::new(Vec::new())),
+            };
+
+        assert!(ControlServer::start("0.0.0.0:0", false, new_target).is_err());
+        assert!(ControlServer::start("0.0.0.0:0", true, new_target).is_ok());
+        }
+
+    #[test]
+    fn control_server_test() {
+        let marks: Arc<Mutex<MarkManager>>=Arc::new(Mutex::new(MarkManager::new()));
+        let event_senders: Arc<Mutex<Vec<mpsc::Sender<SdamEvent>>>>=Arc::new(Mutex::new(Vec::new()));
+
+        let mut server={
+            let marks=marks.clone();
+            let event_senders=event_senders.clone();
+
+            ControlServer::start("127.0.0.1:0", false, move || TestTarget {
+                marks: marks.clone(),
+                event_senders: event_senders.clone(),
+                }).unwrap()
+            };
+
+        let mut client=TcpStream::connect(server.address()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader=BufReader::new(client.try_clone().unwrap());
+
+        let response=request(&mut client, &mut reader, r#"{"id": 1, "command": "add_mark", "category": 2, "label": "Proof", "time": 60}"#);
+        assert_eq!(response, json!({"id": 1, "ok": true, "result": {"id": 0, "time": 60.0, "frame_offset": 1500, "category": 2, "label": "Proof"}}));
+
+        let response=request(&mut client, &mut reader, r#"{"id": "marks", "command": "marks"}"#);
+        assert_eq!(response["id"], "marks");
+        assert_eq!(response["result"].as_array().unwrap().len(), 1);
+        assert_eq!(marks.lock().unwrap().get_mark_list().len(), 1);
+
+        //Unknown marks are answered by an error, keeping the client connected
+        let response=request(&mut client, &mut reader, r#"{"id": 4, "command": "delete_mark", "mark_id": 999}"#);
+        assert_eq!(response, json!({"id": 4, "ok": false, "error": "There's no mark 999"}));
+        assert_eq!(request(&mut client, &mut reader, r#"{"id": 5, "command": "delete_mark", "mark_id": 0}"#), json!({"id": 5, "ok": true}));
+        assert!(marks.lock().unwrap().get_mark_list().is_empty());
+
+        assert_eq!(request(&mut client, &mut reader, r#"{"command": "pause"}"#), json!({"id": null, "ok": true}));
+        assert_eq!(request(&mut client, &mut reader, r#"{"command": "undo"}"#), json!({"id": null, "ok": false, "error": "Not supported"}));
+        assert_eq!(request(&mut client, &mut reader, "not json")["ok"], false);
+        assert_eq!(request(&mut client, &mut reader, r#"{"id": 2, "command": "explode"}"#)["id"], 2);
+
+        //Clients which disconnect are forgotten
+        let second_client=TcpStream::connect(server.address()).unwrap();
+        wait_for(|| server.connections.lock().unwrap().len()==2);
+        drop(second_client);
+        wait_for(|| server.connections.lock().unwrap().len()==1);
+
+        //Events reach the client between responses, the disconnected one is gone
+        for event_sender in event_senders.lock().unwrap().iter() {
+            let _=event_sender.send(SdamEvent::DocumentChanged {change_count: 7});
+            }
+        assert_eq!(read_message(&mut reader), json!({"event": "document_changed", "change_count": 7}));
+
+        //Stopping disconnects the client
+        server.stop();
+        let mut rest=String::new();
+        assert_eq!(reader.read_line(&mut rest).unwrap(), 0);
+        assert!(TcpStream::connect(server.address()).is_err());
+        }
+    }
@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use derive_getters::Getters;
+
+use crate::noise::NoiseSuppressor;
+use crate::SAMPLING_RATE;
+
+/// Amplitude above which the limiter starts to bend the signal, relative to the full scale.
+const LIMITER_KNEE: f32=0.9;
+/// The most the loudness normalization boosts or attenuates a document, in dB.
+const MAX_NORMALIZATION_GAIN: f32=24.0;
+
+/// Settings of the dynamic range compressor, which evens out the loudness of the playback, so quiet speech gets as clear as loud.
+#[derive(Clone, Copy, Debug, Getters)]
+pub struct CompressorSettings {
+    /// Level in dBFS above which the signal gets compressed.
+    threshold: f32,
+    /// How many dB of input above the threshold give one dB of output.
+    ratio: f32,
+    /// Gain in dB applied after the compression.
+    makeup_gain: f32,
+    attack: Duration,
+    release: Duration,
+    }
+impl CompressorSettings {
+
+    pub fn new(threshold: f32, ratio: f32) -> CompressorSettings {
+        CompressorSettings {
+            threshold,
+            ratio,
+            makeup_gain: 0.0,
+            attack: Duration::from_millis(5),
+            release: Duration::from_millis(150),
+            }
+        }
+    /// Strong compression with makeup gain, lifting quiet voices recorded from afar.
+    pub fn speech() -> CompressorSettings {
+        CompressorSettings::new(-30.0, 4.0)
+        .with_makeup_gain(12.0)
+        }
+
+    pub fn with_makeup_gain(self, makeup_gain: f32) -> CompressorSettings {
+        CompressorSettings {
+            makeup_gain,
+            ..self
+            }
+        }
+    pub fn with_timing(self, attack: Duration, release: Duration) -> CompressorSettings {
+        CompressorSettings {
+            attack,
+            release,
+            ..self
+            }
+        }
+    }
+
+struct Compressor {
+    settings: CompressorSettings,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    level: f32,
+    }
+impl Compressor {
+
+    fn new(settings: CompressorSettings) -> Compressor {
+        Compressor {
+            settings,
+            attack_coefficient: Self::smoothing_coefficient(settings.attack),
+            release_coefficient: Self::smoothing_coefficient(settings.release),
+            level: 0.0,
+            }
+        }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let magnitude=sample.abs();
+        let coefficient=if magnitude>self.level {
+            self.attack_coefficient
+            }
+        else {
+            self.release_coefficient
+            };
+        self.level=coefficient*self.level+(1.0-coefficient)*magnitude;
+
+        let level=linear_to_db(self.level);
+        let gain_reduction=if level>self.settings.threshold && self.settings.ratio>1.0 {
+            (level-self.settings.threshold)*(1.0-1.0/self.settings.ratio)
+            }
+        else {
+            0.0
+            };
+
+        sample*db_to_linear(self.settings.makeup_gain-gain_reduction)
+        }
+
+    fn smoothing_coefficient(time: Duration) -> f32 {
+        let samples=time.as_secs_f32()*SAMPLING_RATE as f32;
+
+        if samples<=0.0 {
+            return 0.0;
+            }
+
+        (-1.0/samples).exp()
+        }
+    }
+
+/// The processing of played audio: optional noise suppression, gain, loudness normalization, optional compression, and a limiter keeping the result from clipping.
+pub(crate) struct PlaybackProcessor {
+    noise_suppressor: Option<NoiseSuppressor>,
+    gain: f32,
+    normalization_gain: f32,
+    compressor: Option<Compressor>,
+    }
+impl PlaybackProcessor {
+
+    pub(crate) fn new() -> PlaybackProcessor {
+        PlaybackProcessor {
+            noise_suppressor: None,
+            gain: 1.0,
+            normalization_gain: 1.0,
+            compressor: None,
+            }
+        }
+
+    /// Enables or disables the noise suppression. The noise floor is learned anew after enabling it.
+    pub(crate) fn set_noise_suppression(&mut self, enabled: bool) {
+        if enabled!=self.noise_suppressor.is_some() {
+            self.noise_suppressor=enabled.then(NoiseSuppressor::new);
+            }
+        }
+    /// Forgets the buffered audio, to be called when the played audio doesn't continue the previous one.
+    pub(crate) fn reset(&mut self) {
+        if let Some(noise_suppressor)=&mut self.noise_suppressor {
+            noise_suppressor.reset();
+            }
+        }
+    /// Sets the gain in dB.
+    pub(crate) fn set_gain(&mut self, gain: f32) {
+        self.gain=db_to_linear(gain);
+        }
+    /// Sets the gain bringing the document to the target loudness, both in dB, or resets it if there's no measurement or target.
+    pub(crate) fn set_normalization(&mut self, loudness: Option<f32>, target: Option<f32>) {
+        self.normalization_gain=match (loudness, target) {
+            (Some(loudness), Some(target)) => db_to_linear((target-loudness).clamp(-MAX_NORMALIZATION_GAIN, MAX_NORMALIZATION_GAIN)),
+            _ => 1.0,
+            };
+        }
+    pub(crate) fn set_compressor(&mut self, settings: Option<CompressorSettings>) {
+        self.compressor=settings.map(Compressor::new);
+        }
+
+    pub(crate) fn process(&mut self, samples: &mut [i16]) {
+        let gain=self.gain*self.normalization_gain;
+
+        if gain==1.0 && self.compressor.is_none() && self.noise_suppressor.is_none() {
+            return;
+            }
+
+        let mut values: Vec<f32>=samples.iter()
+        .map(|sample| *sample as f32/32768.0)
+        .collect();
+
+        if let Some(noise_suppressor)=&mut self.noise_suppressor {
+            noise_suppressor.process(&mut values);
+            }
+
+        for (sample, value) in samples.iter_mut().zip(values) {
+            let mut value=value*gain;
+
+            if let Some(compressor)=&mut self.compressor {
+                value=compressor.process(value);
+                }
+
+            *sample=(limit(value)*32767.0) as i16;
+            }
+        }
+    }
+
+/// Bends amplitudes above the knee smoothly towards the full scale, instead of clipping them.
+fn limit(value: f32) -> f32 {
+    let magnitude=value.abs();
+
+    if magnitude<=LIMITER_KNEE {
+        return value;
+        }
+
+    let headroom=1.0-LIMITER_KNEE;
+    let limited=LIMITER_KNEE+headroom*((magnitude-LIMITER_KNEE)/headroom).tanh();
+
+    limited.copysign(value)
+    }
+
+pub(crate) fn db_to_linear(db: f32) -> f32 {
+    10.0_f32.powf(db/20.0)
+    }
+pub(crate) fn linear_to_db(linear: f32) -> f32 {
+    20.0*linear.max(1e-9).log10()
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn tone(amplitude: f32, samples: usize) -> Vec<i16> {
+        (0..samples)
+        .map(|i| ((i as f32*440.0*2.0*std::f32::consts::PI/SAMPLING_RATE as f32).sin()*amplitude*32767.0) as i16)
+        .collect()
+        }
+    fn peak(samples: &[i16]) -> f32 {
+        samples.iter().map(|sample| sample.unsigned_abs()).max().unwrap() as f32/32768.0
+        }
+
+    #[test]
+    fn playback_gain_test() {
+        let mut processor=PlaybackProcessor::new();
+
+        let mut samples=tone(0.1, 4800);
+        processor.process(&mut samples);
+        assert_eq!(samples, tone(0.1, 4800));
+
+        processor.set_gain(6.0);
+        processor.process(&mut samples);
+        assert!((peak(&samples)-0.2).abs()<0.01);
+
+        //Loud input is limited instead of clipped
+        let mut samples=tone(0.8, 4800);
+        processor.set_gain(12.0);
+        processor.process(&mut samples);
+        assert!(peak(&samples)<1.0 && peak(&samples)>0.95);
+
+        processor.set_gain(0.0);
+        processor.set_normalization(Some(-30.0), Some(-20.0));
+        let mut samples=tone(0.05, 4800);
+        processor.process(&mut samples);
+        assert!((peak(&samples)-0.158).abs()<0.01);
+        }
+
+    #[test]
+    fn compressor_test() {
+        let mut processor=PlaybackProcessor::new();
+        processor.set_compressor(Some(CompressorSettings::speech()));
+
+        let mut quiet=tone(0.01, 48000);
+        processor.process(&mut quiet);
+        let mut loud=tone(0.5, 48000);
+        processor.process(&mut loud);
+
+        //The quiet tone is lifted by the makeup gain, the loud one is held down, so their difference shrinks from 34 dB
+        let quiet_peak=peak(&quiet[24000..]);
+        let loud_peak=peak(&loud[24000..]);
+        assert!(quiet_peak>0.03);
+        assert!(loud_peak<1.0);
+        assert!(linear_to_db(loud_peak)-linear_to_db(quiet_peak)<20.0);
+        }
+    }
@@ -0,0 +1,387 @@
+use actix::prelude::*;
+
+use derive_getters::Getters;
+
+use serde::{Serialize, Deserialize};
+
+use opus::Decoder;
+
+use crate::{AudioContainer, FRAME_SIZE, SAMPLING_RATE};
+
+/// RMS level below which a frame is considered silent, about -40 dBFS.
+const SILENCE_THRESHOLD: u16=328;
+/// Frames of a loudness measurement block, 400 ms as in ITU-R BS.1770.
+const LOUDNESS_BLOCK_FRAMES: usize=10;
+/// Frames between the starts of consecutive loudness blocks.
+const LOUDNESS_BLOCK_STEP: usize=2;
+/// Blocks quieter than this, in dBFS, are left out of the loudness measurement.
+const LOUDNESS_ABSOLUTE_GATE: f64=-70.0;
+/// Blocks quieter than this many dB below the ungated loudness are left out of the measurement, so pauses don't lower it.
+const LOUDNESS_RELATIVE_GATE: f64=10.0;
+
+/// Loudness of a part of the audio, with both values relative to the full scale.
+#[derive(Clone, Copy, Debug, PartialEq, Getters, Serialize, Deserialize)]
+pub struct EnvelopePoint {
+    peak: f32,
+    rms: f32,
+    }
+impl EnvelopePoint {
+
+    pub fn new(peak: f32, rms: f32) -> EnvelopePoint {
+        EnvelopePoint {
+            peak,
+            rms,
+            }
+        }
+    }
+
+/// Peak and RMS levels of every frame of the audio, used to draw overviews of the recording at any zoom level.
+/// The levels are computed as frames arrive and may lag behind the audio, e.g. for documents saved by older versions, in which case they're completed in the background, see start_computation.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    peaks: Vec<u16>,
+    rms: Vec<u16>,
+    }
+impl Envelope {
+
+    pub(crate) fn new() -> Envelope {
+        Envelope {
+            peaks: Vec::new(),
+            rms: Vec::new(),
+            }
+        }
+
+    /// Creates an envelope of the given RMS levels, with peaks equal to them.
+    #[cfg(test)]
+    pub(crate) fn from_rms(rms: Vec<u16>) -> Envelope {
+        Envelope {
+            peaks: rms.clone(),
+            rms,
+            }
+        }
+
+    /// The number of frames with computed levels.
+    pub(crate) fn len(&self) -> usize {
+        self.peaks.len()
+        }
+
+    /// Whether the frame is quieter than speech. Frames without computed levels are not considered silent.
+    pub(crate) fn is_silent(&self, frame: usize) -> bool {
+        match self.rms.get(frame) {
+            Some(rms) => *rms<SILENCE_THRESHOLD,
+            None => false,
+            }
+        }
+
+    /// Computes the levels of frames not covered yet.
+    pub(crate) fn update(&mut self, audio: &AudioContainer) {
+        if self.len()>audio.len() {
+            self.truncate(audio.len());
+            }
+
+        let (peaks, rms)=Self::levels(audio, self.len(), audio.len());
+        self.peaks.extend(peaks);
+        self.rms.extend(rms);
+        }
+    /// Adds levels computed by start_computation. Levels which don't continue the computed ones, e.g. because they were computed meanwhile, are refused.
+    pub(crate) fn extend(&mut self, start_frame: usize, peaks: Vec<u16>, rms: Vec<u16>) -> bool {
+        if start_frame!=self.len() || peaks.len()!=rms.len() {
+            return false;
+            }
+
+        self.peaks.extend(peaks);
+        self.rms.extend(rms);
+
+        true
+        }
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.peaks.truncate(len);
+        self.rms.truncate(len);
+        }
+    /// Removes the levels of the given range of frames, following a removal of the audio.
+    pub(crate) fn remove_range(&mut self, start_frame: usize, end_frame: usize) {
+        if end_frame>self.len() {
+            self.truncate(start_frame);
+            return;
+            }
+
+        self.peaks.drain(start_frame..end_frame);
+        self.rms.drain(start_frame..end_frame);
+        }
+    /// Adds the levels of audio inserted at the given frame. Insertions behind the computed levels are left to be covered by the next update.
+    pub(crate) fn insert(&mut self, start_frame: usize, audio: &AudioContainer) {
+        if start_frame>self.len() {
+            return;
+            }
+
+        let (peaks, rms)=Self::levels(audio, 0, audio.len());
+        self.peaks.splice(start_frame..start_frame, peaks);
+        self.rms.splice(start_frame..start_frame, rms);
+        }
+    /// Appends the levels of another document joined at the given frame. They're dropped if this envelope lags behind its audio, the gap is left to be computed.
+    pub(crate) fn append(&mut self, other: Envelope, frame_offset: usize) {
+        if self.len()==frame_offset {
+            self.peaks.extend(other.peaks);
+            self.rms.extend(other.rms);
+            }
+        }
+
+    /// Summarizes the given range of frames into the given number of points, each covering an equal part of the range.
+    /// When there are more points than frames, neighbouring points share the frame they fall into.
+    pub(crate) fn overview(&self, start_frame: usize, end_frame: usize, points: usize) -> Vec<EnvelopePoint> {
+        let end_frame=std::cmp::min(end_frame, self.len());
+
+        if start_frame>=end_frame {
+            return Vec::new();
+            }
+
+        let frames=end_frame-start_frame;
+
+        (0..points)
+        .map(|point| {
+            let bucket_start=start_frame+point*frames/points;
+            let bucket_end=std::cmp::max(start_frame+(point+1)*frames/points, bucket_start+1);
+
+            let peak=self.peaks[bucket_start..bucket_end].iter().max().copied().unwrap_or(0);
+            let square_sum: f64=self.rms[bucket_start..bucket_end].iter()
+            .map(|rms| (*rms as f64).powi(2))
+            .sum();
+            let rms=(square_sum/(bucket_end-bucket_start) as f64).sqrt();
+
+            EnvelopePoint::new(peak as f32/32768.0, rms as f32/32768.0)
+            })
+        .collect()
+        }
+
+    /// The RMS level of the given range of frames in dBFS, or None if it has no computed levels.
+    pub(crate) fn loudness(&self, start_frame: usize, end_frame: usize) -> Option<f32> {
+        let end_frame=std::cmp::min(end_frame, self.len());
+
+        if start_frame>=end_frame {
+            return None;
+            }
+
+        let square_sum: f64=self.rms[start_frame..end_frame].iter()
+        .map(|rms| (*rms as f64/32768.0).powi(2))
+        .sum();
+
+        Some(power_to_db(square_sum/(end_frame-start_frame) as f64) as f32)
+        }
+    /// The integrated loudness of the computed frames in dBFS, gated like in ITU-R BS.1770, but without the frequency weighting. None if there's nothing above the gates.
+    pub(crate) fn integrated_loudness(&self) -> Option<f32> {
+        let block_count=self.len().saturating_sub(LOUDNESS_BLOCK_FRAMES)/LOUDNESS_BLOCK_STEP+1;
+        let block_powers: Vec<f64>=(0..block_count)
+        .map(|block| {
+            let start=block*LOUDNESS_BLOCK_STEP;
+            let end=std::cmp::min(start+LOUDNESS_BLOCK_FRAMES, self.len());
+            let square_sum: f64=self.rms[start..end].iter()
+            .map(|rms| (*rms as f64/32768.0).powi(2))
+            .sum();
+
+            square_sum/(end-start).max(1) as f64
+            })
+        .filter(|power| power_to_db(*power)>LOUDNESS_ABSOLUTE_GATE)
+        .collect();
+
+        if block_powers.is_empty() {
+            return None;
+            }
+
+        let relative_gate=power_to_db(block_powers.iter().sum::<f64>()/block_powers.len() as f64)-LOUDNESS_RELATIVE_GATE;
+        let gated_powers: Vec<f64>=block_powers.into_iter()
+        .filter(|power| power_to_db(*power)>relative_gate)
+        .collect();
+
+        Some(power_to_db(gated_powers.iter().sum::<f64>()/gated_powers.len() as f64) as f32)
+        }
+
+    /// Decodes the given range of frames and returns their peak and RMS levels. Frames which can't be decoded count as silence.
+    fn levels(audio: &AudioContainer, start_frame: usize, end_frame: usize) -> (Vec<u16>, Vec<u16>) {
+        let mut peaks: Vec<u16>=Vec::new();
+        let mut rms: Vec<u16>=Vec::new();
+
+        if start_frame>=end_frame {
+            return (peaks, rms);
+            }
+
+        let mut decoder=match Decoder::new(SAMPLING_RATE, opus::Channels::Mono) {
+            Ok(decoder) => decoder,
+            Err(_) => return (vec![0; end_frame-start_frame], vec![0; end_frame-start_frame]),
+            };
+        let mut decoding_buffer=vec![0_i16; 2*FRAME_SIZE];
+
+        //Opus frames depend on the preceding ones, so the decoder is warmed up with the previous frame to get the levels right at the boundary
+        if start_frame>0 {
+            if let Some(data)=audio.frame_data(start_frame-1) {
+                let _=decoder.decode(data, &mut decoding_buffer, false);
+                }
+            }
+
+        for id in start_frame..end_frame {
+            let samples=match audio.frame_data(id).map(|data| decoder.decode(data, &mut decoding_buffer, false)) {
+                Some(Ok(decoded_samples)) => &decoding_buffer[..decoded_samples],
+                _ => &[],
+                };
+
+            let peak=samples.iter()
+            .map(|sample| sample.unsigned_abs())
+            .max()
+            .unwrap_or(0);
+            let square_sum: f64=samples.iter()
+            .map(|sample| (*sample as f64).powi(2))
+            .sum();
+            let frame_rms=if samples.is_empty() {
+                0.0
+                }
+            else {
+                (square_sum/samples.len() as f64).sqrt()
+                };
+
+            peaks.push(peak);
+            rms.push(frame_rms.round() as u16);
+            }
+
+        (peaks, rms)
+        }
+    }
+
+/// Levels computed in the background, sent back to the audio handler.
+#[derive(Message)]
+#[rtype(result="()")]
+pub(crate) struct LevelsComputed {
+    /// Revision of the audio the levels were computed for. Levels of audio edited since are discarded.
+    pub(crate) revision: u64,
+    pub(crate) start_frame: usize,
+    pub(crate) peaks: Vec<u16>,
+    pub(crate) rms: Vec<u16>,
+    }
+
+/// Computes the levels of the given range of frames on a background thread, as it decodes all of them.
+pub(crate) fn start_computation(audio: AudioContainer, start_frame: usize, end_frame: usize, revision: u64, recipient: Recipient<LevelsComputed>) {
+    std::thread::spawn(move || {
+        let (peaks, rms)=Envelope::levels(&audio, start_frame, end_frame);
+
+        recipient.do_send(LevelsComputed {
+            revision,
+            start_frame,
+            peaks,
+            rms,
+            });
+        });
+    }
+
+fn power_to_db(power: f64) -> f64 {
+    10.0*power.max(1e-12).log10()
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use opus::Encoder;
+
+    use crate::OpusFrame;
+
+    fn test_audio() -> AudioContainer {
+        let mut encoder=Encoder::new(SAMPLING_RATE, opus::Channels::Mono, opus::Application::Audio).unwrap();
+        let mut audio=AudioContainer::new();
+
+        //Five frames of silence followed by five frames of a loud tone
+        for id in 0..10 {
+            let samples: Vec<i16>=(0..FRAME_SIZE)
+            .map(|i| if id<5 { 0 } else { ((i as f64*440.0*2.0*std::f64::consts::PI/SAMPLING_RATE as f64).sin()*16000.0) as i16 })
+            .collect();
+
+            audio.push_new_frame(OpusFrame::new(encoder.encode_vec(&samples, FRAME_SIZE).unwrap()));
+            }
+
+        audio
+        }
+
+    #[test]
+    fn envelope_overview_test() {
+        let audio=test_audio();
+
+        let mut envelope=Envelope::new();
+        envelope.update(&audio);
+        assert_eq!(envelope.len(), 10);
+
+        let overview=envelope.overview(0, 10, 2);
+        assert_eq!(overview.len(), 2);
+        assert!(*overview[0].peak()<0.05);
+        assert!(*overview[1].peak()>0.3);
+        assert!(*overview[1].rms()>0.2 && overview[1].rms()<overview[1].peak());
+
+        //Zooming in past single frames repeats the frame levels
+        let overview=envelope.overview(4, 6, 4);
+        assert_eq!(overview.len(), 4);
+        assert_eq!(overview[0], overview[1]);
+        assert_eq!(overview[2], overview[3]);
+
+        assert!(envelope.overview(10, 20, 5).is_empty());
+
+        assert!(envelope.is_silent(0));
+        assert!(!envelope.is_silent(7));
+        assert!(!envelope.is_silent(10));
+        }
+
+    #[test]
+    fn envelope_edit_test() {
+        let audio=test_audio();
+
+        let mut envelope=Envelope::new();
+        envelope.update(&audio);
+        let full=envelope.clone();
+
+        envelope.remove_range(3, 7);
+        assert_eq!(envelope.len(), 6);
+        assert_eq!(envelope.overview(3, 4, 1), full.overview(7, 8, 1));
+
+        envelope.insert(3, &audio.slice(3, 7));
+        assert_eq!(envelope.len(), 10);
+        assert!(*envelope.overview(5, 7, 1)[0].peak()>0.3);
+
+        //Removals reaching behind the computed levels drop everything after the removed range
+        envelope.remove_range(8, 12);
+        assert_eq!(envelope.len(), 8);
+        }
+
+    #[test]
+    fn envelope_extend_test() {
+        let audio=test_audio();
+
+        let mut full=Envelope::new();
+        full.update(&audio);
+
+        //Levels computed in parts, like in the background, add up to the same envelope
+        let mut envelope=Envelope::new();
+        let (peaks, rms)=Envelope::levels(&audio, 0, 6);
+        assert!(envelope.extend(0, peaks, rms));
+
+        let (peaks, rms)=Envelope::levels(&audio, 6, 10);
+        assert!(!envelope.extend(4, peaks.clone(), rms.clone()));
+        assert!(envelope.extend(6, peaks, rms));
+        assert_eq!(envelope, full);
+        }
+
+    #[test]
+    fn integrated_loudness_test() {
+        assert_eq!(Envelope::new().integrated_loudness(), None);
+
+        //Speech at -20 dBFS interrupted by pauses, which the gating leaves out
+        let rms: Vec<u16>=(0..200).map(|frame| if frame%50<30 { 3277 } else { 0 }).collect();
+        let envelope=Envelope {
+            peaks: rms.clone(),
+            rms,
+            };
+        let loudness=envelope.integrated_loudness().unwrap();
+        assert!((loudness+20.0).abs()<1.0);
+
+        let quiet=Envelope {
+            peaks: vec![1; 20],
+            rms: vec![1; 20],
+            };
+        assert_eq!(quiet.integrated_loudness(), None);
+        }
+    }
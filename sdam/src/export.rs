@@ -0,0 +1,265 @@
+use std::fs;
+use std::path::Path;
+
+use derive_getters::Getters;
+
+use crate::{frame_offset_to_time, AudioContainer, Mark, MarkManager, Notes, FRAME_DURATION};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StudySheetFormat {
+    Markdown,
+    Html,
+    }
+
+#[derive(Clone, Debug, Getters)]
+pub struct StudySheetOptions {
+    format: StudySheetFormat,
+    title: Option<String>,
+    /// Seconds of audio exported before and after each mark, if snippets are requested.
+    snippet_padding: Option<(usize, usize)>,
+    }
+impl StudySheetOptions {
+
+    pub fn new(format: StudySheetFormat) -> StudySheetOptions {
+        StudySheetOptions {
+            format,
+            title: None,
+            snippet_padding: None,
+            }
+        }
+
+    pub fn with_title(self, title: &str) -> StudySheetOptions {
+        StudySheetOptions {
+            title: Some(title.to_string()),
+            ..self
+            }
+        }
+    pub fn with_snippets(self, seconds_before: usize, seconds_after: usize) -> StudySheetOptions {
+        StudySheetOptions {
+            snippet_padding: Some((seconds_before, seconds_after)),
+            ..self
+            }
+        }
+    }
+
+/// Writes the study sheet to the given path. Snippets, if requested, are written to a directory named after the sheet, placed next to it.
+pub fn write_study_sheet(path: &Path, audio: &AudioContainer, marks: &MarkManager, notes: &Notes, options: &StudySheetOptions) -> Result<(), anyhow::Error> {
+    let mut snippets: Vec<(u64, String)>=Vec::new();
+
+    if let Some((seconds_before, seconds_after))=options.snippet_padding {
+        let directory_name=format!("{}_snippets", path.file_stem().unwrap_or_default().to_string_lossy());
+        let directory=path.with_file_name(&directory_name);
+        fs::create_dir_all(&directory)?;
+
+        for mark in marks.get_mark_list() {
+            let id=if let Some(id)=mark.id() {
+                *id
+                }
+            else {
+                continue;
+                };
+
+            let start_frame=mark.frame_offset().saturating_sub(1000*seconds_before/FRAME_DURATION);
+            let end_frame=mark.frame_offset()+1000*seconds_after/FRAME_DURATION;
+            let file_name=format!("mark_{id}.wav");
+
+            audio.write_wav(start_frame, end_frame, &directory.join(&file_name))?;
+            snippets.push((id, format!("{directory_name}/{file_name}")));
+            }
+        }
+
+    let sheet=render_study_sheet(audio.len(), marks, notes, options, &snippets);
+    fs::write(path, sheet)?;
+
+    Ok(())
+    }
+
+/// Renders the study sheet. Snippets are pairs of mark ids and the links of their audio files.
+pub fn render_study_sheet(audio_len: usize, marks: &MarkManager, notes: &Notes, options: &StudySheetOptions, snippets: &[(u64, String)]) -> String {
+    let title=options.title.clone().unwrap_or_else(|| "SDAM notes".to_string());
+
+    let mut categories: Vec<usize>=marks.get_mark_list().iter()
+    .map(|mark| *mark.category())
+    .collect();
+    categories.sort();
+    categories.dedup();
+
+    let mut sheet=String::new();
+
+    match options.format {
+        StudySheetFormat::Markdown => {
+            sheet+=&format!("# {title}\n\nDuration: {}\n", frame_offset_to_time(audio_len));
+
+            if !categories.is_empty() {
+                sheet+="\n## Marks\n";
+                }
+
+            for category in categories {
+                sheet+=&format!("\n### Category {category}\n\n");
+
+                for mark in marks_of_category(marks, category) {
+                    sheet+=&format!("- `{}` {}", frame_offset_to_time(*mark.frame_offset()), mark_label(mark));
+
+                    if let Some(link)=snippet_link(mark, snippets) {
+                        sheet+=&format!(" ([listen]({link}))");
+                        }
+
+                    sheet+="\n";
+                    }
+                }
+
+            if !notes.lines().is_empty() {
+                sheet+="\n## Notes\n";
+                }
+
+            for line in notes.lines() {
+                if line.text().trim().is_empty() {
+                    continue;
+                    }
+
+                sheet+="\n";
+                if let Some(frame_offset)=line.frame_offset() {
+                    sheet+=&format!("`{}` ", frame_offset_to_time(*frame_offset));
+                    }
+                sheet+=&format!("{}\n", line.text());
+                }
+            },
+        StudySheetFormat::Html => {
+            let title=escape_html(&title);
+
+            sheet+="<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+            sheet+=&format!("<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n");
+            sheet+=&format!("<p>Duration: {}</p>\n", frame_offset_to_time(audio_len));
+
+            if !categories.is_empty() {
+                sheet+="<h2>Marks</h2>\n";
+                }
+
+            for category in categories {
+                sheet+=&format!("<h3>Category {category}</h3>\n<ul>\n");
+
+                for mark in marks_of_category(marks, category) {
+                    sheet+=&format!("<li><time>{}</time> {}", frame_offset_to_time(*mark.frame_offset()), escape_html(&mark_label(mark)));
+
+                    if let Some(link)=snippet_link(mark, snippets) {
+                        sheet+=&format!(" <audio controls src=\"{}\"></audio>", escape_html(link));
+                        }
+
+                    sheet+="</li>\n";
+                    }
+
+                sheet+="</ul>\n";
+                }
+
+            if !notes.lines().is_empty() {
+                sheet+="<h2>Notes</h2>\n";
+                }
+
+            for line in notes.lines() {
+                if line.text().trim().is_empty() {
+                    continue;
+                    }
+
+                sheet+="<p>";
+                if let Some(frame_offset)=line.frame_offset() {
+                    sheet+=&format!("<time>{}</time> ", frame_offset_to_time(*frame_offset));
+                    }
+                sheet+=&format!("{}</p>\n", escape_html(line.text()));
+                }
+
+            sheet+="</body>\n</html>\n";
+            },
+        }
+
+    sheet
+    }
+
+fn marks_of_category(marks: &MarkManager, category: usize) -> Vec<&Mark> {
+    let mut result: Vec<&Mark>=marks.get_mark_list().iter()
+    .filter(|mark| *mark.category()==category)
+    .collect();
+    result.sort_by_key(|mark| *mark.frame_offset());
+
+    result
+    }
+fn mark_label(mark: &Mark) -> String {
+    match mark.label() {
+        Some(label) => label.clone(),
+        None => "Unlabelled mark".to_string(),
+        }
+    }
+fn snippet_link<'a>(mark: &Mark, snippets: &'a [(u64, String)]) -> Option<&'a String> {
+    let id=(*mark.id())?;
+
+    snippets.iter()
+    .find(|(snippet_id, _)| *snippet_id==id)
+    .map(|(_, link)| link)
+    }
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn test_document() -> (MarkManager, Notes) {
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(1500, 2, None));
+        marks.add(Mark::new(750, 1, Some("Hilbert <space>".to_string())));
+        marks.add(Mark::new(250, 1, None));
+
+        let mut notes=Notes::new();
+        notes.update("Proof idea", Some(1500));
+        notes.update("Proof idea\n\nUnanchored", None);
+
+        (marks, notes)
+        }
+
+    #[test]
+    fn markdown_study_sheet_test() {
+        let (marks, notes)=test_document();
+        let options=StudySheetOptions::new(StudySheetFormat::Markdown).with_title("Lecture");
+
+        let sheet=render_study_sheet(3000, &marks, &notes, &options, &[(1, "snippets/mark_1.wav".to_string())]);
+
+        assert_eq!(sheet, "# Lecture
+
+Duration: 02:00
+
+## Marks
+
+### Category 1
+
+- `00:10` Unlabelled mark
+- `00:30` Hilbert <space> ([listen](snippets/mark_1.wav))
+
+### Category 2
+
+- `01:00` Unlabelled mark
+
+## Notes
+
+`01:00` Proof idea
+
+Unanchored
+");
+        }
+
+    #[test]
+    fn html_study_sheet_test() {
+        let (marks, notes)=test_document();
+        let options=StudySheetOptions::new(StudySheetFormat::Html);
+
+        let sheet=render_study_sheet(3000, &marks, &notes, &options, &[]);
+
+        assert!(sheet.contains("<title>SDAM notes</title>"));
+        assert!(sheet.contains("<li><time>00:30</time> Hilbert &lt;space&gt;</li>"));
+        assert!(sheet.contains("<p><time>01:00</time> Proof idea</p>"));
+        assert!(sheet.contains("<p>Unanchored</p>"));
+        }
+    }
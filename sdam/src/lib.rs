@@ -1,7 +1,7 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::sync::{mpsc, Arc};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 
@@ -13,13 +13,99 @@ use derive_getters::Getters;
 use ringbuf::HeapRb;
 
 use serde::{Serialize, Deserialize};
-use rmp_serde;
 
 use opus::{Encoder, Decoder};
 
+mod batch;
+mod changes;
+mod clock;
+mod control;
+mod dynamics;
+mod envelope;
+mod export;
+mod monitor;
+mod noise;
+mod search;
+mod speakers;
+mod storage;
+mod suggestions;
+mod time_travel;
+mod transcription;
+
+pub use batch::{document_info, export_document_audio, import_wav, validate_document, DocumentInfo};
+pub use control::ControlServer;
+pub use dynamics::CompressorSettings;
+pub use envelope::EnvelopePoint;
+pub use export::{StudySheetFormat, StudySheetOptions};
+pub use search::{SearchHit, SearchOptions, SearchSource};
+pub use speakers::SpeakerSegment;
+pub use suggestions::{MarkSuggestion, SuggestionReason};
+pub use time_travel::{CatchUpPolicy, TimeTravelOptions};
+pub use transcription::{RecognizedText, TranscriptSegment, TranscriptionEngine, WhisperCli};
+
+use changes::ChangeTracker;
+use clock::PlaybackClock;
+use dynamics::PlaybackProcessor;
+use envelope::{Envelope, LevelsComputed};
+use monitor::{Monitor, MonitorInput, MonitorOutput};
+use speakers::{Diarization, SpeakersDetected};
+use suggestions::MarkSuggestions;
+use time_travel::TimeTravel;
+use transcription::{Transcriber, Transcript, TranscriptionDone};
+
+use storage::MappedFrames;
+
 const FRAME_DURATION: usize=40; //ms
 const SAMPLING_RATE: u32=48000;
 const FRAME_SIZE: usize=(FRAME_DURATION as f64*SAMPLING_RATE as f64/1000.0) as usize;
+const EDIT_HISTORY_LIMIT: usize=500;
+/// Text edits following each other within this time are undone together.
+const TEXT_EDIT_MERGE_INTERVAL: Duration=Duration::from_secs(2);
+const NOTES_MATCHING_LIMIT: usize=1_000_000;
+const MINIMUM_LOOP_RATE: f64=0.25;
+
+/// Formats a frame offset as minutes and seconds, the same way the frontends display positions.
+pub fn frame_offset_to_time(frame_offset: usize) -> String {
+    let frames_in_minute=60000/FRAME_DURATION;
+    let frames_in_second=1000/FRAME_DURATION;
+
+    let minute=frame_offset/frames_in_minute;
+    let second=(frame_offset%frames_in_minute)/frames_in_second;
+
+    format!("{minute:0>2}:{second:0>2}")
+    }
+/// The time the given frame starts at.
+pub fn frame_offset_to_duration(frame_offset: usize) -> Duration {
+    Duration::from_millis((frame_offset*FRAME_DURATION) as u64)
+    }
+/// The frame playing at the given time.
+pub fn duration_to_frame_offset(time: Duration) -> usize {
+    (time.as_millis()/FRAME_DURATION as u128) as usize
+    }
+/// Parses a time given in seconds, minutes:seconds or hours:minutes:seconds, the seconds possibly with a fraction.
+pub fn parse_time(text: &str) -> Result<Duration, String> {
+    let parts: Vec<&str>=text.split(':').collect();
+
+    if parts.len()>3 {
+        return Err(format!("'{}' is not a valid time", text));
+        }
+
+    let (seconds, whole_units)=parts.split_last().unwrap();
+    let seconds: f64=match seconds.parse() {
+        Ok(seconds) if seconds>=0.0 && f64::is_finite(seconds) => seconds,
+        _ => return Err(format!("'{}' is not a valid time", text)),
+        };
+
+    let mut total_seconds=seconds;
+    for (part, unit) in whole_units.iter().rev().zip([60.0, 3600.0]) {
+        let value: u64=part.parse().map_err(|_| format!("'{}' is not a valid time", text))?;
+
+        total_seconds+=value as f64*unit;
+        }
+
+    //Times too long for a Duration are as invalid as malformed ones
+    Duration::try_from_secs_f64(total_seconds).map_err(|_| format!("'{}' is not a valid time", text))
+    }
 
 pub struct Sdam {
     audio_handler: Addr<AudioHandler>,
@@ -121,6 +207,18 @@ impl Sdam {
     pub fn jump_to_frame(&mut self, frame: usize) {
         self.audio_handler.do_send(Seek::Absolute(frame));
         }
+    /// Seeks to the given time. Playback starts exactly there, even in the middle of a frame.
+    pub fn seek(&mut self, position: Duration) {
+        self.audio_handler.do_send(Seek::Time(position));
+        }
+    /// Moves the position forward by the given time. Times longer than a relative seek can express, about 24 days, are cut to it.
+    pub fn forward_by(&mut self, delta: Duration) {
+        self.audio_handler.do_send(Seek::Relative(i32::try_from(delta.as_millis()).unwrap_or(i32::MAX)));
+        }
+    /// Moves the position backward by the given time, limited like in forward_by.
+    pub fn backward_by(&mut self, delta: Duration) {
+        self.audio_handler.do_send(Seek::Relative(-i32::try_from(delta.as_millis()).unwrap_or(i32::MAX)));
+        }
 
     // Getters
 
@@ -148,6 +246,9 @@ impl Sdam {
     pub fn audio_duration(&mut self) -> usize {
         (self.audio_len()*FRAME_DURATION)/1000
         }
+    pub fn duration(&mut self) -> Duration {
+        frame_offset_to_duration(self.audio_len())
+        }
     pub fn current_position(&mut self) -> Option<usize> {
         let (result_sender, result_receiver)=mpsc::channel::<Option<usize>>();
 
@@ -155,6 +256,132 @@ impl Sdam {
 
         result_receiver.recv().unwrap()
         }
+    /// The position being heard in milliseconds, taking the audio buffered for output into account.
+    pub fn current_position_millis(&mut self) -> Option<u64> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<u64>>();
+
+        self.audio_handler.do_send(GetCurrentPositionMillis {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// The loudness of the document in dBFS, measured over the speech and leaving out pauses. None for silent documents.
+    /// While the levels of the audio are computed in the background, e.g. after opening a document saved by an older version, it's measured over the computed part.
+    pub fn integrated_loudness(&mut self) -> Option<f32> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<f32>>();
+
+        self.audio_handler.do_send(GetIntegratedLoudness {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// The loudness in dBFS the playback of the document is normalized to, if the normalization is enabled.
+    pub fn loudness_normalization(&mut self) -> Option<f32> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<f32>>();
+
+        self.audio_handler.do_send(GetLoudnessNormalization {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// The transcript of the whole document.
+    pub fn transcript(&mut self) -> Vec<TranscriptSegment> {
+        self.transcript_between(0, usize::MAX)
+        }
+    /// Transcript segments overlapping the given range of frames.
+    pub fn transcript_between(&mut self, start_frame: usize, end_frame: usize) -> Vec<TranscriptSegment> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<TranscriptSegment>>();
+
+        self.audio_handler.do_send(GetTranscript {start_frame, end_frame, result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Finds a word or phrase in the transcript and mark labels. The hits come in one list, ordered from the best match.
+    pub fn search(&mut self, query: &str, options: SearchOptions) -> Vec<SearchHit> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<SearchHit>>();
+
+        self.audio_handler.do_send(Search {query: query.to_string(), options, result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// The speaker segments of the whole document, as found by the last speaker detection.
+    pub fn speaker_segments(&mut self) -> Vec<SpeakerSegment> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<SpeakerSegment>>();
+
+        self.audio_handler.do_send(GetSpeakerSegments {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// The speaker segment the frame falls into, if anyone speaks there.
+    pub fn speaker_at(&mut self, frame: usize) -> Option<SpeakerSegment> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<SpeakerSegment>>();
+
+        self.audio_handler.do_send(GetSpeakerAt {frame, result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// The first segment after the frame spoken by someone else than the main speaker, e.g. the next question from the audience.
+    pub fn next_question(&mut self, frame: usize) -> Option<SpeakerSegment> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<SpeakerSegment>>();
+
+        self.audio_handler.do_send(GetNextQuestion {frame, result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    pub fn is_detecting_speakers(&mut self) -> bool {
+        let (result_sender, result_receiver)=mpsc::channel::<bool>();
+
+        self.audio_handler.do_send(GetIsDetectingSpeakers {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Whether there's audio waiting for the transcription engine.
+    pub fn is_transcribing(&mut self) -> bool {
+        let (result_sender, result_receiver)=mpsc::channel::<bool>();
+
+        self.audio_handler.do_send(GetIsTranscribing {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Whether the playback of the document is denoised.
+    pub fn noise_suppression(&mut self) -> bool {
+        let (result_sender, result_receiver)=mpsc::channel::<bool>();
+
+        self.audio_handler.do_send(GetNoiseSuppression {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Whether the input is being heard directly at the moment.
+    pub fn is_monitoring_live(&mut self) -> bool {
+        let (result_sender, result_receiver)=mpsc::channel::<bool>();
+
+        self.audio_handler.do_send(GetIsMonitoringLive {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    pub fn is_time_travelling(&mut self) -> bool {
+        let (result_sender, result_receiver)=mpsc::channel::<bool>();
+
+        self.audio_handler.do_send(GetIsTimeTravelling {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// How far the heard audio is behind the recording while time travelling.
+    pub fn live_lag(&mut self) -> Option<Duration> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<Duration>>();
+
+        self.audio_handler.do_send(GetLiveLag {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Start (inclusive) and end (exclusive) frame of the active loop.
+    pub fn loop_region(&mut self) -> Option<(usize, usize)> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<(usize, usize)>>();
+
+        self.audio_handler.do_send(GetLoopRegion {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    pub fn position(&mut self) -> Option<Duration> {
+        self.current_position_millis().map(Duration::from_millis)
+        }
     pub fn is_playing(&mut self) -> bool {
         let (result_sender, result_receiver)=mpsc::channel::<bool>();
 
@@ -174,6 +401,30 @@ impl Sdam {
 
         self.audio_handler.do_send(GetIsRecording {result_sender});
 
+        result_receiver.recv().unwrap()
+        }
+    /// The playback rate set by the user, without the slowdown of a loop.
+    pub fn rate(&mut self) -> f64 {
+        let (result_sender, result_receiver)=mpsc::channel::<f64>();
+
+        self.audio_handler.do_send(GetRate {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Whether the document changed since it was last loaded or saved.
+    pub fn is_modified(&mut self) -> bool {
+        let (result_sender, result_receiver)=mpsc::channel::<bool>();
+
+        self.audio_handler.do_send(GetIsModified {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Number of changes made to the document since the Sdam instance was created. Frontends can compare it to a previously seen value to find out whether they need to refresh.
+    pub fn change_count(&mut self) -> u64 {
+        let (result_sender, result_receiver)=mpsc::channel::<u64>();
+
+        self.audio_handler.do_send(GetChangeCount {result_sender});
+
         result_receiver.recv().unwrap()
         }
     pub fn get_mark(&mut self, id: u64) -> Option<Mark> {
@@ -209,6 +460,31 @@ impl Sdam {
 
         self.audio_handler.do_send(GetUserText {result_sender});
 
+        result_receiver.recv().unwrap()
+        }
+    /// The user text split into lines, each anchored to the frame that was playing or being recorded when the line was written.
+    pub fn note_lines(&mut self) -> Vec<NoteLine> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<NoteLine>>();
+
+        self.audio_handler.do_send(GetNoteLines {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Index of the note line written during the given frame, i.e. the last line anchored at or before it.
+    pub fn note_line_index_at(&mut self, frame: usize) -> Option<usize> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<usize>>();
+
+        self.audio_handler.do_send(GetNoteLineIndexAt {frame, result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Peak and RMS levels of the given range of frames, summarized into the given number of points, e.g. one per pixel of a waveform view.
+    /// Empty while the levels of the range are computed in the background, e.g. after opening a document saved by an older version.
+    pub fn envelope(&mut self, start_frame: usize, end_frame: usize, points: usize) -> Vec<EnvelopePoint> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<EnvelopePoint>>();
+
+        self.audio_handler.do_send(GetEnvelope {start_frame, end_frame, points, result_sender});
+
         result_receiver.recv().unwrap()
         }
 
@@ -221,6 +497,27 @@ impl Sdam {
 
         result_receiver.recv().unwrap()
         }
+    /// Analyzes the recording for places worth a mark, i.e. long pauses, emphasized speech and cue phrases in the transcript, and returns the pending suggestions.
+    /// While the levels of the audio are computed in the background, e.g. after opening a document saved by an older version, the analysis runs once they're complete and SdamEvent::MarksSuggested is emitted.
+    pub fn suggest_marks(&mut self) -> Vec<MarkSuggestion> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<MarkSuggestion>>();
+
+        self.audio_handler.do_send(SuggestMarks {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    /// Turns the suggestion into a mark of the given category, with the label of the suggestion. Can be undone like adding a mark.
+    pub fn accept_suggestion(&mut self, suggestion_id: u64, category: usize) -> Result<Mark, anyhow::Error> {
+        let (result_sender, result_receiver)=mpsc::channel::<Result<Mark, anyhow::Error>>();
+
+        self.audio_handler.do_send(AcceptSuggestion {id: suggestion_id, category, result_sender});
+
+        result_receiver.recv()?
+        }
+    /// Discards the suggestion. The place isn't suggested again for the same reason.
+    pub fn dismiss_suggestion(&mut self, suggestion_id: u64) {
+        self.audio_handler.do_send(DismissSuggestion {id: suggestion_id});
+        }
     pub fn edit_mark(&mut self, mark_id: u64, updated_mark: Mark) {
         self.audio_handler.do_send(EditMark {id: mark_id, updated_mark});
         }
@@ -230,10 +527,187 @@ impl Sdam {
     pub fn set_rate(&mut self, rate: f64) {
         self.audio_handler.do_send(SetRate {rate });
         }
+    /// Makes the playback repeat the given region until the loop is cleared or the requested number of repetitions is played. The playback jumps to the start of the region if it's outside of it.
+    pub fn set_loop(&mut self, playback_loop: PlaybackLoop) -> Result<(), anyhow::Error> {
+        let (result_sender, result_receiver)=mpsc::channel::<Result<(), anyhow::Error>>();
+
+        self.audio_handler.do_send(SetLoop {playback_loop, result_sender});
+
+        result_receiver.recv()?
+        }
+    pub fn clear_loop(&mut self) {
+        self.audio_handler.do_send(ClearLoop {});
+        }
+    /// Sets the volume of the playback in dB, 0 keeping it unchanged. Loud results are limited rather than clipped.
+    pub fn set_playback_gain(&mut self, gain: f32) {
+        self.audio_handler.do_send(SetPlaybackGain {gain});
+        }
+    /// Enables or disables the dynamic range compression of the playback, evening out quiet and loud speech.
+    pub fn set_compressor(&mut self, settings: Option<CompressorSettings>) {
+        self.audio_handler.do_send(SetCompressor {settings});
+        }
+    /// Normalizes the playback of the document to the given loudness in dBFS, or disables the normalization. The setting is stored in the document.
+    pub fn set_loudness_normalization(&mut self, target: Option<f32>) {
+        self.audio_handler.do_send(SetLoudnessNormalization {target});
+        }
+    /// Enables or disables removing steady background noise, such as fans or air conditioning, from the playback. The recording itself is left intact and the setting is stored in the document.
+    pub fn set_noise_suppression(&mut self, enabled: bool) {
+        self.audio_handler.do_send(SetNoiseSuppression {enabled});
+        }
+    /// Sets the engine transcribing the document in the background, or turns the transcription off.
+    /// Audio without transcript is transcribed in chunks, including new recordings while they're being made. The transcript is stored in the document.
+    pub fn set_transcription_engine(&mut self, engine: Option<Box<dyn TranscriptionEngine>>) {
+        self.audio_handler.do_send(SetTranscriptionEngine {engine});
+        }
+    /// Throws away the transcript, so the document gets transcribed anew, e.g. by a better engine.
+    pub fn clear_transcript(&mut self) {
+        self.audio_handler.do_send(ClearTranscript {});
+        }
+    /// Finds out who speaks when in the recording, labelling the segments Speaker 1, 2 etc.
+    /// The detection runs in the background and announces its end by an event. The segments are stored in the document, replacing the previous ones.
+    pub fn detect_speakers(&mut self) {
+        self.audio_handler.do_send(DetectSpeakers {});
+        }
+    /// Enables hearing the input directly through the output while recording at the live edge, i.e. without playback or while time travelling at live.
+    pub fn set_monitoring(&mut self, enabled: bool) {
+        self.audio_handler.do_send(SetMonitoring {enabled});
+        }
+    /// Sets the gain of the monitored input, independently of the playback.
+    pub fn set_monitoring_gain(&mut self, gain: f32) {
+        self.audio_handler.do_send(SetMonitoringGain {gain});
+        }
+    /// Plays the recording while it's being made, starting at the live edge. The recording is started if it isn't running already.
+    pub fn start_time_travel(&mut self, options: TimeTravelOptions) {
+        self.audio_handler.do_send(StartTimeTravel {options});
+        }
+    /// Pauses the playback and stops the recording, if it was started by the time travel.
+    pub fn stop_time_travel(&mut self) {
+        self.audio_handler.do_send(StopTimeTravel {});
+        }
     pub fn set_user_text(&mut self, text: &str) {
         self.audio_handler.do_send(SetUserText{ text: text.to_string() });
         }
 
+    /// Renders the marks and notes into a study sheet written to the given path. If requested by the options, audio snippets of the marks are exported next to it.
+    pub fn export_study_sheet(&mut self, path: &str, options: StudySheetOptions) -> Result<(), anyhow::Error> {
+        let path=PathBuf::from(path);
+        let (result_sender, result_receiver)=mpsc::channel::<Result<(), anyhow::Error>>();
+
+        self.audio_handler.do_send(ExportStudySheet {
+            path,
+            options,
+            result_sender,
+            });
+
+        result_receiver.recv()?
+        }
+
+    /// Exports a part of the recording, given by a mark or frame range extended by the padding, either as a WAV file or a standalone SDAM document with the marks and notes of that part.
+    pub fn export_clip(&mut self, path: &str, source: ClipSource, seconds_before: usize, seconds_after: usize, format: ClipFormat) -> Result<(), anyhow::Error> {
+        let path=PathBuf::from(path);
+        let (result_sender, result_receiver)=mpsc::channel::<Result<(), anyhow::Error>>();
+
+        self.audio_handler.do_send(ExportClip {
+            path,
+            source,
+            seconds_before,
+            seconds_after,
+            format,
+            result_sender,
+            });
+
+        result_receiver.recv()?
+        }
+
+    /// Edits the recorded audio. Marks and note anchors are shifted to stay with their audio, marks in removed ranges are deleted. The edit can be undone.
+    pub fn edit_audio(&mut self, edit: AudioEdit) -> Result<(), anyhow::Error> {
+        let (result_sender, result_receiver)=mpsc::channel::<Result<(), anyhow::Error>>();
+
+        self.audio_handler.do_send(EditAudio {edit, result_sender});
+
+        result_receiver.recv()?
+        }
+    pub fn delete_audio(&mut self, start_frame: usize, end_frame: usize) -> Result<(), anyhow::Error> {
+        self.edit_audio(AudioEdit::Delete {start_frame, end_frame})
+        }
+    pub fn trim_start(&mut self, frame: usize) -> Result<(), anyhow::Error> {
+        self.edit_audio(AudioEdit::TrimStart(frame))
+        }
+    pub fn trim_end(&mut self, frame: usize) -> Result<(), anyhow::Error> {
+        self.edit_audio(AudioEdit::TrimEnd(frame))
+        }
+    /// Inserts the audio, marks and notes of another SDAM document at the given frame.
+    pub fn insert_document(&mut self, path: &str, frame: usize) -> Result<(), anyhow::Error> {
+        self.edit_audio(AudioEdit::InsertDocument {path: PathBuf::from(path), frame})
+        }
+
+    /// Splits the open document at the given points into files written to the output directory, named after the document with the part number. Returns paths of the written files.
+    pub fn split(&mut self, points: Vec<SplitPoint>, output_directory: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let output_directory=PathBuf::from(output_directory);
+        let (result_sender, result_receiver)=mpsc::channel::<Result<Vec<PathBuf>, anyhow::Error>>();
+
+        self.audio_handler.do_send(Split {
+            points,
+            output_directory,
+            result_sender,
+            });
+
+        result_receiver.recv()?
+        }
+
+    // Events
+
+    /// Returns a receiver of document events. The subscription ends when the receiver is dropped.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<SdamEvent> {
+        let (event_sender, event_receiver)=mpsc::channel::<SdamEvent>();
+
+        self.audio_handler.do_send(Subscribe {event_sender});
+
+        event_receiver
+        }
+    /// Starts a server letting other programs control this document and follow its events, see the control module for the protocol. Use port 0 to let the system choose one.
+    /// Addresses reachable from other computers are refused unless remote clients are allowed, as the clients aren't authenticated.
+    pub fn start_control_server(&mut self, address: &str, allow_remote: bool) -> Result<ControlServer, anyhow::Error> {
+        let audio_handler=self.audio_handler.clone();
+
+        //The clients get handles to the same audio handler, which don't stop it when dropped
+        ControlServer::start(address, allow_remote, move || Sdam {
+            audio_handler: audio_handler.clone(),
+            actix_thread: None,
+            })
+        }
+
+    // History
+
+    pub fn undo(&mut self) -> Option<String> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<String>>();
+
+        self.audio_handler.do_send(Undo {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    pub fn redo(&mut self) -> Option<String> {
+        let (result_sender, result_receiver)=mpsc::channel::<Option<String>>();
+
+        self.audio_handler.do_send(Redo {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    pub fn undo_history(&mut self) -> Vec<String> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<String>>();
+
+        self.audio_handler.do_send(GetUndoHistory {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+    pub fn redo_history(&mut self) -> Vec<String> {
+        let (result_sender, result_receiver)=mpsc::channel::<Vec<String>>();
+
+        self.audio_handler.do_send(GetRedoHistory {result_sender});
+
+        result_receiver.recv().unwrap()
+        }
+
     }
 impl Drop for Sdam {
 
@@ -246,6 +720,7 @@ impl Drop for Sdam {
         }
     }
 
+#[derive(Debug)]
 pub struct OpusFrame {
     data: Vec<u8>,
     }
@@ -279,6 +754,15 @@ impl Mark {
             label,
             }
         }
+    /// Creates a mark at the frame playing at the given time.
+    pub fn at(time: Duration, category: usize, label: Option<String>) -> Mark {
+        Mark::new(duration_to_frame_offset(time), category, label)
+        }
+
+    /// The time the marked frame starts at.
+    pub fn time(&self) -> Duration {
+        frame_offset_to_duration(self.frame_offset)
+        }
 
     pub fn with_id(&self, id: u64) -> Mark {
         Mark {
@@ -316,6 +800,28 @@ impl MarkManager {
 
         self.marks.last().unwrap()
         }
+    /// Inserts a mark while preserving its id, used for restoring previously removed marks. Marks without id are assigned a new one, just like with add.
+    pub fn insert(&mut self, mark: Mark) -> Result<&Mark, anyhow::Error> {
+        let id=if let Some(id)=mark.id() {
+            *id
+            }
+        else {
+            return Ok(self.add(mark));
+            };
+
+        if self.get(id).is_ok() {
+            anyhow::bail!("Mark with id {id} already exists.");
+            }
+
+        //Marks are kept ordered by their ids, so a restored mark gets back to its original place in the list
+        let index=self.marks.iter()
+        .position(|m| if let Some(m_id)=m.id() { *m_id>id } else { false })
+        .unwrap_or(self.marks.len());
+
+        self.marks.insert(index, mark);
+
+        Ok(&self.marks[index])
+        }
     pub fn edit(&mut self, id: u64, updated_mark: Mark) -> Result<&Mark, anyhow::Error> {
         let mut mark_index: Option<usize>=None;
         for (index, mark) in self.marks.iter().enumerate() {
@@ -420,6 +926,58 @@ impl MarkManager {
             };
         }
 
+    /// Removes the marks placed in the given frame range and moves the later ones back by the range length, following removal of the audio. Returns the removed marks.
+    pub fn remove_range(&mut self, start_frame: usize, end_frame: usize) -> Vec<Mark> {
+        let (removed, kept): (Vec<Mark>, Vec<Mark>)=self.marks.drain(..)
+        .partition(|mark| *mark.frame_offset()>=start_frame && *mark.frame_offset()<end_frame);
+
+        self.marks=kept.into_iter()
+        .map(|mark| if mark.frame_offset>=end_frame {
+            Mark {
+                frame_offset: mark.frame_offset-(end_frame-start_frame),
+                ..mark
+                }
+            }
+        else {
+            mark
+            })
+        .collect();
+
+        removed
+        }
+    /// Moves the marks placed at or after the given frame forward by the given length, following insertion of audio.
+    pub fn open_range(&mut self, start_frame: usize, len: usize) {
+        for mark in &mut self.marks {
+            if mark.frame_offset>=start_frame {
+                mark.frame_offset+=len;
+                }
+            }
+        }
+
+    /// Returns copies of the marks moved by the given frame offset and renumbered starting with the given id, so they can be added to another document.
+    pub fn rebased(&self, frame_offset: usize, first_id: u64) -> Vec<Mark> {
+        self.marks.iter().zip(first_id..)
+        .map(|(mark, id)| Mark {
+            id: Some(id),
+            frame_offset: mark.frame_offset+frame_offset,
+            ..mark.clone()
+            })
+        .collect()
+        }
+
+    /// Returns the marks placed in the given frame range, with offsets rebased to the range start.
+    pub fn clipped(&self, start_frame: usize, end_frame: usize) -> MarkManager {
+        let marks: Vec<Mark>=self.marks.iter()
+        .filter(|mark| *mark.frame_offset()>=start_frame && *mark.frame_offset()<end_frame)
+        .map(|mark| Mark {
+            frame_offset: mark.frame_offset()-start_frame,
+            ..mark.clone()
+            })
+        .collect();
+
+        MarkManager { marks }
+        }
+
     fn get_available_id(&self) -> u64 {
         let mut max_found_id: Option<u64>=None;
 
@@ -445,29 +1003,722 @@ impl MarkManager {
         }
     }
 
-#[derive(Clone, Getters, Serialize, Deserialize)]
-pub struct SdamFileModel {
-    audio: Vec<Vec<u8>>,
-    marks: MarkManager,
+#[derive(Clone, Debug, Getters, PartialEq, Serialize, Deserialize)]
+pub struct NoteLine {
     text: String,
+    frame_offset: Option<usize>,
     }
+impl NoteLine {
 
-#[derive(Message)]
-#[rtype(result="()")]
-pub struct StartPlayback {}
+    pub fn new(text: String, frame_offset: Option<usize>) -> NoteLine {
+        NoteLine {
+            text,
+            frame_offset,
+            }
+        }
+    }
 
-#[derive(Message)]
-#[rtype(result="()")]
-pub struct PausePlayback {}
+/// The user text, kept as lines anchored to positions in the audio.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Notes {
+    lines: Vec<NoteLine>,
+    }
+impl Notes {
 
-#[derive(Message)]
-#[rtype(result="()")]
-pub struct TogglePlayback {}
+    pub fn new() -> Notes {
+        Notes {
+            lines: Vec::new(),
+            }
+        }
+    /// Creates notes without anchors, used for documents saved before notes were anchored.
+    pub fn from_text(text: &str) -> Notes {
+        let mut notes=Notes::new();
+        notes.update(text, None);
 
-#[derive(Message)]
-#[rtype(result="()")]
+        notes
+        }
+
+    pub fn text(&self) -> String {
+        self.lines.iter()
+        .map(|line| &line.text[..])
+        .collect::<Vec<&str>>()
+        .join("\n")
+        }
+    pub fn lines(&self) -> &Vec<NoteLine> {
+        &self.lines
+        }
+
+    /// Replaces the text of the notes. Lines which remained the same or were edited in place keep their anchors, newly written lines are anchored to the given frame.
+    pub fn update(&mut self, text: &str, frame_offset: Option<usize>) {
+        let new_lines: Vec<&str>=if text.is_empty() {
+            Vec::new()
+            }
+        else {
+            text.split('\n').collect()
+            };
+
+        let prefix_len=self.lines.iter().zip(new_lines.iter())
+        .take_while(|(old, new)| old.text==**new)
+        .count();
+        let max_suffix_len=std::cmp::min(self.lines.len(), new_lines.len())-prefix_len;
+        let suffix_len=self.lines.iter().rev().zip(new_lines.iter().rev())
+        .take(max_suffix_len)
+        .take_while(|(old, new)| old.text==**new)
+        .count();
+
+        let old_middle=&self.lines[prefix_len..self.lines.len()-suffix_len];
+        let new_middle=&new_lines[prefix_len..new_lines.len()-suffix_len];
+
+        //Unchanged lines of the middle part are found as the longest common subsequence, lines between them are paired in place as edits
+        let mut matches=Self::match_lines(old_middle, new_middle);
+        matches.push((old_middle.len(), new_middle.len()));
+
+        let mut middle: Vec<NoteLine>=Vec::new();
+        let (mut old_index, mut new_index)=(0, 0);
+        for (matched_old_index, matched_new_index) in matches {
+            while new_index<matched_new_index {
+                let anchor=if old_index<matched_old_index {
+                    old_index+=1;
+                    old_middle[old_index-1].frame_offset.or(frame_offset)
+                    }
+                else {
+                    frame_offset
+                    };
+
+                middle.push(NoteLine::new(new_middle[new_index].to_string(), anchor));
+                new_index+=1;
+                }
+
+            if let Some(old_line)=old_middle.get(matched_old_index) {
+                middle.push(old_line.clone());
+                }
+
+            old_index=matched_old_index+1;
+            new_index=matched_new_index+1;
+            }
+
+        self.lines.splice(prefix_len..self.lines.len()-suffix_len, middle);
+        }
+
+    /// Follows removal of audio in the given frame range. Lines anchored in it lose their anchors, anchors after it are moved back.
+    pub fn remove_range(&mut self, start_frame: usize, end_frame: usize) {
+        for line in &mut self.lines {
+            if let Some(anchor)=line.frame_offset {
+                if anchor>=end_frame {
+                    line.frame_offset=Some(anchor-(end_frame-start_frame));
+                    }
+                else if anchor>=start_frame {
+                    line.frame_offset=None;
+                    }
+                }
+            }
+        }
+    /// Follows insertion of audio, moving anchors at or after the given frame forward by the given length.
+    pub fn open_range(&mut self, start_frame: usize, len: usize) {
+        for line in &mut self.lines {
+            if let Some(anchor)=line.frame_offset {
+                if anchor>=start_frame {
+                    line.frame_offset=Some(anchor+len);
+                    }
+                }
+            }
+        }
+    /// Appends lines of other notes, with anchors moved by the given offset.
+    pub fn append(&mut self, other: &Notes, frame_offset: usize) {
+        for line in &other.lines {
+            self.lines.push(NoteLine::new(line.text.clone(), line.frame_offset.map(|anchor| anchor+frame_offset)));
+            }
+        }
+
+    /// Returns the lines anchored in the given frame range, with anchors rebased to the range start.
+    pub fn clipped(&self, start_frame: usize, end_frame: usize) -> Notes {
+        let lines: Vec<NoteLine>=self.lines.iter()
+        .filter_map(|line| match line.frame_offset {
+            Some(anchor) if anchor>=start_frame && anchor<end_frame => Some(NoteLine::new(line.text.clone(), Some(anchor-start_frame))),
+            _ => None,
+            })
+        .collect();
+
+        Notes { lines }
+        }
+
+    /// Splits the notes at the given sorted frames, rebasing anchors to the start of each part. Lines without anchor go with the closest anchored line before them, so no text is lost.
+    pub fn split(&self, frames: &[usize]) -> Vec<Notes> {
+        let mut parts: Vec<Notes>=vec![Notes::new(); frames.len()+1];
+        let mut effective_anchor=0;
+
+        for line in &self.lines {
+            if let Some(anchor)=line.frame_offset {
+                effective_anchor=anchor;
+                }
+
+            let part=frames.iter().filter(|frame| **frame<=effective_anchor).count();
+            let part_start=if part>0 { frames[part-1] } else { 0 };
+
+            parts[part].lines.push(NoteLine::new(line.text.clone(), line.frame_offset.map(|anchor| anchor-part_start)));
+            }
+
+        parts
+        }
+
+    /// Returns index pairs of lines which are the same in both versions.
+    fn match_lines(old_lines: &[NoteLine], new_lines: &[&str]) -> Vec<(usize, usize)> {
+        //Bulk replacements are rare enough to be treated just as in-place edits
+        if old_lines.len()*new_lines.len()>NOTES_MATCHING_LIMIT {
+            return Vec::new();
+            }
+
+        let width=new_lines.len()+1;
+        let mut lengths=vec![0_usize; (old_lines.len()+1)*width];
+
+        for i in (0..old_lines.len()).rev() {
+            for j in (0..new_lines.len()).rev() {
+                lengths[i*width+j]=if old_lines[i].text==new_lines[j] {
+                    lengths[(i+1)*width+j+1]+1
+                    }
+                else {
+                    std::cmp::max(lengths[(i+1)*width+j], lengths[i*width+j+1])
+                    };
+                }
+            }
+
+        let mut result: Vec<(usize, usize)>=Vec::new();
+        let (mut i, mut j)=(0, 0);
+        while i<old_lines.len() && j<new_lines.len() {
+            if old_lines[i].text==new_lines[j] {
+                result.push((i, j));
+                i+=1;
+                j+=1;
+                }
+            else if lengths[(i+1)*width+j]>=lengths[i*width+j+1] {
+                i+=1;
+                }
+            else {
+                j+=1;
+                }
+            }
+
+        result
+        }
+
+    /// Index of the last line anchored at or before the given frame. Lines without anchor are skipped.
+    pub fn line_index_at(&self, frame_offset: usize) -> Option<usize> {
+        let mut closest_match: Option<(usize, usize)>=None;
+
+        for (index, line) in self.lines.iter().enumerate() {
+            if let Some(anchor)=line.frame_offset {
+                if anchor>frame_offset {
+                    continue;
+                    }
+
+                //Later lines win ties, as they were written after the earlier ones
+                if let Some((_, closest_anchor))=closest_match {
+                    if anchor<closest_anchor {
+                        continue;
+                        }
+                    }
+
+                closest_match=Some((index, anchor));
+                }
+            }
+
+        closest_match.map(|(index, _)| index)
+        }
+    }
+
+/// A document with all of its audio in memory, used to build documents out of others, e.g. by merging or splitting them.
+#[derive(Clone)]
+pub struct SdamFileModel {
+    audio: Vec<Vec<u8>>,
+    metadata: storage::Metadata,
+    }
+impl SdamFileModel {
+
+    pub fn new(audio: Vec<Vec<u8>>, marks: MarkManager, notes: Notes) -> SdamFileModel {
+        SdamFileModel::with_metadata(audio, storage::Metadata::new(marks, notes))
+        }
+    pub(crate) fn with_metadata(audio: Vec<Vec<u8>>, metadata: storage::Metadata) -> SdamFileModel {
+        SdamFileModel {
+            audio,
+            metadata,
+            }
+        }
+
+    /// Reads a document with all of its audio into memory.
+    pub fn read(path: &Path) -> Result<SdamFileModel, anyhow::Error> {
+        match storage::open(path)? {
+            storage::StoredDocument::Legacy(model) => Ok(model),
+            storage::StoredDocument::Indexed {metadata, frames} => {
+                let audio: Vec<Vec<u8>>=(0..frames.len())
+                .map(|id| frames.frame_data(id).to_vec())
+                .collect();
+
+                Ok(SdamFileModel::with_metadata(audio, metadata))
+                },
+            }
+        }
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        storage::write(path, &self.metadata, self.audio.len(), |id| &self.audio[id][..])
+        }
+
+    /// Appends another document to the end of this one. Its marks are renumbered after the existing ones, its notes are separated by a line with the given separator.
+    pub fn append(&mut self, other: SdamFileModel, separator: &str) {
+        let frame_offset=self.audio.len();
+        let (audio, mut metadata)=other.into_stored();
+        let other_marks=std::mem::replace(&mut metadata.marks, MarkManager::new());
+        let other_notes=std::mem::replace(&mut metadata.notes, Notes::new());
+
+        for mark in other_marks.rebased(frame_offset, self.metadata.marks.get_available_id()) {
+            let _=self.metadata.marks.insert(mark);
+            }
+
+        let notes=&mut self.metadata.notes;
+        if !notes.lines().is_empty() && !other_notes.lines().is_empty() {
+            notes.lines.push(NoteLine::new(separator.to_string(), Some(frame_offset)));
+            }
+        notes.append(&other_notes, frame_offset);
+
+        self.metadata.append(metadata, frame_offset);
+        self.audio.extend(audio);
+        }
+
+    /// Splits the document at the given frames. Each part keeps the marks, notes, transcript and other data of its range, rebased to its start.
+    pub fn split(&self, frames: &[usize]) -> Vec<SdamFileModel> {
+        let mut frames: Vec<usize>=frames.iter()
+        .filter(|frame| **frame>0 && **frame<self.audio.len())
+        .cloned()
+        .collect();
+        frames.sort();
+        frames.dedup();
+
+        let notes=self.metadata.notes.split(&frames);
+
+        let mut boundaries=vec![0];
+        boundaries.extend(&frames);
+        boundaries.push(self.audio.len());
+
+        boundaries.windows(2).zip(notes)
+        .map(|(range, notes)| {
+            let mut metadata=self.metadata.clone();
+            metadata.clip(range[0], range[1], self.audio.len());
+            metadata.marks=self.metadata.marks.clipped(range[0], range[1]);
+            metadata.notes=notes;
+
+            SdamFileModel::with_metadata(self.audio[range[0]..range[1]].to_vec(), metadata)
+            })
+        .collect()
+        }
+
+    pub fn audio(&self) -> &Vec<Vec<u8>> {
+        &self.audio
+        }
+    pub fn marks(&self) -> &MarkManager {
+        &self.metadata.marks
+        }
+    pub fn notes(&self) -> Notes {
+        self.metadata.notes.clone()
+        }
+    pub fn into_parts(self) -> (Vec<Vec<u8>>, MarkManager, Notes) {
+        (self.audio, self.metadata.marks, self.metadata.notes)
+        }
+    pub(crate) fn into_stored(self) -> (Vec<Vec<u8>>, storage::Metadata) {
+        (self.audio, self.metadata)
+        }
+    }
+
+#[derive(Clone, Copy, Debug)]
+pub enum ClipSource {
+    /// The frame of the mark, the clip contains it even without padding.
+    Mark(u64),
+    /// Start (inclusive) and end (exclusive) frame.
+    Frames(usize, usize),
+    }
+
+#[derive(Clone, Copy, Debug)]
+pub enum ClipFormat {
+    Wav,
+    Sdam,
+    }
+
+#[derive(Clone, Copy, Debug)]
+pub enum LoopRegion {
+    /// Start (inclusive) and end (exclusive) frame.
+    Frames(usize, usize),
+    /// The region between two marks, given in any order.
+    Marks(u64, u64),
+    }
+
+/// A region of the audio played repeatedly.
+#[derive(Clone, Debug, Getters)]
+pub struct PlaybackLoop {
+    region: LoopRegion,
+    /// How many times the region is played before the playback continues past it. Without a count, it's repeated until the loop is cleared.
+    repeat_count: Option<usize>,
+    /// Factor the playback rate is multiplied by with every repetition, e.g. 0.9 to play each repetition 10 % slower than the previous one.
+    slowdown: Option<f64>,
+    }
+impl PlaybackLoop {
+
+    pub fn new(region: LoopRegion) -> PlaybackLoop {
+        PlaybackLoop {
+            region,
+            repeat_count: None,
+            slowdown: None,
+            }
+        }
+
+    pub fn with_repeat_count(self, repeat_count: usize) -> PlaybackLoop {
+        PlaybackLoop {
+            repeat_count: Some(repeat_count),
+            ..self
+            }
+        }
+    pub fn with_slowdown(self, slowdown: f64) -> PlaybackLoop {
+        PlaybackLoop {
+            slowdown: Some(slowdown),
+            ..self
+            }
+        }
+    }
+
+/// State of the loop being played.
+struct ActiveLoop {
+    start_frame: usize,
+    end_frame: usize,
+    remaining_repetitions: Option<usize>,
+    slowdown: Option<f64>,
+    rate_factor: f64,
+    }
+impl ActiveLoop {
+
+    fn new(start_frame: usize, end_frame: usize, repeat_count: Option<usize>, slowdown: Option<f64>) -> ActiveLoop {
+        ActiveLoop {
+            start_frame,
+            end_frame,
+            remaining_repetitions: repeat_count,
+            slowdown,
+            rate_factor: 1.0,
+            }
+        }
+
+    /// Returns the frame to be played after the given one, or None if the last repetition has just ended.
+    /// Only playback reaching the end of the region from inside wraps around, playback sought outside of the region proceeds normally.
+    fn next_frame(&mut self, frame: usize, audio_len: usize) -> Option<usize> {
+        //The audio might have been shortened by an edit
+        self.end_frame=std::cmp::min(self.end_frame, audio_len);
+
+        if self.start_frame>=self.end_frame {
+            return None;
+            }
+
+        if frame+1!=self.end_frame {
+            return Some(frame+1);
+            }
+
+        if let Some(remaining_repetitions)=self.remaining_repetitions {
+            if remaining_repetitions<=1 {
+                return None;
+                }
+
+            self.remaining_repetitions=Some(remaining_repetitions-1);
+            }
+
+        if let Some(slowdown)=self.slowdown {
+            self.rate_factor=f64::max(self.rate_factor*slowdown, MINIMUM_LOOP_RATE);
+            }
+
+        Some(self.start_frame)
+        }
+    }
+
+#[derive(Clone, Debug)]
+pub enum AudioEdit {
+    /// Removes the frames from start (inclusive) to end (exclusive).
+    Delete {start_frame: usize, end_frame: usize},
+    /// Removes everything before the given frame.
+    TrimStart(usize),
+    /// Removes everything from the given frame on.
+    TrimEnd(usize),
+    InsertDocument {path: PathBuf, frame: usize},
+    }
+
+/// Notifications about the state of the document, delivered to subscribers.
+#[derive(Clone, Debug)]
+pub enum SdamEvent {
+    /// The document was changed, either by a mark or text operation, by undo / redo, or by new recorded audio, which is announced at most once a second.
+    DocumentChanged {change_count: u64},
+    Loaded {path: PathBuf},
+    Saved {path: PathBuf},
+    /// The time travel playback has caught up with the recording.
+    RejoinedLive,
+    /// The transcription engine failed and was turned off.
+    TranscriptionFailed {message: String},
+    /// The speaker detection finished, finding the given number of speakers.
+    SpeakersDetected {speaker_count: usize},
+    SpeakerDetectionFailed {message: String},
+    /// The analysis for mark suggestions, left for the completion of the envelope, finished with the given number of pending suggestions.
+    MarksSuggested {suggestion_count: usize},
+    }
+
+/// Opens a document for use in the audio handler. Audio of indexed documents stays mapped from the file.
+fn open_document(path: &Path) -> Result<(AudioContainer, storage::Metadata), anyhow::Error> {
+    match storage::open(path)? {
+        storage::StoredDocument::Legacy(model) => {
+            let (audio, metadata)=model.into_stored();
+
+            Ok((AudioContainer::from_vec(audio), metadata))
+            },
+        storage::StoredDocument::Indexed {metadata, frames} => Ok((AudioContainer::from_mapped(frames), metadata)),
+        }
+    }
+
+/// Joins the given documents in order into a new document written to the output path.
+pub fn merge_documents(paths: &[&str], output_path: &str) -> Result<(), anyhow::Error> {
+    if paths.len()<2 {
+        anyhow::bail!("At least two documents are needed for merging");
+        }
+
+    let mut merged=SdamFileModel::read(Path::new(paths[0]))?;
+
+    for path in &paths[1..] {
+        let path=Path::new(path);
+        let model=SdamFileModel::read(path)?;
+        let separator=format!("--- {} ---", path.file_stem().unwrap_or_default().to_string_lossy());
+
+        merged.append(model, &separator);
+        }
+
+    merged.write(Path::new(output_path))
+    }
+
+/// Splits the document at the given points into files named after it with the part number, written to the output directory. Returns paths of the written files.
+pub fn split_document(path: &str, points: &[SplitPoint], output_directory: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let path=Path::new(path);
+    let model=SdamFileModel::read(path)?;
+    let stem=path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+
+    write_split_parts(&model, points, Path::new(output_directory), &stem)
+    }
+fn write_split_parts(model: &SdamFileModel, points: &[SplitPoint], output_directory: &Path, stem: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut frames: Vec<usize>=Vec::new();
+    for point in points {
+        frames.push(match point {
+            SplitPoint::Mark(id) => *model.marks().get(*id)?.frame_offset(),
+            SplitPoint::Frame(frame) => *frame,
+            });
+        }
+
+    let parts=model.split(&frames);
+    let mut paths: Vec<PathBuf>=Vec::new();
+
+    for (index, part) in parts.iter().enumerate() {
+        let part_path=output_directory.join(format!("{stem}_{}.sdam", index+1));
+        part.write(&part_path)?;
+
+        paths.push(part_path);
+        }
+
+    Ok(paths)
+    }
+
+#[derive(Clone, Copy, Debug)]
+pub enum SplitPoint {
+    Mark(u64),
+    Frame(usize),
+    }
+
+/// A reversible change of the document, recorded in the edit history.
+#[derive(Clone, Debug)]
+pub enum EditCommand {
+    AddMark(Mark),
+    EditMark {previous: Mark, updated: Mark},
+    DeleteMark(Mark),
+    SetUserText {previous: Notes, updated: Notes},
+    /// Removal of frames, holding them together with the removed marks, so they can be put back.
+    RemoveAudio {start_frame: usize, frames: AudioContainer, marks: Vec<Mark>, previous_notes: Notes, updated_notes: Notes},
+    /// Insertion of frames, together with marks placed in them.
+    InsertAudio {start_frame: usize, frames: AudioContainer, marks: Vec<Mark>, previous_notes: Notes, updated_notes: Notes},
+    }
+impl EditCommand {
+
+    pub fn description(&self) -> String {
+        match self {
+            EditCommand::AddMark(mark) => format!("Add {}", Self::mark_name(mark)),
+            EditCommand::EditMark {previous, ..} => format!("Edit {}", Self::mark_name(previous)),
+            EditCommand::DeleteMark(mark) => format!("Delete {}", Self::mark_name(mark)),
+            EditCommand::SetUserText {..} => "Edit text".to_string(),
+            EditCommand::RemoveAudio {start_frame, frames, ..} => format!("Delete audio {} - {}", frame_offset_to_time(*start_frame), frame_offset_to_time(start_frame+frames.len())),
+            EditCommand::InsertAudio {start_frame, ..} => format!("Insert audio at {}", frame_offset_to_time(*start_frame)),
+            }
+        }
+
+    fn apply(&self, audio: &mut AudioContainer, mark_manager: &mut MarkManager, notes: &mut Notes) {
+        match self {
+            EditCommand::AddMark(mark) => {
+                let _=mark_manager.insert(mark.clone());
+                },
+            EditCommand::EditMark {previous, updated} => {
+                if let Some(id)=previous.id() {
+                    let _=mark_manager.edit(*id, updated.clone());
+                    }
+                },
+            EditCommand::DeleteMark(mark) => {
+                if let Some(id)=mark.id() {
+                    mark_manager.remove(*id);
+                    }
+                },
+            EditCommand::SetUserText {updated, ..} => {
+                *notes=updated.clone();
+                },
+            EditCommand::RemoveAudio {start_frame, frames, updated_notes, ..} => {
+                audio.remove_range(*start_frame, start_frame+frames.len());
+                mark_manager.remove_range(*start_frame, start_frame+frames.len());
+                *notes=updated_notes.clone();
+                },
+            EditCommand::InsertAudio {start_frame, frames, marks, updated_notes, ..} => {
+                audio.insert(*start_frame, frames.clone());
+                mark_manager.open_range(*start_frame, frames.len());
+                for mark in marks {
+                    let _=mark_manager.insert(mark.clone());
+                    }
+                *notes=updated_notes.clone();
+                },
+            }
+        }
+    fn revert(&self, audio: &mut AudioContainer, mark_manager: &mut MarkManager, notes: &mut Notes) {
+        match self {
+            EditCommand::AddMark(mark) => {
+                if let Some(id)=mark.id() {
+                    mark_manager.remove(*id);
+                    }
+                },
+            EditCommand::EditMark {previous, ..} => {
+                if let Some(id)=previous.id() {
+                    let _=mark_manager.edit(*id, previous.clone());
+                    }
+                },
+            EditCommand::DeleteMark(mark) => {
+                let _=mark_manager.insert(mark.clone());
+                },
+            EditCommand::SetUserText {previous, ..} => {
+                *notes=previous.clone();
+                },
+            EditCommand::RemoveAudio {start_frame, frames, marks, previous_notes, ..} => {
+                audio.insert(*start_frame, frames.clone());
+                mark_manager.open_range(*start_frame, frames.len());
+                for mark in marks {
+                    let _=mark_manager.insert(mark.clone());
+                    }
+                *notes=previous_notes.clone();
+                },
+            EditCommand::InsertAudio {start_frame, frames, previous_notes, ..} => {
+                audio.remove_range(*start_frame, start_frame+frames.len());
+                mark_manager.remove_range(*start_frame, start_frame+frames.len());
+                *notes=previous_notes.clone();
+                },
+            }
+        }
+
+    fn mark_name(mark: &Mark) -> String {
+        match mark.label() {
+            Some(label) => format!("mark {label}"),
+            None => "mark".to_string(),
+            }
+        }
+    }
+
+/// Undo and redo stacks of executed edit commands.
+#[derive(Default)]
+pub struct EditHistory {
+    undo_stack: Vec<EditCommand>,
+    redo_stack: Vec<EditCommand>,
+    /// When the text was last edited, if that's the most recent command.
+    last_text_edit: Option<Instant>,
+    }
+impl EditHistory {
+
+    pub fn new() -> EditHistory {
+        EditHistory {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_text_edit: None,
+            }
+        }
+
+    /// Records an already executed command. Any undone commands are discarded, since they can no longer be redone on top of the new state.
+    pub fn push(&mut self, command: EditCommand) {
+        self.last_text_edit=None;
+        self.redo_stack.clear();
+        self.undo_stack.push(command);
+
+        if self.undo_stack.len()>EDIT_HISTORY_LIMIT {
+            self.undo_stack.remove(0);
+            }
+        }
+    /// Records an already executed edit of the text. Edits coming shortly after the previous one extend it instead, so typing doesn't produce a command per keystroke.
+    pub fn push_text_edit(&mut self, previous: Notes, updated: Notes, time: Instant) {
+        let merge=self.last_text_edit.is_some_and(|last_text_edit| time.saturating_duration_since(last_text_edit)<=TEXT_EDIT_MERGE_INTERVAL);
+
+        match self.undo_stack.last_mut() {
+            Some(EditCommand::SetUserText {updated: last_updated, ..}) if merge => *last_updated=updated,
+            _ => self.push(EditCommand::SetUserText {previous, updated}),
+            }
+
+        self.last_text_edit=Some(time);
+        }
+    /// Moves the most recent command to the redo stack and returns it, so the caller can revert it.
+    pub fn undo(&mut self) -> Option<EditCommand> {
+        self.last_text_edit=None;
+        let command=self.undo_stack.pop()?;
+        self.redo_stack.push(command.clone());
+
+        Some(command)
+        }
+    /// Moves the most recently undone command back to the undo stack and returns it, so the caller can apply it again.
+    pub fn redo(&mut self) -> Option<EditCommand> {
+        self.last_text_edit=None;
+        let command=self.redo_stack.pop()?;
+        self.undo_stack.push(command.clone());
+
+        Some(command)
+        }
+    pub fn clear(&mut self) {
+        self.last_text_edit=None;
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        }
+
+    /// Descriptions of the undoable commands, the most recent first.
+    pub fn undo_descriptions(&self) -> Vec<String> {
+        self.undo_stack.iter().rev()
+        .map(|command| command.description())
+        .collect()
+        }
+    /// Descriptions of the redoable commands, the next to redo first.
+    pub fn redo_descriptions(&self) -> Vec<String> {
+        self.redo_stack.iter().rev()
+        .map(|command| command.description())
+        .collect()
+        }
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct StartPlayback {}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct PausePlayback {}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct TogglePlayback {}
+
+#[derive(Message)]
+#[rtype(result="()")]
 pub enum Seek {
     Absolute(usize),
+    Time(Duration),
     Relative(i32),
     Percentual(usize),
     ToStart,
@@ -498,12 +1749,114 @@ pub struct GetCurrentPosition {
     result_sender: mpsc::Sender<Option<usize>>,
     }
 
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetCurrentPositionMillis {
+    result_sender: mpsc::Sender<Option<u64>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetIntegratedLoudness {
+    result_sender: mpsc::Sender<Option<f32>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetLoudnessNormalization {
+    result_sender: mpsc::Sender<Option<f32>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetTranscript {
+    start_frame: usize,
+    end_frame: usize,
+    result_sender: mpsc::Sender<Vec<TranscriptSegment>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct Search {
+    query: String,
+    options: SearchOptions,
+    result_sender: mpsc::Sender<Vec<SearchHit>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetIsTranscribing {
+    result_sender: mpsc::Sender<bool>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetSpeakerSegments {
+    result_sender: mpsc::Sender<Vec<SpeakerSegment>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetSpeakerAt {
+    frame: usize,
+    result_sender: mpsc::Sender<Option<SpeakerSegment>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetNextQuestion {
+    frame: usize,
+    result_sender: mpsc::Sender<Option<SpeakerSegment>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetIsDetectingSpeakers {
+    result_sender: mpsc::Sender<bool>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetNoiseSuppression {
+    result_sender: mpsc::Sender<bool>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetIsMonitoringLive {
+    result_sender: mpsc::Sender<bool>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetIsTimeTravelling {
+    result_sender: mpsc::Sender<bool>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetLiveLag {
+    result_sender: mpsc::Sender<Option<Duration>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetLoopRegion {
+    result_sender: mpsc::Sender<Option<(usize, usize)>>,
+    }
+
 #[derive(Message)]
 #[rtype(result="()")]
 pub struct GetIsPlaying {
     result_sender: mpsc::Sender<bool>,
     }
 
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetRate {
+    result_sender: mpsc::Sender<f64>,
+    }
+
 #[derive(Message)]
 #[rtype(result="()")]
 pub struct GetIsPaused {
@@ -516,6 +1869,24 @@ pub struct GetIsRecording {
     result_sender: mpsc::Sender<bool>,
     }
 
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetIsModified {
+    result_sender: mpsc::Sender<bool>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetChangeCount {
+    result_sender: mpsc::Sender<u64>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct Subscribe {
+    event_sender: mpsc::Sender<SdamEvent>,
+    }
+
 #[derive(Message)]
 #[rtype(result="()")]
 pub struct GetMark {
@@ -531,61 +1902,232 @@ pub struct GetMarks {
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct GetNextClosestMark {
-    frame: usize,
-    result_sender: mpsc::Sender<Option<Mark>>,
-    }
+pub struct GetNextClosestMark {
+    frame: usize,
+    result_sender: mpsc::Sender<Option<Mark>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetPreviousClosestMark {
+    frame: usize,
+    result_sender: mpsc::Sender<Option<Mark>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetUserText {
+    result_sender: mpsc::Sender<String>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetNoteLines {
+    result_sender: mpsc::Sender<Vec<NoteLine>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetNoteLineIndexAt {
+    frame: usize,
+    result_sender: mpsc::Sender<Option<usize>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct GetEnvelope {
+    start_frame: usize,
+    end_frame: usize,
+    points: usize,
+    result_sender: mpsc::Sender<Vec<EnvelopePoint>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct AddMark {
+    mark: Mark,
+    result_sender: mpsc::Sender<Mark>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SuggestMarks {
+    result_sender: mpsc::Sender<Vec<MarkSuggestion>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct AcceptSuggestion {
+    id: u64,
+    category: usize,
+    result_sender: mpsc::Sender<Result<Mark, anyhow::Error>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct DismissSuggestion {
+    id: u64,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct EditMark {id: u64, updated_mark: Mark}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct DeleteMark {id: u64}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetRate { rate: f64 }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetLoop {
+    playback_loop: PlaybackLoop,
+    result_sender: mpsc::Sender<Result<(), anyhow::Error>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct ClearLoop {}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetPlaybackGain {
+    gain: f32,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetCompressor {
+    settings: Option<CompressorSettings>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetLoudnessNormalization {
+    target: Option<f32>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetTranscriptionEngine {
+    engine: Option<Box<dyn TranscriptionEngine>>,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct ClearTranscript {}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct DetectSpeakers {}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetNoiseSuppression {
+    enabled: bool,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetMonitoring {
+    enabled: bool,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetMonitoringGain {
+    gain: f32,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct StartTimeTravel {
+    options: TimeTravelOptions,
+    }
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct StopTimeTravel {}
+
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct SetUserText { text: String }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct GetPreviousClosestMark {
-    frame: usize,
-    result_sender: mpsc::Sender<Option<Mark>>,
+pub struct Undo {
+    result_sender: mpsc::Sender<Option<String>>,
     }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct GetUserText {
-    result_sender: mpsc::Sender<String>,
+pub struct Redo {
+    result_sender: mpsc::Sender<Option<String>>,
     }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct AddMark {
-    mark: Mark,
-    result_sender: mpsc::Sender<Mark>,
+pub struct GetUndoHistory {
+    result_sender: mpsc::Sender<Vec<String>>,
     }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct EditMark {id: u64, updated_mark: Mark}
+pub struct GetRedoHistory {
+    result_sender: mpsc::Sender<Vec<String>>,
+    }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct DeleteMark {id: u64}
+pub struct Load {
+    path: PathBuf,
+    result_sender: mpsc::Sender<Result<(), anyhow::Error>>,
+    }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct SetRate { rate: f64 }
+pub struct Save {
+    path: Option<PathBuf>,
+    result_sender: mpsc::Sender<Result<(), anyhow::Error>>,
+    }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct SetUserText { text: String }
+pub struct ExportStudySheet {
+    path: PathBuf,
+    options: StudySheetOptions,
+    result_sender: mpsc::Sender<Result<(), anyhow::Error>>,
+    }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct Load {
+pub struct ExportClip {
     path: PathBuf,
+    source: ClipSource,
+    seconds_before: usize,
+    seconds_after: usize,
+    format: ClipFormat,
     result_sender: mpsc::Sender<Result<(), anyhow::Error>>,
     }
 
 #[derive(Message)]
 #[rtype(result="()")]
-pub struct Save {
-    path: Option<PathBuf>,
+pub struct EditAudio {
+    edit: AudioEdit,
     result_sender: mpsc::Sender<Result<(), anyhow::Error>>,
     }
 
+#[derive(Message)]
+#[rtype(result="()")]
+pub struct Split {
+    points: Vec<SplitPoint>,
+    output_directory: PathBuf,
+    result_sender: mpsc::Sender<Result<Vec<PathBuf>, anyhow::Error>>,
+    }
+
 #[derive(Message)]
 #[rtype(result="()")]
 pub struct Quit {}
@@ -600,16 +2142,45 @@ pub struct AudioHandler {
     decoding_buffer: Vec<i16>,
     current_position: Option<usize>,
     future_position: Option<usize>,
+    seek_start: Option<usize>,
+    active_loop: Option<ActiveLoop>,
+    time_travel: Option<TimeTravel>,
     rate: f64,
     _host: cpal::Host,
-    _device: cpal::Device,
+    _device: Option<cpal::Device>,
     _stream_config: StreamConfig,
-    _output_stream: cpal::Stream,
+    /// Missing without an output device, e.g. on servers, where the document can still be worked with.
+    _output_stream: Option<cpal::Stream>,
     audio_producer: ringbuf::HeapProducer<i16>,
     decoder: Decoder,
     playback_state: PlaybackState,
+    playback_clock: PlaybackClock,
+    playback_processor: PlaybackProcessor,
+    loudness_target: Option<f32>,
+    noise_suppression: bool,
+    monitor: Arc<Monitor>,
+    monitoring: bool,
     mark_manager: MarkManager,
-    user_text: String,
+    notes: Notes,
+    envelope: Envelope,
+    /// Revision of the audio the levels being computed in the background are for.
+    envelope_job: Option<u64>,
+    transcript: Transcript,
+    transcriber: Option<Transcriber>,
+    /// Range of frames being transcribed at the moment.
+    transcription_job: Option<(usize, usize)>,
+    suggestions: MarkSuggestions,
+    speakers: Diarization,
+    /// Revision of the audio the running speaker detection was started for.
+    speaker_detection: Option<u64>,
+    /// Whether the speaker detection waits for the envelope to be completed before it starts.
+    speaker_detection_waiting: bool,
+    /// Whether the analysis for mark suggestions waits for the envelope to be completed.
+    suggestions_waiting: bool,
+    /// Counter of changes of the audio other than recording, invalidating transcription jobs created before them.
+    audio_revision: u64,
+    edit_history: EditHistory,
+    changes: ChangeTracker,
     }
 impl AudioHandler {
 
@@ -618,10 +2189,11 @@ impl AudioHandler {
             let self_addr=ctx.address();
 
             let audio=AudioContainer::new();
-            let recorder=Recorder::new(ctx.address().recipient());
+            let (monitor, monitor_input, monitor_output)=monitor::monitor_path();
+            let recorder=Recorder::new(ctx.address().recipient(), monitor_input);
 
             let host=cpal::default_host();
-            let device=host.default_output_device().unwrap();
+            let device=host.default_output_device();
             let config=StreamConfig {
                 buffer_size: BufferSize::Fixed(FRAME_SIZE as u32),
                 channels: 1,
@@ -629,7 +2201,8 @@ impl AudioHandler {
                 };
             let decoder=Decoder::new(SAMPLING_RATE, opus::Channels::Mono).unwrap();
 
-            let (output_stream, audio_producer, playback_state)=Self::initialize_playback(&device, &config);
+            let playback_clock=PlaybackClock::new();
+            let (output_stream, audio_producer, playback_state)=Self::initialize_playback(device.as_ref(), &config, playback_clock.consumed_samples(), monitor_output);
 
             AudioHandler {
                 self_addr,
@@ -641,6 +2214,9 @@ impl AudioHandler {
                 decoding_buffer: vec![0_i16; 2*FRAME_SIZE],
                 current_position: None,
                 future_position: None,
+                seek_start: None,
+                active_loop: None,
+                time_travel: None,
                 rate: 1.0,
                 _host: host,
                 _device: device,
@@ -649,12 +2225,31 @@ impl AudioHandler {
                 audio_producer,
                 decoder,
                 playback_state,
+                playback_clock,
+                playback_processor: PlaybackProcessor::new(),
+                loudness_target: None,
+                noise_suppression: false,
+                monitor,
+                monitoring: false,
                 mark_manager: MarkManager::new(),
-                user_text: String::new(),
+                notes: Notes::new(),
+                envelope: Envelope::new(),
+                envelope_job: None,
+                transcript: Transcript::new(),
+                transcriber: None,
+                transcription_job: None,
+                suggestions: MarkSuggestions::new(),
+                speakers: Diarization::new(),
+                speaker_detection: None,
+                speaker_detection_waiting: false,
+                suggestions_waiting: false,
+                audio_revision: 0,
+                edit_history: EditHistory::new(),
+                changes: ChangeTracker::new(),
                 }
             })
         }
-    fn initialize_playback(device: &cpal::Device, config: &StreamConfig) -> (cpal::Stream, ringbuf::HeapProducer<i16>, PlaybackState) {
+    fn initialize_playback(device: Option<&cpal::Device>, config: &StreamConfig, consumed_samples: Arc<AtomicU64>, mut monitor_output: MonitorOutput) -> (Option<cpal::Stream>, ringbuf::HeapProducer<i16>, PlaybackState) {
         let ringbuf=HeapRb::<i16>::new(20*FRAME_SIZE);
         let (audio_producer, mut audio_consumer)=ringbuf.split();
 
@@ -663,18 +2258,25 @@ impl AudioHandler {
 
             if available_samples>=data.len() {
                 audio_consumer.pop_slice(data);
+                consumed_samples.fetch_add(data.len() as u64, Ordering::Relaxed);
                 }
             else {
                 audio_consumer.pop_slice(&mut data[..available_samples]);
+                consumed_samples.fetch_add(available_samples as u64, Ordering::Relaxed);
 
                 let remaining_samples=data.len()-available_samples;
 
                 (&mut data[available_samples..]).copy_from_slice(&[0_i16; 5000][..remaining_samples]);
                 }
+
+            //While monitoring, the played samples are only consumed to keep the playback going, the input is heard instead
+            monitor_output.play_into(data);
             };
 
-        let output_stream=device.build_output_stream(config, output_fn, Self::stream_err_fn, None).unwrap();
-        output_stream.play().unwrap();
+        let output_stream=device.and_then(|device| device.build_output_stream(config, output_fn, Self::stream_err_fn, None).ok());
+        if let Some(output_stream)=&output_stream {
+            output_stream.play().unwrap();
+            }
 
         (output_stream,
         audio_producer,
@@ -682,106 +2284,384 @@ impl AudioHandler {
         }
 
     fn active_rate(&self) -> f64 {
-        if self.rate==1.0 {
+        let mut rate=match &self.active_loop {
+            Some(active_loop) => self.rate*active_loop.rate_factor,
+            None => self.rate,
+            };
+
+        if let Some(time_travel)=&self.time_travel {
+            rate*=time_travel.catch_up_rate();
+            }
+
+        if rate==1.0 {
             return 1.0;
             }
 
         if let Some(current_position)=&self.current_position {
-            if self.audio.len()-current_position<=5 {
+            if self.audio.len().saturating_sub(self.live_latency_frames()+*current_position)<=5 {
                 return 1.0;
                 }
             }
 
-        self.rate
+        rate
+        }
+    fn live_latency_frames(&self) -> usize {
+        match &self.time_travel {
+            Some(time_travel) => time_travel.latency_frames(),
+            None => 0,
+            }
+        }
+    /// Returns the frame if it can be played. While time travelling, frames closer to the end of the audio than the live latency are held back.
+    fn playable_frame(&self, id: usize) -> Option<Arc<OpusFrame>> {
+        if self.time_travel.is_some() && id+self.live_latency_frames()>=self.audio.len() {
+            return None;
+            }
+
+        self.audio.get_frame(id)
+        }
+    /// How far the heard position is behind the end of the audio while time travelling.
+    fn live_lag_millis(&mut self) -> Option<f64> {
+        self.time_travel.as_ref()?;
+
+        let position=self.heard_position_millis().unwrap_or(0.0);
+
+        Some(f64::max((self.audio.len()*FRAME_DURATION) as f64-position, 0.0))
+        }
+    /// The frame to be played after the given one, wrapping around the active loop and skipping pauses while catching up with the recording.
+    fn next_frame(&mut self, frame: usize) -> usize {
+        if let Some(active_loop)=&mut self.active_loop {
+            match active_loop.next_frame(frame, self.audio.len()) {
+                Some(next_frame) => return next_frame,
+                None => self.active_loop=None,
+                }
+            }
+
+        let mut next_frame=frame+1;
+
+        if let Some(time_travel)=&mut self.time_travel {
+            while next_frame<self.audio.len() && time_travel.skips(self.envelope.is_silent(next_frame), self.audio.len()-next_frame) {
+                next_frame+=1;
+                }
+
+            time_travel.update_rate(self.audio.len().saturating_sub(next_frame));
+            }
+
+        next_frame
         }
 
-    fn decode_into_producer(&mut self, frame: &Arc<OpusFrame>, active_rate: f64) {
+    /// Decodes the frame and pushes its samples from the given one on into the output buffer.
+    fn decode_into_producer(&mut self, id: usize, frame: &Arc<OpusFrame>, active_rate: f64, start_sample: usize) {
         let decoded_samples=self.decoder.decode(frame.data(), &mut self.decoding_buffer, false).unwrap();
+        let start_sample=std::cmp::min(start_sample, decoded_samples);
+        self.playback_processor.process(&mut self.decoding_buffer[start_sample..decoded_samples]);
+        let samples=&self.decoding_buffer[start_sample..decoded_samples];
+        let mut pushed_samples=0;
+
         if active_rate==1.0 {
-            self.audio_producer.push_slice(&self.decoding_buffer[..decoded_samples]);
+            pushed_samples+=self.audio_producer.push_slice(samples);
             }
         else if active_rate>1.0 {
-            let chunk=&self.decoding_buffer[..(decoded_samples as f64/active_rate) as usize];
-            self.audio_producer.push_slice(chunk);
+            let chunk=&samples[..(samples.len() as f64/active_rate) as usize];
+            pushed_samples+=self.audio_producer.push_slice(chunk);
             }
         else {
             let recip_rate=active_rate.recip();
 
             for _ in 0..recip_rate.trunc() as usize {
-                self.audio_producer.push_slice(&self.decoding_buffer[..decoded_samples]);
+                pushed_samples+=self.audio_producer.push_slice(samples);
                 }
 
             if recip_rate.fract()!=0.0 {
-                self.audio_producer.push_slice(&self.decoding_buffer[..(decoded_samples as f64*recip_rate.fract()) as usize]);
+                pushed_samples+=self.audio_producer.push_slice(&samples[..(samples.len() as f64*recip_rate.fract()) as usize]);
                 }
             }
+
+        let start_millis=(id*FRAME_DURATION) as f64+start_sample as f64*1000.0/SAMPLING_RATE as f64;
+        let end_millis=(id*FRAME_DURATION) as f64+decoded_samples as f64*1000.0/SAMPLING_RATE as f64;
+        self.playback_clock.push(start_millis, end_millis-start_millis, pushed_samples);
+        }
+    fn start_recording(&mut self) {
+        self.recorder.do_send(StartRecording {});
+        self.recording=true;
+        self.update_monitoring();
+        }
+    fn stop_recording(&mut self) {
+        self.recorder.do_send(StopRecording {});
+        self.recording=false;
+        self.update_monitoring();
+
+        //The last recorded frames may not have been announced yet
+        self.changes.flush_recording();
         }
     fn start_playback(&mut self) {
         if let PlaybackState::Paused=self.playback_state {
+            self.update_normalization();
             self.playback_state=PlaybackState::Playing;
             self.self_addr.do_send(UpdateAudioBuffer {});
             }
+
+        self.update_monitoring();
         }
     fn pause_playback(&mut self) {
         if let PlaybackState::Playing=self.playback_state {
             self.playback_state=PlaybackState::Paused;
             }
+
+        self.update_monitoring();
+        }
+    /// Measures the loudness of the audio and adjusts the playback to reach the normalization target.
+    /// Until the levels of the audio are computed in the background, the loudness is measured over the computed part, the normalization is updated again once they're complete.
+    fn update_normalization(&mut self) {
+        if self.loudness_target.is_some() {
+            self.complete_envelope();
+            }
+
+        self.playback_processor.set_normalization(self.envelope.integrated_loudness(), self.loudness_target);
+        }
+    /// Switches between hearing the input directly and hearing the recorded audio. The input is heard while recording without playback, or while time travelling at the live edge.
+    fn update_monitoring(&mut self) {
+        let playing=matches!(self.playback_state, PlaybackState::Playing);
+
+        let live=match &self.time_travel {
+            Some(time_travel) => playing && time_travel.is_live(),
+            None => !playing,
+            };
+
+        self.monitor.set_active(self.monitoring && self.recording && live);
         }
     fn seek(&mut self, seek: Seek) {
-        if self.audio.len()<3 {
+        let latency_frames=match &self.time_travel {
+            Some(time_travel) => std::cmp::max(time_travel.latency_frames(), 1),
+            None => 3,
+            };
+
+        if self.audio.len()<latency_frames {
             return;
             }
 
-        let end_frame=self.audio.len()-3; //Some offset is applied here to introduce latency for situations where recording is performed in parallel to playback
-
-        let frame=match seek {
-            Seek::Absolute(mut frame) => {
-                if frame>end_frame {
-                    frame=end_frame;
-                    }
+        let end_frame=self.audio.len()-latency_frames; //Some offset is applied here to introduce latency for situations where recording is performed in parallel to playback
 
-                frame
+        let position_millis=match seek {
+            Seek::Absolute(frame) => {
+                (frame*FRAME_DURATION) as f64
+                },
+            Seek::Time(time) => {
+                time.as_secs_f64()*1000.0
                 },
             Seek::Relative(delta_millis) => {
-                let base=if let Some(current_position)=self.current_position {
-                    current_position as i32
-                    }
-                else {
-                    0
-                    };
-
-                let mut frame=std::cmp::max(0, base+delta_millis/(FRAME_DURATION as i32)) as usize;
-
-                if frame>end_frame {
-                    frame=end_frame;
-                    }
+                let base=self.heard_position_millis().unwrap_or(0.0);
 
-                frame
+                f64::max(0.0, base+delta_millis as f64)
                 },
             Seek::Percentual(percent) => {
-                let mut frame=(self.audio.len() as f64*(percent as f64)/100.0) as usize;
+                let frame=(self.audio.len() as f64*(percent as f64)/100.0) as usize;
 
-                if frame>end_frame {
-                    frame=end_frame;
-                    }
-
-                frame
+                (frame*FRAME_DURATION) as f64
                 },
             Seek::ToStart => {
-                0
+                0.0
                 },
             Seek::ToEnd => {
-                end_frame
+                (end_frame*FRAME_DURATION) as f64
                 },
             };
+        let position_millis=position_millis.min((end_frame*FRAME_DURATION) as f64);
+
+        let frame=position_millis as usize/FRAME_DURATION;
+        let start_sample=((position_millis-(frame*FRAME_DURATION) as f64)*SAMPLING_RATE as f64/1000.0) as usize;
 
         self.current_position=Some(frame);
-        self.future_position=Some(frame+1);
+        self.future_position=None;
+        self.seek_start=Some(start_sample);
+        self.playback_clock.seek(position_millis);
+        self.playback_processor.reset();
+
+        if let Some(time_travel)=&mut self.time_travel {
+            time_travel.seek(self.audio.len()-frame);
+            }
+        self.update_monitoring();
 
         //We don't perform loading the audio data into the output buffer here.
         // The reason is if the user kept seeking rapidly, data would pile up in the buffer and weird things would happen, especially if the playback was paused at the moment, but even during the playback
-        // So instead, we just change the numbers and let the audio loop deal with it. We lose some precision that way (80ms), since the loop pwill consider those values aleady loaded in the buffer
-        // However this inprecision in theory shouldn't be noticeable
+        // So instead, we just change the numbers and let the audio loop deal with it. The loop starts with the sought frame, skipping the samples before the sought position, so playback can start in the middle of a frame
+        }
+
+    /// The position being heard in milliseconds. It trails the current position by the audio waiting in the output buffer.
+    fn heard_position_millis(&mut self) -> Option<f64> {
+        let current_position=self.current_position?;
+
+        let position=self.playback_clock.position_millis()
+        .unwrap_or((current_position*FRAME_DURATION) as f64);
+
+        Some(position.min((self.audio.len()*FRAME_DURATION) as f64))
+        }
+    fn heard_position(&mut self) -> Option<usize> {
+        let position=self.heard_position_millis()?;
+
+        Some(std::cmp::min(position as usize/FRAME_DURATION, self.audio.len().saturating_sub(1)))
+        }
+
+    /// The frame new notes are anchored to. While recording without playback, this is the live end of the audio, otherwise the playback position.
+    fn anchor_position(&mut self) -> Option<usize> {
+        if let PlaybackState::Paused=self.playback_state {
+            if self.recording {
+                return Some(self.audio.len());
+                }
+            }
+
+        self.heard_position()
+        }
+
+    fn audio_edit_command(&self, edit: AudioEdit) -> Result<EditCommand, anyhow::Error> {
+        let audio_len=self.audio.len();
+
+        let (start_frame, end_frame)=match edit {
+            AudioEdit::Delete {start_frame, end_frame} => (start_frame, std::cmp::min(end_frame, audio_len)),
+            AudioEdit::TrimStart(frame) => (0, std::cmp::min(frame, audio_len)),
+            AudioEdit::TrimEnd(frame) => (frame, audio_len),
+            AudioEdit::InsertDocument {path, frame} => {
+                if frame>audio_len {
+                    anyhow::bail!("Frame {frame} is out of the audio");
+                    }
+
+                let (frames, metadata)=open_document(&path)?;
+
+                let inserted_marks=metadata.marks.rebased(frame, self.mark_manager.get_available_id());
+
+                let mut updated_notes=self.notes.clone();
+                updated_notes.open_range(frame, frames.len());
+                updated_notes.append(&metadata.notes, frame);
+
+                return Ok(EditCommand::InsertAudio {
+                    start_frame: frame,
+                    frames,
+                    marks: inserted_marks,
+                    previous_notes: self.notes.clone(),
+                    updated_notes,
+                    });
+                },
+            };
+
+        if start_frame>=end_frame {
+            anyhow::bail!("The edited range contains no audio");
+            }
+
+        let mut updated_notes=self.notes.clone();
+        updated_notes.remove_range(start_frame, end_frame);
+
+        let marks: Vec<Mark>=self.mark_manager.get_mark_list().iter()
+        .filter(|mark| *mark.frame_offset()>=start_frame && *mark.frame_offset()<end_frame)
+        .cloned()
+        .collect();
+
+        Ok(EditCommand::RemoveAudio {
+            start_frame,
+            frames: self.audio.slice(start_frame, end_frame),
+            marks,
+            previous_notes: self.notes.clone(),
+            updated_notes,
+            })
+        }
+    /// Everything stored in the document besides the audio.
+    fn metadata(&self) -> storage::Metadata {
+        storage::Metadata {
+            marks: self.mark_manager.clone(),
+            notes: self.notes.clone(),
+            envelope: self.envelope.clone(),
+            loudness_target: self.loudness_target,
+            noise_suppression: self.noise_suppression,
+            transcript: self.transcript.clone(),
+            suggestions: self.suggestions.clone(),
+            speakers: self.speakers.clone(),
+            }
+        }
+    /// Keeps the data tied to positions in the audio, i.e. the envelope, the transcript, the mark suggestions and the speaker segments, in sync with an audio edit.
+    fn follow_audio_edit(&mut self, command: &EditCommand, reverted: bool) {
+        match (command, reverted) {
+            (EditCommand::RemoveAudio {start_frame, frames, ..}, false) | (EditCommand::InsertAudio {start_frame, frames, ..}, true) => {
+                self.envelope.remove_range(*start_frame, start_frame+frames.len());
+                self.transcript.remove_range(*start_frame, start_frame+frames.len());
+                self.suggestions.remove_range(*start_frame, start_frame+frames.len());
+                self.speakers.remove_range(*start_frame, start_frame+frames.len());
+                },
+            (EditCommand::RemoveAudio {start_frame, frames, ..}, true) | (EditCommand::InsertAudio {start_frame, frames, ..}, false) => {
+                self.envelope.insert(*start_frame, frames);
+                self.transcript.insert(*start_frame, frames.len());
+                self.suggestions.insert(*start_frame, frames.len());
+                self.speakers.insert(*start_frame, frames.len());
+                },
+            _ => return,
+            }
+
+        self.audio_revision+=1;
+        self.transcription_job=None;
+        self.schedule_transcription();
+        }
+    /// Sends the next chunk of audio without transcript to the transcriber, unless it's busy.
+    fn schedule_transcription(&mut self) {
+        let transcriber=match &self.transcriber {
+            Some(transcriber) if self.transcription_job.is_none() => transcriber,
+            _ => return,
+            };
+
+        //Pauses are looked up in the envelope to cut the chunks at, the transcription continues once it's complete
+        if !self.recording && self.envelope.len()<self.audio.len() {
+            self.complete_envelope();
+            return;
+            }
+
+        if let Some((start_frame, end_frame))=self.transcript.next_chunk(self.audio.len(), &self.envelope, self.recording) {
+            transcriber.transcribe(&self.audio, start_frame, end_frame, self.audio_revision);
+            self.transcription_job=Some((start_frame, end_frame));
+            }
+        }
+    /// Analyzes the recording for new mark suggestions.
+    fn update_suggestions(&mut self) {
+        if self.suggestions.update(&self.envelope, self.transcript.segments(), self.mark_manager.get_mark_list()) {
+            self.mark_modified();
+            }
+        }
+    /// Starts the speaker detection on the current audio. Only the frames the envelope doesn't consider silent are analysed, so it waits for the envelope to be completed first.
+    fn start_speaker_detection(&mut self, ctx: &mut Context<Self>) {
+        self.speaker_detection=Some(self.audio_revision);
+
+        if self.envelope.len()<self.audio.len() {
+            self.speaker_detection_waiting=true;
+            self.complete_envelope();
+            return;
+            }
+
+        self.speaker_detection_waiting=false;
+        speakers::start_detection(self.audio.clone(), self.envelope.clone(), self.audio_revision, ctx.address().recipient());
+        }
+    /// Computes the levels of the frames the envelope lags behind by in the background, as it decodes them. What waits for them continues once they arrive.
+    fn complete_envelope(&mut self) {
+        if self.envelope.len()>self.audio.len() {
+            self.envelope.truncate(self.audio.len());
+            }
+
+        if self.envelope_job.is_some() || self.envelope.len()==self.audio.len() {
+            return;
+            }
+
+        envelope::start_computation(self.audio.clone(), self.envelope.len(), self.audio.len(), self.audio_revision, self.self_addr.clone().recipient());
+        self.envelope_job=Some(self.audio_revision);
+        }
+    /// Keeps the playback position inside the audio after it was edited.
+    fn clamp_position(&mut self) {
+        if let Some(current_position)=self.current_position {
+            if current_position>=self.audio.len() {
+                self.current_position=self.audio.len().checked_sub(1);
+                self.future_position=None;
+                }
+            }
+        }
+
+    fn mark_modified(&mut self) {
+        self.changes.mark_modified();
+        }
+    fn emit(&mut self, event: SdamEvent) {
+        self.changes.emit(event);
         }
 
     fn stream_err_fn(err: cpal::StreamError) {
@@ -791,89 +2671,209 @@ impl AudioHandler {
 impl Actor for AudioHandler {
     type Context=Context<AudioHandler>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
-        println!("Audio handler started");
-        }
     }
 
 impl Handler<StartRecording> for AudioHandler {
     type Result=();
 
     fn handle(&mut self, _msg: StartRecording, _ctx: &mut Context<Self>) -> Self::Result {
-        println!("Starting recording");
-        self.recorder.do_send(StartRecording {});
-        self.recording=true;
+        self.start_recording();
+        }
+    }
+impl Handler<StopRecording> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: StopRecording, _ctx: &mut Context<Self>) -> Self::Result {
+        //Without the recording there is no live edge to follow
+        self.time_travel=None;
+        self.stop_recording();
+
+        //The end of the recording can be transcribed now, even if it's short
+        self.schedule_transcription();
+        }
+    }
+
+impl Handler<StartPlayback> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: StartPlayback, _ctx: &mut Context<Self>) -> Self::Result {
+        self.start_playback();
+        }
+    }
+impl Handler<PausePlayback> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: PausePlayback, _ctx: &mut Context<Self>) -> Self::Result {
+        self.pause_playback();
+        }
+    }
+impl Handler<TogglePlayback> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: TogglePlayback, _ctx: &mut Context<Self>) -> Self::Result {
+        match &self.playback_state {
+            PlaybackState::Playing => self.pause_playback(),
+            PlaybackState::Paused => self.start_playback(),
+            }
+        }
+    }
+
+impl Handler<Seek> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: Seek, _ctx: &mut Context<Self>) -> Self::Result {
+        self.seek(msg);
+        }
+    }
+
+impl Handler<GetFileName> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetFileName, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.file_name.clone()).unwrap();
+        }
+    }
+impl Handler<GetFilePath> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetFilePath, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.file_path.clone()).unwrap();
+        }
+    }
+impl Handler<GetAudioLen> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetAudioLen, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.audio.len()).unwrap();
+        }
+    }
+impl Handler<GetCurrentPosition> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetCurrentPosition, _ctx: &mut Context<Self>) -> Self::Result {
+        let position=self.heard_position();
+        msg.result_sender.send(position).unwrap();
+        }
+    }
+impl Handler<GetCurrentPositionMillis> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetCurrentPositionMillis, _ctx: &mut Context<Self>) -> Self::Result {
+        let position=self.heard_position_millis().map(|position| position as u64);
+        msg.result_sender.send(position).unwrap();
+        }
+    }
+impl Handler<GetIntegratedLoudness> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetIntegratedLoudness, _ctx: &mut Context<Self>) -> Self::Result {
+        self.complete_envelope();
+
+        msg.result_sender.send(self.envelope.integrated_loudness()).unwrap();
+        }
+    }
+impl Handler<GetLoudnessNormalization> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetLoudnessNormalization, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.loudness_target).unwrap();
+        }
+    }
+impl Handler<GetTranscript> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetTranscript, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.transcript.segments_between(msg.start_frame, msg.end_frame)).unwrap();
+        }
+    }
+impl Handler<Search> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: Search, _ctx: &mut Context<Self>) -> Self::Result {
+        let hits=search::search(self.transcript.segments(), self.mark_manager.get_mark_list(), &msg.query, &msg.options);
+
+        msg.result_sender.send(hits).unwrap();
+        }
+    }
+impl Handler<GetIsTranscribing> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetIsTranscribing, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.transcriber.is_some() && self.transcription_job.is_some()).unwrap();
         }
     }
-impl Handler<StopRecording> for AudioHandler {
+impl Handler<GetSpeakerSegments> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, _msg: StopRecording, _ctx: &mut Context<Self>) -> Self::Result {
-        println!("Stopping recording");
-        self.recorder.do_send(StopRecording {});
-        self.recording=false;
+    fn handle(&mut self, msg: GetSpeakerSegments, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.speakers.segments().to_vec()).unwrap();
         }
     }
-
-impl Handler<StartPlayback> for AudioHandler {
+impl Handler<GetSpeakerAt> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, _msg: StartPlayback, _ctx: &mut Context<Self>) -> Self::Result {
-        self.start_playback();
+    fn handle(&mut self, msg: GetSpeakerAt, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.speakers.segment_at(msg.frame).cloned()).unwrap();
         }
     }
-impl Handler<PausePlayback> for AudioHandler {
+impl Handler<GetNextQuestion> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, _msg: PausePlayback, _ctx: &mut Context<Self>) -> Self::Result {
-        self.pause_playback();
+    fn handle(&mut self, msg: GetNextQuestion, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.speakers.next_question(msg.frame).cloned()).unwrap();
         }
     }
-impl Handler<TogglePlayback> for AudioHandler {
+impl Handler<GetIsDetectingSpeakers> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, _msg: TogglePlayback, _ctx: &mut Context<Self>) -> Self::Result {
-        match &self.playback_state {
-            PlaybackState::Playing => self.pause_playback(),
-            PlaybackState::Paused => self.start_playback(),
-            }
+    fn handle(&mut self, msg: GetIsDetectingSpeakers, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.speaker_detection.is_some()).unwrap();
         }
     }
-
-impl Handler<Seek> for AudioHandler {
+impl Handler<GetNoiseSuppression> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: Seek, _ctx: &mut Context<Self>) -> Self::Result {
-        self.seek(msg);
+    fn handle(&mut self, msg: GetNoiseSuppression, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.noise_suppression).unwrap();
         }
     }
+impl Handler<GetIsMonitoringLive> for AudioHandler {
+    type Result=();
 
-impl Handler<GetFileName> for AudioHandler {
+    fn handle(&mut self, msg: GetIsMonitoringLive, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.monitor.is_active()).unwrap();
+        }
+    }
+impl Handler<GetIsTimeTravelling> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: GetFileName, _ctx: &mut Context<Self>) -> Self::Result {
-        msg.result_sender.send(self.file_name.clone()).unwrap();
+    fn handle(&mut self, msg: GetIsTimeTravelling, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.time_travel.is_some()).unwrap();
         }
     }
-impl Handler<GetFilePath> for AudioHandler {
+impl Handler<GetLiveLag> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: GetFilePath, _ctx: &mut Context<Self>) -> Self::Result {
-        msg.result_sender.send(self.file_path.clone()).unwrap();
+    fn handle(&mut self, msg: GetLiveLag, _ctx: &mut Context<Self>) -> Self::Result {
+        let live_lag=self.live_lag_millis().map(|lag| Duration::from_millis(lag as u64));
+        msg.result_sender.send(live_lag).unwrap();
         }
     }
-impl Handler<GetAudioLen> for AudioHandler {
+impl Handler<GetLoopRegion> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: GetAudioLen, _ctx: &mut Context<Self>) -> Self::Result {
-        msg.result_sender.send(self.audio.len()).unwrap();
+    fn handle(&mut self, msg: GetLoopRegion, _ctx: &mut Context<Self>) -> Self::Result {
+        let region=self.active_loop.as_ref()
+        .map(|active_loop| (active_loop.start_frame, active_loop.end_frame));
+
+        msg.result_sender.send(region).unwrap();
         }
     }
-impl Handler<GetCurrentPosition> for AudioHandler {
+impl Handler<GetRate> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: GetCurrentPosition, _ctx: &mut Context<Self>) -> Self::Result {
-        msg.result_sender.send(self.current_position.clone()).unwrap();
+    fn handle(&mut self, msg: GetRate, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.rate).unwrap();
         }
     }
 impl Handler<GetIsPlaying> for AudioHandler {
@@ -907,14 +2907,32 @@ impl Handler<GetIsRecording> for AudioHandler {
         msg.result_sender.send(self.recording).unwrap();
         }
     }
+impl Handler<GetIsModified> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetIsModified, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.changes.is_modified()).unwrap();
+        }
+    }
+impl Handler<GetChangeCount> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetChangeCount, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.changes.change_count()).unwrap();
+        }
+    }
+impl Handler<Subscribe> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: Subscribe, _ctx: &mut Context<Self>) -> Self::Result {
+        self.changes.subscribe(msg.event_sender);
+        }
+    }
 impl Handler<GetMark> for AudioHandler {
     type Result=();
 
     fn handle(&mut self, msg: GetMark, _ctx: &mut Context<Self>) -> Self::Result {
-        if let Ok(mark)=self.mark_manager.get(msg.id) {
-            msg.result_sender.send(Some(mark.clone())).unwrap();
-            return;
-            }
+        msg.result_sender.send(self.mark_manager.get(msg.id).ok().cloned()).unwrap();
         }
     }
 impl Handler<GetMarks> for AudioHandler {
@@ -952,7 +2970,35 @@ impl Handler<GetUserText> for AudioHandler {
     type Result=();
 
     fn handle(&mut self, msg: GetUserText, _ctx: &mut Context<Self>) -> Self::Result {
-        msg.result_sender.send(self.user_text.clone()).unwrap();
+        msg.result_sender.send(self.notes.text()).unwrap();
+        }
+    }
+impl Handler<GetNoteLines> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetNoteLines, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.notes.lines().clone()).unwrap();
+        }
+    }
+impl Handler<GetNoteLineIndexAt> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetNoteLineIndexAt, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.notes.line_index_at(msg.frame)).unwrap();
+        }
+    }
+impl Handler<GetEnvelope> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetEnvelope, _ctx: &mut Context<Self>) -> Self::Result {
+        //Ranges without computed levels would be stretched over the computed part
+        if std::cmp::min(msg.end_frame, self.audio.len())>self.envelope.len() {
+            self.complete_envelope();
+            msg.result_sender.send(Vec::new()).unwrap();
+            return;
+            }
+
+        msg.result_sender.send(self.envelope.overview(msg.start_frame, msg.end_frame, msg.points)).unwrap();
         }
     }
 
@@ -960,41 +3006,422 @@ impl Handler<AddMark> for AudioHandler {
     type Result=();
 
     fn handle(&mut self, msg: AddMark, _ctx: &mut Context<Self>) -> Self::Result {
-        let assigned_mark=self.mark_manager.add(msg.mark.clone());
+        let assigned_mark=self.mark_manager.add(msg.mark.clone()).clone();
+        self.edit_history.push(EditCommand::AddMark(assigned_mark.clone()));
+        self.mark_modified();
+
+        msg.result_sender.send(assigned_mark).unwrap();
+        }
+    }
+impl Handler<SuggestMarks> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SuggestMarks, _ctx: &mut Context<Self>) -> Self::Result {
+        //Pauses and emphasis at the end of a partial envelope would be misjudged, so the analysis waits for it to be complete
+        if self.envelope.len()<self.audio.len() {
+            self.suggestions_waiting=true;
+            self.complete_envelope();
+            }
+        else {
+            self.update_suggestions();
+            }
+
+        msg.result_sender.send(self.suggestions.list().to_vec()).unwrap();
+        }
+    }
+impl Handler<AcceptSuggestion> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: AcceptSuggestion, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send((move || {
+            let suggestion=match self.suggestions.take(msg.id) {
+                Some(suggestion) => suggestion,
+                None => anyhow::bail!("Unknown suggestion {}", msg.id),
+                };
+
+            let mark=Mark::new(*suggestion.frame_offset(), msg.category, suggestion.label().clone());
+            let assigned_mark=self.mark_manager.add(mark).clone();
+            self.edit_history.push(EditCommand::AddMark(assigned_mark.clone()));
+            self.mark_modified();
+
+            Ok(assigned_mark)
+            })()).unwrap();
+        }
+    }
+impl Handler<DismissSuggestion> for AudioHandler {
+    type Result=();
 
-        msg.result_sender.send(assigned_mark.clone()).unwrap();
+    fn handle(&mut self, msg: DismissSuggestion, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.suggestions.dismiss(msg.id) {
+            self.mark_modified();
+            }
         }
     }
 impl Handler<EditMark> for AudioHandler {
     type Result=();
 
     fn handle(&mut self, msg: EditMark, _ctx: &mut Context<Self>) -> Self::Result {
-        let _=self.mark_manager.edit(msg.id, msg.updated_mark);
+        let previous=if let Ok(mark)=self.mark_manager.get(msg.id) {
+            mark.clone()
+            }
+        else {
+            return;
+            };
+
+        if let Ok(updated)=self.mark_manager.edit(msg.id, msg.updated_mark) {
+            let updated=updated.clone();
+            self.edit_history.push(EditCommand::EditMark {previous, updated});
+            self.mark_modified();
+            }
         }
     }
 impl Handler<DeleteMark> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: DeleteMark, _ctx: &mut Context<Self>) -> Self::Result {
-        self.mark_manager.remove(msg.id);
+    fn handle(&mut self, msg: DeleteMark, _ctx: &mut Context<Self>) -> Self::Result {
+        let mark=if let Ok(mark)=self.mark_manager.get(msg.id) {
+            mark.clone()
+            }
+        else {
+            return;
+            };
+
+        if self.mark_manager.remove(msg.id) {
+            self.edit_history.push(EditCommand::DeleteMark(mark));
+            self.mark_modified();
+            }
+        }
+    }
+impl Handler<SetRate> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetRate, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.rate<=0.0 {
+            return;
+            }
+
+        self.rate=msg.rate;
+        }
+    }
+impl Handler<SetLoop> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetLoop, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send((move || {
+            let (start_frame, end_frame)=match *msg.playback_loop.region() {
+                LoopRegion::Frames(start_frame, end_frame) => (start_frame, end_frame),
+                LoopRegion::Marks(first_id, second_id) => {
+                    let first_frame=*self.mark_manager.get(first_id)?.frame_offset();
+                    let second_frame=*self.mark_manager.get(second_id)?.frame_offset();
+
+                    (std::cmp::min(first_frame, second_frame), std::cmp::max(first_frame, second_frame))
+                    },
+                };
+            let end_frame=std::cmp::min(end_frame, self.audio.len());
+
+            if start_frame>=end_frame {
+                anyhow::bail!("The loop region contains no audio");
+                }
+            if *msg.playback_loop.repeat_count()==Some(0) {
+                anyhow::bail!("The loop must be played at least once");
+                }
+            if let Some(slowdown)=msg.playback_loop.slowdown() {
+                if *slowdown<=0.0 {
+                    anyhow::bail!("The slowdown must be positive");
+                    }
+                }
+
+            self.active_loop=Some(ActiveLoop::new(start_frame, end_frame, *msg.playback_loop.repeat_count(), *msg.playback_loop.slowdown()));
+
+            let inside_loop=match self.heard_position() {
+                Some(position) => position>=start_frame && position<end_frame,
+                None => false,
+                };
+            if !inside_loop {
+                self.seek(Seek::Absolute(start_frame));
+                }
+
+            Ok(())
+            })()).unwrap();
+        }
+    }
+impl Handler<ClearLoop> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: ClearLoop, _ctx: &mut Context<Self>) -> Self::Result {
+        self.active_loop=None;
+        }
+    }
+impl Handler<SetPlaybackGain> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetPlaybackGain, _ctx: &mut Context<Self>) -> Self::Result {
+        self.playback_processor.set_gain(msg.gain);
+        }
+    }
+impl Handler<SetCompressor> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetCompressor, _ctx: &mut Context<Self>) -> Self::Result {
+        self.playback_processor.set_compressor(msg.settings);
+        }
+    }
+impl Handler<SetLoudnessNormalization> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetLoudnessNormalization, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.loudness_target==msg.target {
+            return;
+            }
+
+        self.loudness_target=msg.target;
+        self.update_normalization();
+        self.mark_modified();
+        }
+    }
+impl Handler<SetTranscriptionEngine> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetTranscriptionEngine, ctx: &mut Context<Self>) -> Self::Result {
+        self.transcriber=msg.engine.map(|engine| Transcriber::new(engine, ctx.address().recipient()));
+
+        //Results of a job of the previous engine are discarded
+        self.transcription_job=None;
+        self.audio_revision+=1;
+        self.schedule_transcription();
+        }
+    }
+impl Handler<ClearTranscript> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: ClearTranscript, _ctx: &mut Context<Self>) -> Self::Result {
+        self.transcript=Transcript::new();
+        self.transcription_job=None;
+        self.audio_revision+=1;
+        self.mark_modified();
+        self.schedule_transcription();
+        }
+    }
+impl Handler<TranscriptionDone> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: TranscriptionDone, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.revision!=self.audio_revision {
+            return;
+            }
+
+        self.transcription_job=None;
+
+        match msg.result {
+            Ok(recognized) => {
+                self.transcript.add(msg.start_frame, msg.end_frame, recognized);
+                self.mark_modified();
+                self.schedule_transcription();
+                },
+            Err(error) => {
+                self.transcriber=None;
+                self.emit(SdamEvent::TranscriptionFailed {message: error.to_string()});
+                },
+            }
+        }
+    }
+impl Handler<DetectSpeakers> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: DetectSpeakers, ctx: &mut Context<Self>) -> Self::Result {
+        if self.speaker_detection.is_none() {
+            self.start_speaker_detection(ctx);
+            }
+        }
+    }
+impl Handler<SpeakersDetected> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SpeakersDetected, ctx: &mut Context<Self>) -> Self::Result {
+        //Results for another document are discarded
+        if self.speaker_detection!=Some(msg.revision) {
+            return;
+            }
+
+        //The audio was edited meanwhile, so the segments wouldn't fit
+        if msg.revision!=self.audio_revision {
+            self.start_speaker_detection(ctx);
+            return;
+            }
+
+        self.speaker_detection=None;
+
+        match msg.result {
+            Ok(segments) => {
+                let speaker_count=segments.iter().map(|segment| *segment.speaker()).max().unwrap_or(0);
+
+                self.speakers.set_segments(segments);
+                self.mark_modified();
+                self.emit(SdamEvent::SpeakersDetected {speaker_count});
+                },
+            Err(error) => self.emit(SdamEvent::SpeakerDetectionFailed {message: error.to_string()}),
+            }
+        }
+    }
+impl Handler<LevelsComputed> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: LevelsComputed, ctx: &mut Context<Self>) -> Self::Result {
+        //Results for another document are discarded
+        if self.envelope_job!=Some(msg.revision) {
+            return;
+            }
+
+        self.envelope_job=None;
+
+        //Levels of edited audio wouldn't fit, they're computed again
+        if msg.revision==self.audio_revision {
+            self.envelope.extend(msg.start_frame, msg.peaks, msg.rms);
+            }
+
+        //Frames recorded meanwhile are computed next
+        if self.envelope.len()<self.audio.len() {
+            self.complete_envelope();
+            return;
+            }
+
+        if self.speaker_detection_waiting {
+            self.start_speaker_detection(ctx);
+            }
+        if self.suggestions_waiting {
+            self.suggestions_waiting=false;
+            self.update_suggestions();
+            self.emit(SdamEvent::MarksSuggested {suggestion_count: self.suggestions.list().len()});
+            }
+        self.update_normalization();
+        self.schedule_transcription();
+        }
+    }
+impl Handler<SetNoiseSuppression> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetNoiseSuppression, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.noise_suppression==msg.enabled {
+            return;
+            }
+
+        self.noise_suppression=msg.enabled;
+        self.playback_processor.set_noise_suppression(msg.enabled);
+        self.mark_modified();
+        }
+    }
+impl Handler<SetMonitoring> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetMonitoring, _ctx: &mut Context<Self>) -> Self::Result {
+        self.monitoring=msg.enabled;
+        self.update_monitoring();
+        }
+    }
+impl Handler<SetMonitoringGain> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetMonitoringGain, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.gain<0.0 {
+            return;
+            }
+
+        self.monitor.set_gain(msg.gain);
+        }
+    }
+impl Handler<StartTimeTravel> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: StartTimeTravel, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.time_travel.is_some() {
+            return;
+            }
+
+        let started_recording=!self.recording;
+        if started_recording {
+            self.start_recording();
+            }
+
+        self.pause_playback();
+        self.time_travel=Some(TimeTravel::new(msg.options, started_recording));
+        self.seek(Seek::ToEnd);
+        self.start_playback();
+        }
+    }
+impl Handler<StopTimeTravel> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, _msg: StopTimeTravel, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(time_travel)=self.time_travel.take() {
+            self.pause_playback();
+
+            if time_travel.started_recording {
+                self.stop_recording();
+                }
+            }
+        }
+    }
+impl Handler<SetUserText> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: SetUserText, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.text==self.notes.text() {
+            return;
+            }
+
+        let previous=self.notes.clone();
+        let anchor_position=self.anchor_position();
+        self.notes.update(&msg.text, anchor_position);
+
+        self.edit_history.push_text_edit(previous, self.notes.clone(), Instant::now());
+        self.mark_modified();
+        }
+    }
+
+impl Handler<Undo> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: Undo, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(command)=self.edit_history.undo() {
+            command.revert(&mut self.audio, &mut self.mark_manager, &mut self.notes);
+            self.follow_audio_edit(&command, true);
+            self.clamp_position();
+            self.mark_modified();
+            msg.result_sender.send(Some(command.description())).unwrap();
+            return;
+            }
+
+        msg.result_sender.send(None).unwrap();
         }
     }
-impl Handler<SetRate> for AudioHandler {
+impl Handler<Redo> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: SetRate, _ctx: &mut Context<Self>) -> Self::Result {
-        if msg.rate<=0.0 {
+    fn handle(&mut self, msg: Redo, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(command)=self.edit_history.redo() {
+            command.apply(&mut self.audio, &mut self.mark_manager, &mut self.notes);
+            self.follow_audio_edit(&command, false);
+            self.clamp_position();
+            self.mark_modified();
+            msg.result_sender.send(Some(command.description())).unwrap();
             return;
             }
 
-        self.rate=msg.rate;
+        msg.result_sender.send(None).unwrap();
         }
     }
-impl Handler<SetUserText> for AudioHandler {
+impl Handler<GetUndoHistory> for AudioHandler {
     type Result=();
 
-    fn handle(&mut self, msg: SetUserText, _ctx: &mut Context<Self>) -> Self::Result {
-        self.user_text=msg.text;
+    fn handle(&mut self, msg: GetUndoHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.edit_history.undo_descriptions()).unwrap();
+        }
+    }
+impl Handler<GetRedoHistory> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: GetRedoHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send(self.edit_history.redo_descriptions()).unwrap();
         }
     }
 
@@ -1003,26 +3430,38 @@ impl Handler<Load> for AudioHandler {
 
     fn handle(&mut self, msg: Load, _ctx: &mut Context<Self>) -> Self::Result {
         msg.result_sender.send((move || {
-            let mut file=File::open(&msg.path)?;
-
-            let mut serialized: Vec<u8>=Vec::new();
-            file.read_to_end(&mut serialized)?;
-
-            let model: SdamFileModel=rmp_serde::from_slice(&serialized)?;
-
-            drop(serialized);
-
-            let SdamFileModel { audio, marks, text }=model;
-
-            self.audio=AudioContainer::from_vec(audio);
-            self.mark_manager=marks;
-            self.user_text=text;
+            let (audio, metadata)=open_document(&msg.path)?;
+
+            self.audio=audio;
+            self.mark_manager=metadata.marks;
+            self.notes=metadata.notes;
+            self.envelope=metadata.envelope;
+            self.loudness_target=metadata.loudness_target;
+            self.noise_suppression=metadata.noise_suppression;
+            self.transcript=metadata.transcript;
+            self.suggestions=metadata.suggestions;
+            self.speakers=metadata.speakers;
+            self.transcription_job=None;
+            self.speaker_detection=None;
+            self.speaker_detection_waiting=false;
+            self.suggestions_waiting=false;
+            self.envelope_job=None;
+            self.audio_revision+=1;
+            self.playback_processor.set_noise_suppression(self.noise_suppression);
+            self.edit_history.clear();
+            self.update_normalization();
 
             self.file_path=Some(msg.path.clone());
             self.file_name=Some(msg.path.file_name().unwrap().to_string_lossy().to_string());
             self.pause_playback();
             self.current_position=None;
             self.future_position=None;
+            self.seek_start=None;
+            self.active_loop=None;
+            self.changes.mark_saved();
+            self.schedule_transcription();
+
+            self.emit(SdamEvent::Loaded {path: msg.path.clone()});
 
             Ok(())
             })()).unwrap();
@@ -1043,28 +3482,112 @@ impl Handler<Save> for AudioHandler {
                 anyhow::bail!("No file opened");
                 };
 
-            let mut file=File::create(&path)?;
+            storage::write(&path, &self.metadata(), self.audio.len(), |id| self.audio.frame_data(id).unwrap())?;
+
+            //The saved audio is mapped back from the file, so recorded frames don't need to stay in memory
+            if let Ok(storage::StoredDocument::Indexed {frames, ..})=storage::open(&path) {
+                if frames.len()==self.audio.len() {
+                    self.audio=AudioContainer::from_mapped(frames);
+                    }
+                }
+
+            self.file_path=Some(path.clone());
+            self.file_name=Some(path.file_name().unwrap().to_string_lossy().to_string());
+            self.changes.mark_saved();
+
+            self.emit(SdamEvent::Saved {path});
+
+            Ok(())
+            })()).unwrap();
+        }
+    }
+impl Handler<ExportStudySheet> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: ExportStudySheet, _ctx: &mut Context<Self>) -> Self::Result {
+        let options=match (msg.options.title(), &self.file_path) {
+            (None, Some(file_path)) => {
+                let title=file_path.file_stem().unwrap().to_string_lossy().to_string();
+                msg.options.with_title(&title)
+                },
+            _ => msg.options,
+            };
+
+        let result=export::write_study_sheet(&msg.path, &self.audio, &self.mark_manager, &self.notes, &options);
+        msg.result_sender.send(result).unwrap();
+        }
+    }
+impl Handler<ExportClip> for AudioHandler {
+    type Result=();
 
-            let model=SdamFileModel {
-                audio: self.audio.to_vec(),
-                marks: self.mark_manager.clone(),
-                text: self.user_text.clone(),
+    fn handle(&mut self, msg: ExportClip, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send((move || {
+            let (start_frame, end_frame)=match msg.source {
+                ClipSource::Mark(id) => {
+                    let frame_offset=*self.mark_manager.get(id)?.frame_offset();
+                    (frame_offset, frame_offset+1)
+                    },
+                ClipSource::Frames(start_frame, end_frame) => (start_frame, end_frame),
                 };
 
-            let serialized=rmp_serde::to_vec(&model).unwrap();
-            drop(model);
+            let start_frame=start_frame.saturating_sub(1000*msg.seconds_before/FRAME_DURATION);
+            let end_frame=std::cmp::min(end_frame+1000*msg.seconds_after/FRAME_DURATION, self.audio.len());
 
-            file.write(&serialized)?;
+            if start_frame>=end_frame {
+                anyhow::bail!("The clip contains no audio");
+                }
 
-            drop(serialized);
+            match msg.format {
+                ClipFormat::Wav => self.audio.write_wav(start_frame, end_frame, &msg.path),
+                ClipFormat::Sdam => {
+                    let mut metadata=self.metadata();
+                    metadata.clip(start_frame, end_frame, self.audio.len());
+                    metadata.marks=self.mark_manager.clipped(start_frame, end_frame);
+                    metadata.notes=self.notes.clipped(start_frame, end_frame);
 
-            self.file_path=Some(path.clone());
-            self.file_name=Some(path.file_name().unwrap().to_string_lossy().to_string());
+                    SdamFileModel::with_metadata(self.audio.range_to_vec(start_frame, end_frame), metadata).write(&msg.path)
+                    },
+                }
+            })()).unwrap();
+        }
+    }
+impl Handler<EditAudio> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: EditAudio, _ctx: &mut Context<Self>) -> Self::Result {
+        msg.result_sender.send((move || {
+            if self.recording {
+                anyhow::bail!("Audio can't be edited while recording");
+                }
+
+            let command=self.audio_edit_command(msg.edit)?;
+            command.apply(&mut self.audio, &mut self.mark_manager, &mut self.notes);
+            self.follow_audio_edit(&command, false);
+
+            self.edit_history.push(command);
+            self.clamp_position();
+            self.mark_modified();
 
             Ok(())
             })()).unwrap();
         }
     }
+impl Handler<Split> for AudioHandler {
+    type Result=();
+
+    fn handle(&mut self, msg: Split, _ctx: &mut Context<Self>) -> Self::Result {
+        let stem=if let Some(file_path)=&self.file_path {
+            file_path.file_stem().unwrap_or_default().to_string_lossy().to_string()
+            }
+        else {
+            "untitled".to_string()
+            };
+
+        let model=SdamFileModel::with_metadata(self.audio.to_vec(), self.metadata());
+
+        msg.result_sender.send(write_split_parts(&model, &msg.points, &msg.output_directory, &stem)).unwrap();
+        }
+    }
 impl Handler<Quit> for AudioHandler {
     type Result=();
 
@@ -1083,36 +3606,57 @@ impl Handler<UpdateAudioBuffer> for AudioHandler {
 
             if self.audio_producer.len()<=(FRAME_SIZE as f64/active_rate) as usize {
                 if let Some(current_position)=self.current_position {
+                    //Playback continuing from a seek starts with the remaining samples of the sought frame
+                    if let Some(start_sample)=self.seek_start.take() {
+                        if let Some(frame)=self.playable_frame(current_position) {
+                            self.decode_into_producer(current_position, &frame, active_rate, start_sample);
+                            self.future_position=Some(current_position);
+                            }
+                        }
+
                     if self.future_position.is_none() {
-                        if let Some(future_frame)=self.audio.get_frame(current_position+1) {
-                            self.decode_into_producer(&future_frame, active_rate);
-                            self.future_position=Some(current_position+1);
+                        let next_frame=self.next_frame(current_position);
+
+                        if let Some(future_frame)=self.playable_frame(next_frame) {
+                            self.decode_into_producer(next_frame, &future_frame, active_rate, 0);
+                            self.future_position=Some(next_frame);
                             }
                         }
 
                     if let Some(future_position)=self.future_position {
                         self.current_position=Some(future_position);
                         let current_position=future_position;
+                        let next_frame=self.next_frame(current_position);
 
-                        if let Some(future_frame)=self.audio.get_frame(current_position+1) {
-                            self.decode_into_producer(&future_frame, active_rate);
-                            self.future_position=Some(current_position+1);
+                        if let Some(future_frame)=self.playable_frame(next_frame) {
+                            self.decode_into_producer(next_frame, &future_frame, active_rate, 0);
+                            self.future_position=Some(next_frame);
                             }
                         }
                     }
                 else {
-                    if let Some(frame)=self.audio.get_frame(0) {
-                        self.decode_into_producer(&frame, active_rate);
+                    if let Some(frame)=self.playable_frame(0) {
+                        self.decode_into_producer(0, &frame, active_rate, 0);
                         self.current_position=Some(0);
 
-                        if let Some(future_frame)=self.audio.get_frame(1) {
-                            self.decode_into_producer(&future_frame, active_rate);
+                        if let Some(future_frame)=self.playable_frame(1) {
+                            self.decode_into_producer(1, &future_frame, active_rate, 0);
                             self.future_position=Some(1);
                             }
                         }
                     }
                 }
 
+            if let Some(live_lag)=self.live_lag_millis() {
+                let lag_frames=live_lag as usize/FRAME_DURATION;
+
+                if self.time_travel.as_mut().is_some_and(|time_travel| time_travel.update_live(lag_frames)) {
+                    self.emit(SdamEvent::RejoinedLive);
+                    }
+
+                self.update_monitoring();
+                }
+
             ctx.notify_later(UpdateAudioBuffer {}, std::time::Duration::from_millis(5));
             }
         }
@@ -1122,6 +3666,14 @@ impl Handler<NewOpusFrame> for AudioHandler {
 
     fn handle(&mut self, msg: NewOpusFrame, _ctx: &mut Context<Self>) -> Self::Result {
         self.audio.push_new_frame(msg.frame);
+
+        //Only the new frame is measured, an envelope lagging behind is completed when requested
+        if self.envelope.len()+1==self.audio.len() {
+            self.envelope.update(&self.audio);
+            }
+
+        self.changes.mark_recorded(Instant::now());
+        self.schedule_transcription();
         }
     }
 
@@ -1140,14 +3692,56 @@ pub struct NewOpusFrame {
     frame: OpusFrame,
     }
 
+/// A continuous part of the audio, either held in memory or mapped from a document file.
+#[derive(Clone, Debug)]
+enum AudioSegment {
+    Loaded(Vec<Arc<OpusFrame>>),
+    Mapped {frames: Arc<MappedFrames>, start: usize, len: usize},
+    }
+impl AudioSegment {
+
+    fn len(&self) -> usize {
+        match self {
+            AudioSegment::Loaded(frames) => frames.len(),
+            AudioSegment::Mapped {len, ..} => *len,
+            }
+        }
+    fn slice(&self, start_frame: usize, end_frame: usize) -> AudioSegment {
+        match self {
+            AudioSegment::Loaded(frames) => AudioSegment::Loaded(frames[start_frame..end_frame].to_vec()),
+            AudioSegment::Mapped {frames, start, ..} => AudioSegment::Mapped {
+                frames: frames.clone(),
+                start: start+start_frame,
+                len: end_frame-start_frame,
+                },
+            }
+        }
+    fn get_frame(&self, id: usize) -> Arc<OpusFrame> {
+        match self {
+            AudioSegment::Loaded(frames) => frames[id].clone(),
+            AudioSegment::Mapped {frames, start, ..} => Arc::new(OpusFrame::new(frames.frame_data(start+id).to_vec())),
+            }
+        }
+    fn frame_data(&self, id: usize) -> &[u8] {
+        match self {
+            AudioSegment::Loaded(frames) => frames[id].data(),
+            AudioSegment::Mapped {frames, start, ..} => frames.frame_data(start+id),
+            }
+        }
+    }
+
+/// The recorded audio. Frames of loaded documents stay in the document file and are read only when needed, so even very long recordings open instantly.
+#[derive(Clone, Debug)]
 pub struct AudioContainer {
-    frames: Vec<Arc<OpusFrame>>,
+    segments: Vec<AudioSegment>,
+    len: usize,
     }
 impl AudioContainer {
 
     pub fn new() -> AudioContainer {
         AudioContainer {
-            frames: Vec::new(),
+            segments: Vec::new(),
+            len: 0,
             }
         }
     pub fn from_vec(v: Vec<Vec<u8>>) -> AudioContainer {
@@ -1155,50 +3749,158 @@ impl AudioContainer {
         .map(|i| Arc::new(OpusFrame::new(i)))
         .collect();
 
+        Self::from_segments(vec![AudioSegment::Loaded(frames)])
+        }
+    fn from_mapped(frames: Arc<MappedFrames>) -> AudioContainer {
+        let len=frames.len();
+
+        Self::from_segments(vec![AudioSegment::Mapped {frames, start: 0, len}])
+        }
+    fn from_segments(segments: Vec<AudioSegment>) -> AudioContainer {
+        let segments: Vec<AudioSegment>=segments.into_iter()
+        .filter(|segment| segment.len()>0)
+        .collect();
+        let len=segments.iter().map(|segment| segment.len()).sum();
+
         AudioContainer {
-            frames
+            segments,
+            len,
             }
         }
     pub fn to_vec(&self) -> Vec<Vec<u8>> {
-        self.frames.iter()
-        .map(|i| i.data().to_vec())
+        self.range_to_vec(0, self.len)
+        }
+    pub fn range_to_vec(&self, start_frame: usize, end_frame: usize) -> Vec<Vec<u8>> {
+        let end_frame=std::cmp::min(end_frame, self.len);
+        let start_frame=std::cmp::min(start_frame, end_frame);
+
+        (start_frame..end_frame)
+        .map(|id| self.frame_data(id).unwrap().to_vec())
         .collect()
         }
 
     pub fn get_frame(&self, id: usize) -> Option<Arc<OpusFrame>> {
-        if id>=self.frames.len() {
-            return None;
+        let (segment, segment_id)=self.locate(id)?;
+
+        Some(segment.get_frame(segment_id))
+        }
+    /// Returns the data of the given frame, without copying it out of the container.
+    pub fn frame_data(&self, id: usize) -> Option<&[u8]> {
+        let (segment, segment_id)=self.locate(id)?;
+
+        Some(segment.frame_data(segment_id))
+        }
+    fn locate(&self, mut id: usize) -> Option<(&AudioSegment, usize)> {
+        for segment in &self.segments {
+            if id<segment.len() {
+                return Some((segment, id));
+                }
+
+            id-=segment.len();
             }
 
-        Some(self.frames[id].clone())
+        None
         }
 
-    pub fn push_new_frame(&mut self, frame: OpusFrame) {
-        self.frames.push(Arc::new(frame));
+    /// Decodes the frames in the given range and writes them to a WAV file.
+    pub fn write_wav(&self, start_frame: usize, end_frame: usize, path: &Path) -> Result<(), anyhow::Error> {
+        let end_frame=std::cmp::min(end_frame, self.len);
+
+        let spec=hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLING_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+            };
+        let mut writer=hound::WavWriter::create(path, spec)?;
+        let mut decoder=Decoder::new(SAMPLING_RATE, opus::Channels::Mono)?;
+        let mut decoding_buffer=vec![0_i16; 2*FRAME_SIZE];
+
+        for id in std::cmp::min(start_frame, end_frame)..end_frame {
+            let decoded_samples=decoder.decode(self.frame_data(id).unwrap(), &mut decoding_buffer, false)?;
+
+            for sample in &decoding_buffer[..decoded_samples] {
+                writer.write_sample(*sample)?;
+                }
+            }
+
+        writer.finalize()?;
+
+        Ok(())
+        }
+
+    /// Returns the given range of frames as a new container. The frames are shared, not copied.
+    pub fn slice(&self, start_frame: usize, end_frame: usize) -> AudioContainer {
+        let end_frame=std::cmp::min(end_frame, self.len);
+        let start_frame=std::cmp::min(start_frame, end_frame);
+
+        let mut segments: Vec<AudioSegment>=Vec::new();
+        let mut segment_start=0;
+
+        for segment in &self.segments {
+            let segment_end=segment_start+segment.len();
+
+            if segment_end>start_frame && segment_start<end_frame {
+                let slice_start=std::cmp::max(start_frame, segment_start)-segment_start;
+                let slice_end=std::cmp::min(end_frame, segment_end)-segment_start;
+
+                segments.push(segment.slice(slice_start, slice_end));
+                }
+
+            segment_start=segment_end;
+            }
+
+        Self::from_segments(segments)
+        }
+    /// Removes the frames in the given range and returns them.
+    pub fn remove_range(&mut self, start_frame: usize, end_frame: usize) -> AudioContainer {
+        let removed=self.slice(start_frame, end_frame);
+
+        let mut segments=self.slice(0, start_frame).segments;
+        segments.extend(self.slice(end_frame, self.len).segments);
+        *self=Self::from_segments(segments);
+
+        removed
+        }
+    pub fn insert(&mut self, start_frame: usize, audio: AudioContainer) {
+        let mut segments=self.slice(0, start_frame).segments;
+        segments.extend(audio.segments);
+        segments.extend(self.slice(start_frame, self.len).segments);
+
+        *self=Self::from_segments(segments);
+        }
 
-        if self.frames.len()==100 {
-            println!("Received first 1000 frames.");
+    pub fn push_new_frame(&mut self, frame: OpusFrame) {
+        if let Some(AudioSegment::Loaded(frames))=self.segments.last_mut() {
+            frames.push(Arc::new(frame));
+            }
+        else {
+            self.segments.push(AudioSegment::Loaded(vec![Arc::new(frame)]));
             }
+
+        self.len+=1;
         }
 
     pub fn len(&self) -> usize {
-        self.frames.len()
+        self.len
         }
     }
 
 pub struct Recorder {
     _host: cpal::Host,
-    device: cpal::Device,
+    /// Missing on machines without an input device, which can't record.
+    device: Option<cpal::Device>,
     stream_config: StreamConfig,
     input_stream: Option<cpal::Stream>,
     encoder: Encoder,
     recipient: Recipient<NewOpusFrame>,
+    monitor_input: Arc<Mutex<MonitorInput>>,
     }
 impl Recorder {
 
-    pub fn new(recipient: Recipient<NewOpusFrame>) -> Addr<Recorder> {
+    fn new(recipient: Recipient<NewOpusFrame>, monitor_input: MonitorInput) -> Addr<Recorder> {
         let host=cpal::default_host();
-        let device=host.default_input_device().unwrap();
+        let device=host.default_input_device();
         let config=StreamConfig {
             buffer_size: BufferSize::Fixed(FRAME_SIZE as u32),
             channels: 1,
@@ -1213,6 +3915,7 @@ impl Recorder {
             input_stream: None,
             encoder,
             recipient,
+            monitor_input: Arc::new(Mutex::new(monitor_input)),
             }
         .start()
         }
@@ -1241,11 +3944,24 @@ impl Handler<StartRecording> for Recorder {
         if !self.input_stream.is_none() {
             return;
             }
+        let device=match &self.device {
+            Some(device) => device,
+            None => {
+                eprintln!("No input device to record from");
+                return;
+                },
+            };
 
         let mut collector_buffer=CollectorBuffer::with_capacity(FRAME_SIZE);
         let addr=ctx.address();
+        let monitor_input=self.monitor_input.clone();
 
         let input_fn=move |data: &[i16], _callback_info: &cpal::InputCallbackInfo| {
+            //The lock is only ever taken by this callback, try_lock just avoids blocking the audio thread in any case
+            if let Ok(mut monitor_input)=monitor_input.try_lock() {
+                monitor_input.push(data);
+                }
+
             if let Some(chunks)=collector_buffer.push(data) {
                 for chunk in chunks {
                     addr.do_send(NewAudioChunk { chunk });
@@ -1253,7 +3969,7 @@ impl Handler<StartRecording> for Recorder {
                 }
             };
 
-        let input_stream=self.device.build_input_stream(&self.stream_config, input_fn, Self::stream_err_fn, None).unwrap();
+        let input_stream=device.build_input_stream(&self.stream_config, input_fn, Self::stream_err_fn, None).unwrap();
         input_stream.play().unwrap();
 
         self.input_stream=Some(input_stream);
@@ -1337,6 +4053,18 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn parse_time_test() {
+        assert_eq!(parse_time("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_time("1:30.5"), Ok(Duration::from_millis(90500)));
+        assert_eq!(parse_time("1:02:03"), Ok(Duration::from_secs(3723)));
+        assert!(parse_time("1:x").is_err());
+        assert!(parse_time("-5").is_err());
+        assert!(parse_time("1:2:3:4").is_err());
+        assert!(parse_time("1e20").is_err());
+        assert!(parse_time("18446744073709551615:00").is_err());
+        }
+
     #[test]
     fn collector_buffer_test() {
         let mut cb=CollectorBuffer::with_capacity(5);
@@ -1373,6 +4101,10 @@ mod tests {
 
         assert_eq!(mark_list.len(), 3);
         assert!(mark_list.last().unwrap().is(3));
+
+        //The GetMark handler answers lookups of removed or unknown ids by None
+        assert!(manager.get(1).ok().cloned().is_none());
+        assert!(manager.get(99).is_err());
         }
 
     #[test]
@@ -1431,4 +4163,435 @@ mod tests {
             assert_eq!(matched_id, expected_result);
             }
         }
+
+    #[test]
+    fn duration_conversion_test() {
+        assert_eq!(frame_offset_to_duration(25), Duration::from_secs(1));
+        assert_eq!(duration_to_frame_offset(Duration::from_millis(1039)), 25);
+        assert_eq!(duration_to_frame_offset(Duration::from_millis(1040)), 26);
+
+        let mark=Mark::at(Duration::from_millis(2050), 1, None);
+        assert_eq!(*mark.frame_offset(), 51);
+        assert_eq!(mark.time(), Duration::from_millis(2040));
+        }
+
+    #[test]
+    fn active_loop_test() {
+        let mut active_loop=ActiveLoop::new(10, 13, Some(2), Some(0.5));
+
+        //Playback before the loop runs into it
+        assert_eq!(active_loop.next_frame(8, 100), Some(9));
+        assert_eq!(active_loop.next_frame(9, 100), Some(10));
+        assert_eq!(active_loop.next_frame(11, 100), Some(12));
+
+        assert_eq!(active_loop.next_frame(12, 100), Some(10));
+        assert_eq!(active_loop.rate_factor, 0.5);
+
+        //The second repetition is the last one
+        assert_eq!(active_loop.next_frame(12, 100), None);
+
+        let mut active_loop=ActiveLoop::new(10, 13, None, Some(0.1));
+        for _ in 0..5 {
+            assert_eq!(active_loop.next_frame(12, 100), Some(10));
+            }
+        assert_eq!(active_loop.rate_factor, MINIMUM_LOOP_RATE);
+
+        //Playback sought behind the loop doesn't return to it
+        assert_eq!(active_loop.next_frame(20, 100), Some(21));
+
+        //The loop shrinks with the audio
+        assert_eq!(active_loop.next_frame(10, 12), Some(11));
+        assert_eq!(active_loop.next_frame(11, 12), Some(10));
+        assert_eq!(active_loop.next_frame(5, 8), None);
+        }
+
+    #[test]
+    fn mark_manager_insert_test() {
+        let mut manager=MarkManager::new();
+
+        manager.add(Mark::new(3, 1, None));
+        manager.add(Mark::new(5, 1, None));
+        manager.add(Mark::new(7, 1, None));
+
+        let removed=manager.get(1).unwrap().clone();
+        assert!(manager.remove(1));
+
+        assert!(manager.insert(removed.clone()).is_ok());
+        assert!(manager.insert(removed).is_err());
+
+        let ids: Vec<Option<u64>>=manager.get_mark_list().iter().map(|mark| *mark.id()).collect();
+        assert_eq!(ids, vec![Some(0), Some(1), Some(2)]);
+        }
+
+    #[test]
+    fn edit_history_test() {
+        let mut manager=MarkManager::new();
+        let mut audio=AudioContainer::new();
+        let mut notes=Notes::new();
+        let mut history=EditHistory::new();
+
+        let mark=manager.add(Mark::new(3, 1, None)).clone();
+        history.push(EditCommand::AddMark(mark.clone()));
+
+        let updated=manager.edit(0, Mark::new(4, 2, Some("Theorem".to_string()))).unwrap().clone();
+        history.push(EditCommand::EditMark {previous: mark, updated});
+
+        let previous=notes.clone();
+        notes.update("Notes", Some(4));
+        history.push(EditCommand::SetUserText {previous, updated: notes.clone()});
+
+        assert_eq!(history.undo_descriptions(), vec!["Edit text", "Edit mark", "Add mark"]);
+
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert_eq!(notes.text(), "");
+
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert_eq!(*manager.get(0).unwrap().frame_offset(), 3);
+
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert!(manager.get(0).is_err());
+        assert!(history.undo().is_none());
+
+        history.redo().unwrap().apply(&mut audio, &mut manager, &mut notes);
+        assert!(manager.get(0).unwrap().is(0));
+        assert_eq!(history.redo_descriptions(), vec!["Edit mark", "Edit text"]);
+
+        //A new command invalidates the redo stack
+        history.push(EditCommand::DeleteMark(manager.get(0).unwrap().clone()));
+        manager.remove(0);
+        assert!(history.redo().is_none());
+
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert!(manager.get(0).is_ok());
+        }
+
+    #[test]
+    fn text_edit_merging_test() {
+        let mut history=EditHistory::new();
+        let mut notes=Notes::new();
+        let start=Instant::now();
+
+        //Keystrokes close to each other are a single edit
+        for (index, text) in ["L", "Le", "Lem", "Lemma"].iter().enumerate() {
+            let previous=notes.clone();
+            notes.update(text, None);
+            history.push_text_edit(previous, notes.clone(), start+Duration::from_millis(300*index as u64));
+            }
+        assert_eq!(history.undo_descriptions(), vec!["Edit text"]);
+
+        //A pause starts a new one
+        let previous=notes.clone();
+        notes.update("Lemma 1", None);
+        history.push_text_edit(previous, notes.clone(), start+Duration::from_secs(10));
+        assert_eq!(history.undo_descriptions().len(), 2);
+
+        let mut audio=AudioContainer::new();
+        let mut manager=MarkManager::new();
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert_eq!(notes.text(), "Lemma");
+
+        //Edits after an undo aren't merged into the undone one
+        let previous=notes.clone();
+        notes.update("Lemma 2", None);
+        history.push_text_edit(previous, notes.clone(), start+Duration::from_secs(11));
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert_eq!(notes.text(), "Lemma");
+
+        history.undo().unwrap().revert(&mut audio, &mut manager, &mut notes);
+        assert_eq!(notes.text(), "");
+        assert!(history.undo().is_none());
+        }
+
+    #[test]
+    fn notes_update_test() {
+        let mut notes=Notes::new();
+
+        notes.update("Definition", Some(10));
+        notes.update("Definition\n", Some(20));
+        notes.update("Definition\nTheorem", Some(30));
+        notes.update("Introduction\nDefinition\nTheorem 1", Some(40));
+
+        let anchors: Vec<Option<usize>>=notes.lines().iter().map(|line| *line.frame_offset()).collect();
+        assert_eq!(notes.text(), "Introduction\nDefinition\nTheorem 1");
+        assert_eq!(anchors, vec![Some(40), Some(10), Some(20)]);
+
+        notes.update("Introduction\nTheorem 1", Some(50));
+        let anchors: Vec<Option<usize>>=notes.lines().iter().map(|line| *line.frame_offset()).collect();
+        assert_eq!(anchors, vec![Some(40), Some(20)]);
+
+        notes.update("", Some(60));
+        assert!(notes.lines().is_empty());
+        }
+
+    #[test]
+    fn notes_line_index_at_test() {
+        let mut notes=Notes::from_text("Title");
+        notes.update("Title\nFirst", Some(5));
+        notes.update("Title\nFirst\nSecond", Some(12));
+        notes.update("Title\nZeroth\nFirst\nSecond", Some(2));
+
+        let tested_offsets: Vec<usize>=vec![0, 2, 4, 5, 11, 12, 100];
+        let expected_results: Vec<Option<usize>>=vec![None, Some(1), Some(1), Some(2), Some(2), Some(3), Some(3)];
+
+        for (tested_offset, expected_result) in tested_offsets.iter().zip(expected_results) {
+            assert_eq!(notes.line_index_at(*tested_offset), expected_result);
+            }
+        }
+
+    #[test]
+    fn write_wav_test() {
+        let mut encoder=Encoder::new(SAMPLING_RATE, opus::Channels::Mono, opus::Application::Audio).unwrap();
+        let mut audio=AudioContainer::new();
+
+        for _ in 0..10 {
+            audio.push_new_frame(OpusFrame::new(encoder.encode_vec(&[0_i16; FRAME_SIZE], FRAME_SIZE).unwrap()));
+            }
+
+        let path=std::env::temp_dir().join("sdam_write_wav_test.wav");
+        audio.write_wav(2, 6, &path).unwrap();
+
+        let reader=hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLING_RATE);
+        assert_eq!(reader.len() as usize, 4*FRAME_SIZE);
+
+        std::fs::remove_file(&path).unwrap();
+        }
+
+    #[test]
+    fn legacy_file_model_test() {
+        #[derive(Serialize)]
+        struct LegacySdamFileModel {
+            audio: Vec<Vec<u8>>,
+            marks: MarkManager,
+            text: String,
+            }
+
+        let legacy=LegacySdamFileModel {
+            audio: vec![vec![1, 2, 3]],
+            marks: MarkManager::new(),
+            text: "Old notes".to_string(),
+            };
+
+        let serialized=rmp_serde::to_vec(&legacy).unwrap();
+        let document: storage::LegacyDocument=rmp_serde::from_slice(&serialized).unwrap();
+        let model=document.into_model();
+
+        assert_eq!(model.notes().text(), "Old notes");
+        assert_eq!(model.notes(), Notes::from_text("Old notes"));
+        }
+
+    #[test]
+    fn audio_edit_test() {
+        let mut audio=AudioContainer::from_vec((0..30).map(|i| vec![i as u8]).collect());
+
+        let mut manager=MarkManager::new();
+        manager.add(Mark::new(5, 1, None));
+        manager.add(Mark::new(12, 1, None));
+        manager.add(Mark::new(25, 1, None));
+
+        let mut notes=Notes::new();
+        notes.update("Early", Some(2));
+        notes.update("Early\nRemoved", Some(14));
+        notes.update("Early\nRemoved\nLate", Some(28));
+
+        let mut updated_notes=notes.clone();
+        updated_notes.remove_range(10, 20);
+
+        let command=EditCommand::RemoveAudio {
+            start_frame: 10,
+            frames: audio.slice(10, 20),
+            marks: vec![manager.get(1).unwrap().clone()],
+            previous_notes: notes.clone(),
+            updated_notes,
+            };
+
+        command.apply(&mut audio, &mut manager, &mut notes);
+
+        assert_eq!(audio.len(), 20);
+        assert_eq!(audio.get_frame(10).unwrap().data(), &[20]);
+        let offsets: Vec<usize>=manager.get_mark_list().iter().map(|mark| *mark.frame_offset()).collect();
+        assert_eq!(offsets, vec![5, 15]);
+        let anchors: Vec<Option<usize>>=notes.lines().iter().map(|line| *line.frame_offset()).collect();
+        assert_eq!(anchors, vec![Some(2), None, Some(18)]);
+
+        command.revert(&mut audio, &mut manager, &mut notes);
+
+        assert_eq!(audio.len(), 30);
+        assert_eq!(audio.get_frame(10).unwrap().data(), &[10]);
+        let offsets: Vec<usize>=manager.get_mark_list().iter().map(|mark| *mark.frame_offset()).collect();
+        assert_eq!(offsets, vec![5, 12, 25]);
+        assert!(manager.get_mark_list()[1].is(1));
+        let anchors: Vec<Option<usize>>=notes.lines().iter().map(|line| *line.frame_offset()).collect();
+        assert_eq!(anchors, vec![Some(2), Some(14), Some(28)]);
+        }
+
+    #[test]
+    fn audio_container_segments_test() {
+        let path=std::env::temp_dir().join("sdam_audio_container_segments_test.sdam");
+        SdamFileModel::new((0..10).map(|i| vec![i as u8]).collect(), MarkManager::new(), Notes::new()).write(&path).unwrap();
+
+        let (mut audio, _)=open_document(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        for i in 10..15 {
+            audio.push_new_frame(OpusFrame::new(vec![i]));
+            }
+
+        let data=|audio: &AudioContainer| -> Vec<u8> {
+            audio.to_vec().into_iter().map(|frame| frame[0]).collect()
+            };
+
+        assert_eq!(audio.len(), 15);
+        assert_eq!(data(&audio.slice(8, 12)), vec![8, 9, 10, 11]);
+
+        let removed=audio.remove_range(8, 12);
+        assert_eq!(data(&audio), vec![0, 1, 2, 3, 4, 5, 6, 7, 12, 13, 14]);
+
+        audio.insert(2, removed);
+        assert_eq!(data(&audio), vec![0, 1, 8, 9, 10, 11, 2, 3, 4, 5, 6, 7, 12, 13, 14]);
+        assert_eq!(audio.get_frame(3).unwrap().data(), &[9]);
+        assert!(audio.get_frame(15).is_none());
+
+        audio.push_new_frame(OpusFrame::new(vec![15]));
+        assert_eq!(audio.frame_data(15).unwrap(), &[15]);
+        }
+
+    #[test]
+    fn unknown_mark_request_test() {
+        //Goes through the audio handler, which must answer lookups of marks which don't exist rather than leave the caller waiting
+        let mut sdam=Sdam::new();
+        assert!(sdam.get_mark(99).is_none());
+
+        let id=sdam.add_mark(Mark::new(0, 1, None)).id().unwrap();
+        assert!(sdam.get_mark(id).is_some_and(|mark| mark.is(id)));
+        sdam.delete_mark(id);
+        assert!(sdam.get_mark(id).is_none());
+        }
+
+    #[test]
+    fn merge_test() {
+        let mut first_marks=MarkManager::new();
+        first_marks.add(Mark::new(2, 1, None));
+        first_marks.add(Mark::new(4, 1, None));
+        let mut second_marks=MarkManager::new();
+        second_marks.add(Mark::new(1, 2, Some("Question".to_string())));
+
+        let mut first=SdamFileModel::new(vec![vec![0]; 5], first_marks, Notes::from_text("First"));
+        let second=SdamFileModel::new(vec![vec![1]; 3], second_marks, Notes::from_text("Second"));
+
+        first.append(second, "---");
+        let (audio, marks, notes)=first.into_parts();
+
+        assert_eq!(audio.len(), 8);
+        assert_eq!(audio[5], vec![1]);
+
+        let merged_mark=marks.get(2).unwrap();
+        assert_eq!(*merged_mark.frame_offset(), 6);
+        assert_eq!(merged_mark.label(), &Some("Question".to_string()));
+
+        assert_eq!(notes.text(), "First\n---\nSecond");
+        assert_eq!(*notes.lines()[1].frame_offset(), Some(5));
+        }
+
+    #[test]
+    fn split_test() {
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(2, 1, None));
+        marks.add(Mark::new(10, 1, Some("Second talk".to_string())));
+        marks.add(Mark::new(15, 1, None));
+
+        let mut notes=Notes::new();
+        notes.update("Opening", Some(1));
+        notes.update("Opening\nTalk", Some(12));
+        notes.update("Opening\nTalk\nUnanchored", None);
+
+        let model=SdamFileModel::new((0..20).map(|i| vec![i as u8]).collect(), marks, notes);
+        let parts=model.split(&[10, 0, 25]);
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].audio().len(), 10);
+        assert_eq!(parts[1].audio()[0], vec![10]);
+
+        let offsets: Vec<usize>=parts[1].marks().get_mark_list().iter().map(|mark| *mark.frame_offset()).collect();
+        assert_eq!(offsets, vec![0, 5]);
+
+        assert_eq!(parts[0].notes().text(), "Opening");
+        let second_notes=parts[1].notes();
+        assert_eq!(second_notes.text(), "Talk\nUnanchored");
+        assert_eq!(*second_notes.lines()[0].frame_offset(), Some(2));
+        }
+
+    #[test]
+    fn split_merge_metadata_test() {
+        let mut transcript=Transcript::new();
+        transcript.add(0, 20, vec![
+            RecognizedText::new(frame_offset_to_duration(0), frame_offset_to_duration(4), "Opening"),
+            RecognizedText::new(frame_offset_to_duration(12), frame_offset_to_duration(16), "Talk"),
+            ]);
+        let mut speakers=Diarization::new();
+        speakers.set_segments(vec![SpeakerSegment::new(0, 8, 1), SpeakerSegment::new(12, 18, 1)]);
+
+        let mut metadata=storage::Metadata::new(MarkManager::new(), Notes::new());
+        metadata.transcript=transcript.clone();
+        metadata.speakers=speakers;
+        metadata.loudness_target=Some(-20.0);
+
+        let directory=std::env::temp_dir().join("sdam_split_merge_metadata_test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path=directory.join("lecture.sdam");
+        SdamFileModel::with_metadata((0..20).map(|i| vec![i as u8]).collect(), metadata).write(&path).unwrap();
+
+        let parts=split_document(path.to_str().unwrap(), &[SplitPoint::Frame(10)], directory.to_str().unwrap()).unwrap();
+        let (_, second)=SdamFileModel::read(&parts[1]).unwrap().into_stored();
+        assert_eq!(second.transcript.segments(), &[TranscriptSegment::new(2, 6, "Talk")]);
+        assert_eq!(second.speakers.segments(), &[SpeakerSegment::new(2, 8, 1)]);
+        assert_eq!(second.loudness_target, Some(-20.0));
+
+        let merged_path=directory.join("merged.sdam");
+        let part_paths: Vec<&str>=parts.iter().map(|part| part.to_str().unwrap()).collect();
+        merge_documents(&part_paths, merged_path.to_str().unwrap()).unwrap();
+        let (audio, merged)=SdamFileModel::read(&merged_path).unwrap().into_stored();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(audio.len(), 20);
+        assert_eq!(merged.transcript, transcript);
+        //The parts' speakers can't be told apart anymore
+        assert_eq!(merged.speakers.segments(), &[SpeakerSegment::new(0, 8, 1), SpeakerSegment::new(12, 18, 2)]);
+        }
+
+    #[test]
+    fn clipped_document_test() {
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(5, 1, None));
+        marks.add(Mark::new(10, 2, Some("Theorem".to_string())));
+        marks.add(Mark::new(20, 1, None));
+
+        let clipped_marks=marks.clipped(8, 20);
+        let mark_list=clipped_marks.get_mark_list();
+        assert_eq!(mark_list.len(), 1);
+        assert!(mark_list[0].is(1));
+        assert_eq!(*mark_list[0].frame_offset(), 2);
+
+        let mut notes=Notes::new();
+        notes.update("Before", Some(3));
+        notes.update("Before\nInside", Some(12));
+        notes.update("Before\nInside\nUnanchored", None);
+
+        let clipped_notes=notes.clipped(8, 20);
+        assert_eq!(clipped_notes.lines(), &vec![NoteLine::new("Inside".to_string(), Some(4))]);
+
+        let audio: Vec<Vec<u8>>=(0..30).map(|i| vec![i as u8]).collect();
+        let audio=AudioContainer::from_vec(audio);
+        let model=SdamFileModel::new(audio.range_to_vec(8, 20), clipped_marks, clipped_notes);
+
+        let path=std::env::temp_dir().join("sdam_clipped_document_test.sdam");
+        model.write(&path).unwrap();
+        let (audio, marks, notes)=SdamFileModel::read(&path).unwrap().into_parts();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(audio.len(), 12);
+        assert_eq!(audio[0], vec![8]);
+        assert_eq!(marks.get_mark_list().len(), 1);
+        assert_eq!(notes.text(), "Inside");
+        }
     }
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+use crate::FRAME_SIZE;
+
+/// Input samples the monitoring path buffers at most. Older samples are dropped, so the latency can't build up.
+const MAX_MONITOR_BACKLOG: usize=FRAME_SIZE;
+
+/// Settings of the monitoring path, shared with the audio callbacks.
+pub(crate) struct Monitor {
+    active: AtomicBool,
+    gain: AtomicU32,
+    }
+impl Monitor {
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+        }
+    pub(crate) fn set_active(&self, active: bool) {
+        self.active.store(active, Ordering::Relaxed);
+        }
+    pub(crate) fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+        }
+    pub(crate) fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+/// The end of the monitoring path fed by the input callback.
+pub(crate) struct MonitorInput {
+    producer: HeapProducer<i16>,
+    monitor: Arc<Monitor>,
+    }
+impl MonitorInput {
+
+    pub(crate) fn push(&mut self, samples: &[i16]) {
+        if self.monitor.is_active() {
+            self.producer.push_slice(samples);
+            }
+        }
+    }
+
+/// The end of the monitoring path read by the output callback.
+pub(crate) struct MonitorOutput {
+    consumer: HeapConsumer<i16>,
+    monitor: Arc<Monitor>,
+    }
+impl MonitorOutput {
+
+    /// Replaces the output with the monitored input, if the monitoring is active. Returns whether it was.
+    pub(crate) fn play_into(&mut self, data: &mut [i16]) -> bool {
+        if !self.monitor.is_active() {
+            self.consumer.clear();
+            return false;
+            }
+
+        let backlog=self.consumer.len().saturating_sub(data.len()+MAX_MONITOR_BACKLOG);
+        self.consumer.skip(backlog);
+
+        let popped_samples=self.consumer.pop_slice(data);
+        let gain=self.monitor.gain();
+
+        for sample in &mut data[..popped_samples] {
+            *sample=(*sample as f32*gain).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        for sample in &mut data[popped_samples..] {
+            *sample=0;
+            }
+
+        true
+        }
+    }
+
+/// Creates the monitoring path, initially inactive with unity gain.
+pub(crate) fn monitor_path() -> (Arc<Monitor>, MonitorInput, MonitorOutput) {
+    let monitor=Arc::new(Monitor {
+        active: AtomicBool::new(false),
+        gain: AtomicU32::new(1.0_f32.to_bits()),
+        });
+
+    let (producer, consumer)=HeapRb::<i16>::new(4*FRAME_SIZE).split();
+
+    (monitor.clone(),
+    MonitorInput {producer, monitor: monitor.clone()},
+    MonitorOutput {consumer, monitor},)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn monitor_path_test() {
+        let (monitor, mut input, mut output)=monitor_path();
+        let mut data=[7_i16; 4];
+
+        //Inactive monitoring neither buffers the input nor touches the output
+        input.push(&[100; 4]);
+        assert!(!output.play_into(&mut data));
+        assert_eq!(data, [7; 4]);
+
+        monitor.set_active(true);
+        monitor.set_gain(0.5);
+
+        input.push(&[100, -100, 30000]);
+        assert!(output.play_into(&mut data));
+        assert_eq!(data, [50, -50, 15000, 0]);
+
+        monitor.set_gain(2.0);
+        input.push(&[30000]);
+        output.play_into(&mut data);
+        assert_eq!(data, [i16::MAX, 0, 0, 0]);
+
+        //A backlog is dropped except for the most recent samples
+        let samples: Vec<i16>=(0..2*FRAME_SIZE).map(|i| (i%100) as i16).collect();
+        monitor.set_gain(1.0);
+        input.push(&samples);
+        output.play_into(&mut data);
+        assert_eq!(output.consumer.len(), MAX_MONITOR_BACKLOG);
+        }
+    }
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+/// Samples of a spectrum the audio is analysed in, about 21 ms.
+const FFT_SIZE: usize=1024;
+/// Samples between the starts of consecutive spectra, half of the size, so every sample is covered by two of them.
+const HOP_SIZE: usize=FFT_SIZE/2;
+/// How fast the noise floor estimate rises per hop when the signal stays above it, about 2 dB per second.
+const NOISE_FLOOR_RISE: f32=1.005;
+/// The lowest noise floor, below the quantization noise of 16 bit audio. A floor of zero, learned from digital silence, could never rise, as it rises by multiplication.
+const MINIMUM_NOISE_FLOOR: f32=1e-8;
+/// Weight of the previous spectrum in the smoothed power the noise floor is tracked in.
+const POWER_SMOOTHING: f32=0.9;
+/// Ratio of the mean noise power to its tracked floor, the minimum of the smoothed power lying below the mean.
+const NOISE_FLOOR_BIAS: f32=1.5;
+/// How many times the noise floor is subtracted, so the noise is removed also where it fluctuates above its estimate.
+const OVER_SUBTRACTION: f32=2.0;
+/// The lowest gain of a frequency, about -16 dB. Removing the noise completely would leave unpleasant artifacts.
+const GAIN_FLOOR: f32=0.15;
+/// Weight of the previous gain of a frequency, preventing quick fluctuations heard as musical noise.
+const GAIN_SMOOTHING: f32=0.5;
+
+/// Spectral gating denoiser. It follows the noise floor of every frequency, i.e. the level the frequency doesn't fall below for long, and attenuates the frequencies near it.
+/// Steady noise like fans and air conditioning is removed, while speech, rising above the floor, passes.
+/// The output is delayed by the size of a spectrum against the input, about 21 ms.
+pub(crate) struct NoiseSuppressor {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    time_buffer: Vec<f32>,
+    smoothed_power: Vec<f32>,
+    noise_floor: Vec<f32>,
+    gains: Vec<f32>,
+    /// Samples taken since the last processed spectrum.
+    pending: usize,
+    }
+impl NoiseSuppressor {
+
+    pub(crate) fn new() -> NoiseSuppressor {
+        let mut planner=RealFftPlanner::<f32>::new();
+        let forward=planner.plan_fft_forward(FFT_SIZE);
+        let inverse=planner.plan_fft_inverse(FFT_SIZE);
+        let spectrum=forward.make_output_vec();
+        let bins=spectrum.len();
+
+        //The square root of the periodic Hann window, applied both before and after the filtering. Their product sums to one over overlapping spectra.
+        let window: Vec<f32>=(0..FFT_SIZE)
+        .map(|i| (0.5-0.5*(2.0*std::f32::consts::PI*i as f32/FFT_SIZE as f32).cos()).sqrt())
+        .collect();
+
+        NoiseSuppressor {
+            forward,
+            inverse,
+            window,
+            input: vec![0.0; FFT_SIZE],
+            output: vec![0.0; FFT_SIZE],
+            spectrum,
+            time_buffer: vec![0.0; FFT_SIZE],
+            smoothed_power: vec![0.0; bins],
+            noise_floor: vec![f32::MAX; bins],
+            gains: vec![1.0; bins],
+            pending: 0,
+            }
+        }
+
+    /// Forgets the buffered audio, e.g. after a seek, keeping the learned noise floor.
+    pub(crate) fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.pending=0;
+        }
+
+    /// Replaces the samples, given relative to the full scale, by the denoised ones.
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let input_sample: f32=*sample;
+
+            self.input[FFT_SIZE-HOP_SIZE+self.pending]=input_sample;
+            *sample=self.output[self.pending];
+            self.pending+=1;
+
+            if self.pending==HOP_SIZE {
+                self.process_spectrum();
+                self.pending=0;
+                }
+            }
+        }
+
+    fn process_spectrum(&mut self) {
+        for ((buffer, input), window) in self.time_buffer.iter_mut().zip(&self.input).zip(&self.window) {
+            *buffer=input*window;
+            }
+
+        if self.forward.process(&mut self.time_buffer, &mut self.spectrum).is_err() {
+            return;
+            }
+
+        for bin in 0..self.spectrum.len() {
+            let power=self.spectrum[bin].norm_sqr();
+
+            self.smoothed_power[bin]=if self.noise_floor[bin]==f32::MAX {
+                power
+                }
+            else {
+                POWER_SMOOTHING*self.smoothed_power[bin]+(1.0-POWER_SMOOTHING)*power
+                };
+            self.noise_floor[bin]=f32::min(self.noise_floor[bin]*NOISE_FLOOR_RISE, self.smoothed_power[bin]).max(MINIMUM_NOISE_FLOOR);
+
+            let gain=if power>0.0 {
+                (1.0-OVER_SUBTRACTION*NOISE_FLOOR_BIAS*self.noise_floor[bin]/power).max(GAIN_FLOOR)
+                }
+            else {
+                GAIN_FLOOR
+                };
+            self.gains[bin]=GAIN_SMOOTHING*self.gains[bin]+(1.0-GAIN_SMOOTHING)*gain;
+
+            self.spectrum[bin]*=self.gains[bin];
+            }
+
+        //The imaginary parts of the lowest and highest bins must be zero for the inverse transform
+        let last=self.spectrum.len()-1;
+        self.spectrum[0].im=0.0;
+        self.spectrum[last].im=0.0;
+
+        if self.inverse.process(&mut self.spectrum, &mut self.time_buffer).is_err() {
+            return;
+            }
+
+        //The output keeps the second half of the previous spectrum, to which the first half of this one is added, and the other way round
+        self.output.copy_within(HOP_SIZE.., 0);
+        self.output[FFT_SIZE-HOP_SIZE..].fill(0.0);
+        for ((output, filtered), window) in self.output.iter_mut().zip(&self.time_buffer).zip(&self.window) {
+            *output+=filtered*window/FFT_SIZE as f32;
+            }
+
+        self.input.copy_within(HOP_SIZE.., 0);
+        }
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::SAMPLING_RATE;
+
+    /// Deterministic white noise.
+    fn noise(amplitude: f32, samples: usize) -> Vec<f32> {
+        let mut state: u32=12345;
+
+        (0..samples)
+        .map(|_| {
+            state=state.wrapping_mul(1664525).wrapping_add(1013904223);
+
+            (state as f32/u32::MAX as f32*2.0-1.0)*amplitude
+            })
+        .collect()
+        }
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|sample| sample*sample).sum::<f32>()/samples.len() as f32
+        }
+
+    #[test]
+    fn noise_suppression_test() {
+        let mut suppressor=NoiseSuppressor::new();
+
+        //Five seconds of noise, the second half with a tone standing for speech
+        let samples=5*SAMPLING_RATE as usize;
+        let mut input=noise(0.05, samples);
+        for (i, sample) in input.iter_mut().enumerate().skip(samples/2) {
+            *sample+=(i as f32*440.0*2.0*std::f32::consts::PI/SAMPLING_RATE as f32).sin()*0.3;
+            }
+
+        let mut output=input.clone();
+        suppressor.process(&mut output);
+
+        //The noise is attenuated once learned
+        let noise_only=SAMPLING_RATE as usize..samples/2;
+        assert!(power(&output[noise_only.clone()])<power(&input[noise_only])/4.0);
+
+        //The tone passes, delayed by a spectrum
+        let tone=samples/2+SAMPLING_RATE as usize..samples-FFT_SIZE;
+        let delayed_tone=tone.start+FFT_SIZE..tone.end+FFT_SIZE;
+        let ratio=power(&output[delayed_tone])/power(&input[tone]);
+        assert!(ratio>0.8 && ratio<1.1);
+
+        //After a reset, the output starts with the buffered delay filled by silence
+        suppressor.reset();
+        let mut output=vec![0.5; HOP_SIZE];
+        suppressor.process(&mut output);
+        assert!(output.iter().all(|sample| *sample==0.0));
+        }
+
+    #[test]
+    fn noise_after_silence_test() {
+        let mut suppressor=NoiseSuppressor::new();
+
+        //A second of digital silence, e.g. a muted microphone, then noise long enough for the floor to rise to it
+        let silence=SAMPLING_RATE as usize;
+        let samples=45*SAMPLING_RATE as usize;
+        let mut input=vec![0.0; silence];
+        input.extend(noise(0.05, samples-silence));
+
+        let mut output=input.clone();
+        suppressor.process(&mut output);
+
+        let end=samples-3*SAMPLING_RATE as usize..samples;
+        assert!(power(&output[end.clone()])<power(&input[end])/4.0);
+        }
+    }
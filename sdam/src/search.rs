@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+use derive_getters::Getters;
+
+use crate::transcription::TranscriptSegment;
+use crate::{frame_offset_to_duration, Mark};
+
+/// The least similarity of a fuzzy match, tolerating a wrong letter in every four or so.
+const DEFAULT_FUZZY_THRESHOLD: f64=0.75;
+
+/// Where a search hit was found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchSource {
+    Transcript,
+    /// The label of the mark with the given id.
+    Mark(u64),
+    }
+
+/// A place in the document matching a search query.
+#[derive(Clone, Debug, PartialEq, Getters)]
+pub struct SearchHit {
+    /// The frame the matched words start at, or the frame of the matched mark.
+    frame_offset: usize,
+    source: SearchSource,
+    /// The transcript segment or the mark label containing the match.
+    text: String,
+    /// How well the text matches the query, 1.0 being an exact match.
+    score: f64,
+    }
+impl SearchHit {
+
+    /// The time the matched words start at.
+    pub fn time(&self) -> Duration {
+        frame_offset_to_duration(self.frame_offset)
+        }
+    }
+
+#[derive(Clone, Copy, Debug, Getters)]
+pub struct SearchOptions {
+    /// The least similarity of words to the query counting as a match, None for exact matches only.
+    fuzzy_threshold: Option<f64>,
+    include_marks: bool,
+    include_transcript: bool,
+    /// The most hits returned, the best ones first.
+    limit: Option<usize>,
+    }
+impl SearchOptions {
+
+    pub fn new() -> SearchOptions {
+        SearchOptions {
+            fuzzy_threshold: None,
+            include_marks: true,
+            include_transcript: true,
+            limit: None,
+            }
+        }
+
+    /// Accepts words similar to the query, such as misheard or misspelled ones.
+    pub fn with_fuzzy_matching(self) -> SearchOptions {
+        self.with_fuzzy_threshold(DEFAULT_FUZZY_THRESHOLD)
+        }
+    pub fn with_fuzzy_threshold(self, fuzzy_threshold: f64) -> SearchOptions {
+        SearchOptions {
+            fuzzy_threshold: Some(fuzzy_threshold),
+            ..self
+            }
+        }
+    pub fn marks_only(self) -> SearchOptions {
+        SearchOptions {
+            include_marks: true,
+            include_transcript: false,
+            ..self
+            }
+        }
+    pub fn transcript_only(self) -> SearchOptions {
+        SearchOptions {
+            include_marks: false,
+            include_transcript: true,
+            ..self
+            }
+        }
+    pub fn with_limit(self, limit: usize) -> SearchOptions {
+        SearchOptions {
+            limit: Some(limit),
+            ..self
+            }
+        }
+    }
+impl Default for SearchOptions {
+
+    fn default() -> SearchOptions {
+        SearchOptions::new()
+        }
+    }
+
+/// A word of the searched text, with its position.
+struct Word {
+    text: String,
+    /// Index of the segment or mark the word belongs to.
+    owner: usize,
+    frame_offset: usize,
+    }
+
+/// Finds the query in the transcript and mark labels, returning the hits ordered from the best match, and by time among equal ones.
+pub(crate) fn search(transcript: &[TranscriptSegment], marks: &[Mark], query: &str, options: &SearchOptions) -> Vec<SearchHit> {
+    let query: Vec<String>=normalized_words(query).into_iter().map(|(word, _)| word).collect();
+
+    if query.is_empty() {
+        return Vec::new();
+        }
+
+    let threshold=options.fuzzy_threshold.unwrap_or(1.0);
+    let mut hits: Vec<SearchHit>=Vec::new();
+
+    if options.include_transcript {
+        //The words are searched across segment boundaries, since the engine may split a phrase
+        let mut words: Vec<Word>=Vec::new();
+        for (index, segment) in transcript.iter().enumerate() {
+            let text=segment.text();
+            let length=text.chars().count().max(1);
+
+            for (word, char_offset) in normalized_words(text) {
+                //Words are assumed to be spoken at an even pace over the segment
+                let frame_offset=segment.start_frame()+(segment.end_frame()-segment.start_frame())*char_offset/length;
+
+                words.push(Word {text: word, owner: index, frame_offset});
+                }
+            }
+
+        for (start, score) in find_phrase(&words, &query, threshold) {
+            let word=&words[start];
+
+            hits.push(SearchHit {
+                frame_offset: word.frame_offset,
+                source: SearchSource::Transcript,
+                text: transcript[word.owner].text().clone(),
+                score,
+                });
+            }
+        }
+
+    if options.include_marks {
+        for mark in marks {
+            let (Some(id), Some(label))=(mark.id(), mark.label()) else {
+                continue;
+                };
+
+            let words: Vec<Word>=normalized_words(label).into_iter()
+            .map(|(word, _)| Word {text: word, owner: 0, frame_offset: *mark.frame_offset()})
+            .collect();
+
+            //A label is a single hit, however many times it contains the query
+            let best=find_phrase(&words, &query, threshold).into_iter()
+            .map(|(_, score)| score)
+            .fold(None, |best: Option<f64>, score| Some(best.map_or(score, |best| best.max(score))));
+
+            if let Some(score)=best {
+                hits.push(SearchHit {
+                    frame_offset: *mark.frame_offset(),
+                    source: SearchSource::Mark(*id),
+                    text: label.clone(),
+                    score,
+                    });
+                }
+            }
+        }
+
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.frame_offset.cmp(&b.frame_offset)));
+
+    if let Some(limit)=options.limit {
+        hits.truncate(limit);
+        }
+
+    hits
+    }
+
+/// Finds the runs of words matching the query, returning their starts and scores. Overlapping matches are reduced to the best one.
+fn find_phrase(words: &[Word], query: &[String], threshold: f64) -> Vec<(usize, f64)> {
+    if words.len()<query.len() {
+        return Vec::new();
+        }
+
+    let mut candidates: Vec<(usize, f64)>=(0..=words.len()-query.len())
+    .filter_map(|start| {
+        let score=words[start..start+query.len()].iter()
+        .zip(query)
+        .map(|(word, query_word)| similarity(&word.text, query_word))
+        .sum::<f64>()/query.len() as f64;
+
+        (score>=threshold).then_some((start, score))
+        })
+    .collect();
+
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut accepted: Vec<(usize, f64)>=Vec::new();
+    for (start, score) in candidates {
+        if accepted.iter().all(|(accepted_start, _)| start+query.len()<=*accepted_start || accepted_start+query.len()<=start) {
+            accepted.push((start, score));
+            }
+        }
+
+    accepted
+    }
+
+/// Splits the text into lowercase words without punctuation, returning them with their character offsets.
+fn normalized_words(text: &str) -> Vec<(String, usize)> {
+    let mut words: Vec<(String, usize)>=Vec::new();
+    let mut current=String::new();
+    let mut current_start=0;
+
+    for (offset, character) in text.chars().enumerate() {
+        if character.is_alphanumeric() || character=='\'' {
+            if current.is_empty() {
+                current_start=offset;
+                }
+
+            current.extend(character.to_lowercase());
+            }
+        else if !current.is_empty() {
+            words.push((std::mem::take(&mut current), current_start));
+            }
+        }
+
+    if !current.is_empty() {
+        words.push((current, current_start));
+        }
+
+    words
+    }
+
+/// Similarity of two words from 0 to 1, based on their edit distance relative to the longer one.
+fn similarity(a: &str, b: &str) -> f64 {
+    if a==b {
+        return 1.0;
+        }
+
+    let a: Vec<char>=a.chars().collect();
+    let b: Vec<char>=b.chars().collect();
+
+    let mut previous: Vec<usize>=(0..=b.len()).collect();
+    let mut current: Vec<usize>=vec![0; b.len()+1];
+
+    for i in 1..=a.len() {
+        current[0]=i;
+
+        for j in 1..=b.len() {
+            let substitution=previous[j-1]+usize::from(a[i-1]!=b[j-1]);
+
+            current[j]=substitution.min(previous[j]+1).min(current[j-1]+1);
+            }
+
+        std::mem::swap(&mut previous, &mut current);
+        }
+
+    1.0-previous[b.len()] as f64/a.len().max(b.len()) as f64
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn test_transcript() -> Vec<TranscriptSegment> {
+        vec![
+            TranscriptSegment::new(0, 100, "Today we define a Hilbert space."),
+            TranscriptSegment::new(100, 200, "Every Hilbert"),
+            TranscriptSegment::new(200, 300, "space is a Banach space."),
+            ]
+        }
+
+    #[test]
+    fn transcript_search_test() {
+        let transcript=test_transcript();
+
+        let hits=search(&transcript, &[], "hilbert SPACE", &SearchOptions::new());
+        assert_eq!(hits.len(), 2);
+        assert_eq!(*hits[0].source(), SearchSource::Transcript);
+        assert_eq!(*hits[0].score(), 1.0);
+
+        //The word position is interpolated within the segment
+        assert_eq!(*hits[0].frame_offset(), 56);
+        assert_eq!(hits[0].text(), "Today we define a Hilbert space.");
+
+        //The phrase split between two segments is found at its start
+        assert_eq!(*hits[1].frame_offset(), 146);
+
+        //Misheard words are only found by fuzzy search
+        assert!(search(&transcript, &[], "Hilbret spaces", &SearchOptions::new()).is_empty());
+        let hits=search(&transcript, &[], "Hilbret spaces", &SearchOptions::new().with_fuzzy_matching());
+        assert_eq!(hits.len(), 2);
+        assert!(*hits[0].score()<1.0);
+
+        assert!(search(&transcript, &[], " ?! ", &SearchOptions::new()).is_empty());
+        }
+
+    #[test]
+    fn unified_search_test() {
+        let transcript=test_transcript();
+        let mut marks=crate::MarkManager::new();
+        marks.add(Mark::new(150, 1, Some("Hilbert spaces".to_string())));
+        marks.add(Mark::new(250, 2, None));
+        marks.add(Mark::new(260, 1, Some("Banach".to_string())));
+
+        let hits=search(&transcript, marks.get_mark_list(), "Hilbert space", &SearchOptions::new().with_fuzzy_matching());
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits.iter().filter(|hit| matches!(hit.source(), SearchSource::Mark(_))).count(), 1);
+
+        //Exact hits go first, then the fuzzy ones
+        assert_eq!(*hits[2].frame_offset(), 150);
+        assert_eq!(hits[2].text(), "Hilbert spaces");
+
+        let hits=search(&transcript, marks.get_mark_list(), "banach", &SearchOptions::new().marks_only());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(*hits[0].frame_offset(), 260);
+
+        let hits=search(&transcript, marks.get_mark_list(), "space", &SearchOptions::new().with_limit(2));
+        assert_eq!(hits.len(), 2);
+        }
+    }
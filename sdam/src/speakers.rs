@@ -0,0 +1,474 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use derive_getters::Getters;
+
+use serde::{Serialize, Deserialize};
+
+use opus::Decoder;
+
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+
+use crate::envelope::Envelope;
+use crate::{frame_offset_to_duration, AudioContainer, FRAME_SIZE, SAMPLING_RATE};
+
+/// Samples of the spectrum of a frame, the frame padded with zeros.
+const FFT_SIZE: usize=2048;
+/// Mel bands the spectrum of a frame is summarized in.
+const BANDS: usize=24;
+/// The frequency range of the bands, covering the speech.
+const LOWEST_FREQUENCY: f32=100.0;
+const HIGHEST_FREQUENCY: f32=8000.0;
+/// Cepstral coefficients describing the voice, without the first one, which is just the loudness.
+const COEFFICIENTS: usize=12;
+/// Frames of a window the voice is compared in, 2 seconds.
+const WINDOW_FRAMES: usize=50;
+/// The fewest frames with speech for a window to be compared.
+const MIN_VOICED_FRAMES: usize=20;
+/// The least distance of a window from the known voices, in dB, to be counted as a new speaker.
+const SPEAKER_DISTANCE: f32=3.0;
+/// The longest pause, in windows, a speaker's segment continues over, 10 seconds.
+const MAX_PAUSE_WINDOWS: usize=5;
+
+/// A range of frames spoken by one speaker.
+#[derive(Clone, Debug, PartialEq, Getters, Serialize, Deserialize)]
+pub struct SpeakerSegment {
+    start_frame: usize,
+    end_frame: usize,
+    /// Number of the speaker, starting at 1 in the order they're first heard.
+    speaker: usize,
+    }
+impl SpeakerSegment {
+
+    pub fn new(start_frame: usize, end_frame: usize, speaker: usize) -> SpeakerSegment {
+        SpeakerSegment {
+            start_frame,
+            end_frame,
+            speaker,
+            }
+        }
+
+    pub fn label(&self) -> String {
+        format!("Speaker {}", self.speaker)
+        }
+    pub fn start(&self) -> Duration {
+        frame_offset_to_duration(self.start_frame)
+        }
+    pub fn end(&self) -> Duration {
+        frame_offset_to_duration(self.end_frame)
+        }
+    }
+
+/// Who speaks when in a document, as found by the speaker detection.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Diarization {
+    segments: Vec<SpeakerSegment>,
+    }
+impl Diarization {
+
+    pub(crate) fn new() -> Diarization {
+        Diarization {
+            segments: Vec::new(),
+            }
+        }
+
+    pub(crate) fn segments(&self) -> &[SpeakerSegment] {
+        &self.segments
+        }
+    pub(crate) fn set_segments(&mut self, segments: Vec<SpeakerSegment>) {
+        self.segments=segments;
+        }
+
+    pub(crate) fn segment_at(&self, frame: usize) -> Option<&SpeakerSegment> {
+        self.segments.iter()
+        .find(|segment| segment.start_frame<=frame && frame<segment.end_frame)
+        }
+    /// The speaker speaking the most, usually the lecturer.
+    pub(crate) fn main_speaker(&self) -> Option<usize> {
+        let speakers=self.segments.iter().map(|segment| segment.speaker).max()?;
+
+        (1..=speakers)
+        .max_by_key(|speaker| {
+            let speech: usize=self.segments.iter()
+            .filter(|segment| segment.speaker==*speaker)
+            .map(|segment| segment.end_frame-segment.start_frame)
+            .sum();
+
+            //The first speaker wins ties
+            (speech, std::cmp::Reverse(*speaker))
+            })
+        }
+    /// The first segment after the given frame spoken by anyone but the main speaker, e.g. a question from the audience.
+    pub(crate) fn next_question(&self, frame: usize) -> Option<&SpeakerSegment> {
+        let main_speaker=self.main_speaker()?;
+
+        self.segments.iter()
+        .find(|segment| segment.start_frame>frame && segment.speaker!=main_speaker)
+        }
+
+    /// Follows a removal of the given frames, cutting the segments overlapping them.
+    pub(crate) fn remove_range(&mut self, start_frame: usize, end_frame: usize) {
+        let removed=end_frame-start_frame;
+        let shift=|frame: usize| if frame>=end_frame { frame-removed } else if frame>start_frame { start_frame } else { frame };
+
+        for segment in &mut self.segments {
+            segment.start_frame=shift(segment.start_frame);
+            segment.end_frame=shift(segment.end_frame);
+            }
+
+        self.segments.retain(|segment| segment.start_frame<segment.end_frame);
+        }
+    /// Follows an insertion of frames at the given one. Nobody is known to speak in the inserted audio, so a segment it falls into is split.
+    pub(crate) fn insert(&mut self, start_frame: usize, frames: usize) {
+        let mut segments: Vec<SpeakerSegment>=Vec::new();
+
+        for segment in &self.segments {
+            if segment.end_frame<=start_frame {
+                segments.push(segment.clone());
+                }
+            else if segment.start_frame>=start_frame {
+                segments.push(SpeakerSegment::new(segment.start_frame+frames, segment.end_frame+frames, segment.speaker));
+                }
+            else {
+                segments.push(SpeakerSegment::new(segment.start_frame, start_frame, segment.speaker));
+                segments.push(SpeakerSegment::new(start_frame+frames, segment.end_frame+frames, segment.speaker));
+                }
+            }
+
+        self.segments=segments;
+        }
+    /// Appends the segments of another document joined at the given frame. Its speakers are numbered after the existing ones, as the detection can't tell whether they're the same people.
+    pub(crate) fn append(&mut self, other: Diarization, frame_offset: usize) {
+        let speaker_offset=self.segments.iter().map(|segment| segment.speaker).max().unwrap_or(0);
+
+        for segment in other.segments {
+            self.segments.push(SpeakerSegment::new(segment.start_frame+frame_offset, segment.end_frame+frame_offset, segment.speaker+speaker_offset));
+            }
+        }
+    }
+
+/// Result of the speaker detection, sent back to the audio handler.
+#[derive(Message)]
+#[rtype(result="()")]
+pub(crate) struct SpeakersDetected {
+    /// Revision of the audio the detection ran on. Results for audio edited since are discarded.
+    pub(crate) revision: u64,
+    pub(crate) result: Result<Vec<SpeakerSegment>, anyhow::Error>,
+    }
+
+/// Runs the speaker detection on a background thread, as it decodes the whole recording.
+pub(crate) fn start_detection(audio: AudioContainer, envelope: Envelope, revision: u64, recipient: Recipient<SpeakersDetected>) {
+    std::thread::spawn(move || {
+        let result=detect_speakers(&audio, &envelope);
+
+        recipient.do_send(SpeakersDetected {
+            revision,
+            result,
+            });
+        });
+    }
+
+/// Finds the speaker turns in the audio. Only the frames the envelope doesn't consider silent are analysed.
+fn detect_speakers(audio: &AudioContainer, envelope: &Envelope) -> Result<Vec<SpeakerSegment>, anyhow::Error> {
+    let mut decoder=Decoder::new(SAMPLING_RATE, opus::Channels::Mono)?;
+    let mut decoding_buffer=vec![0_i16; 2*FRAME_SIZE];
+    let mut extractor=FeatureExtractor::new();
+    let mut features: Vec<Option<Vec<f32>>>=Vec::with_capacity(audio.len());
+
+    for id in 0..audio.len() {
+        //Every frame is decoded, as Opus frames depend on the preceding ones
+        let decoded_samples=decoder.decode(audio.frame_data(id).unwrap_or(&[]), &mut decoding_buffer, false)?;
+
+        if id<envelope.len() && !envelope.is_silent(id) {
+            features.push(Some(extractor.features(&decoding_buffer[..decoded_samples])));
+            }
+        else {
+            features.push(None);
+            }
+        }
+
+    Ok(segment_speakers(&features))
+    }
+
+/// Describes the voice in a frame by its mel cepstrum.
+struct FeatureExtractor {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    /// Weights of the FFT bins for every band, as the first bin and the weights from it.
+    filters: Vec<(usize, Vec<f32>)>,
+    time_buffer: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    }
+impl FeatureExtractor {
+
+    fn new() -> FeatureExtractor {
+        let fft=RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let spectrum=fft.make_output_vec();
+
+        let window: Vec<f32>=(0..FRAME_SIZE)
+        .map(|i| 0.5-0.5*(2.0*std::f32::consts::PI*i as f32/FRAME_SIZE as f32).cos())
+        .collect();
+
+        //Triangular filters spaced evenly on the mel scale
+        let to_mel=|frequency: f32| 2595.0*(1.0+frequency/700.0).log10();
+        let from_mel=|mel: f32| 700.0*(10_f32.powf(mel/2595.0)-1.0);
+        let bin_width=SAMPLING_RATE as f32/FFT_SIZE as f32;
+        let edges: Vec<f32>=(0..BANDS+2)
+        .map(|i| from_mel(to_mel(LOWEST_FREQUENCY)+(to_mel(HIGHEST_FREQUENCY)-to_mel(LOWEST_FREQUENCY))*i as f32/(BANDS+1) as f32)/bin_width)
+        .collect();
+        let filters: Vec<(usize, Vec<f32>)>=edges.windows(3)
+        .map(|edges| {
+            let (low, center, high)=(edges[0], edges[1], edges[2]);
+            let first_bin=low.ceil() as usize;
+            let weights: Vec<f32>=(first_bin..=high.floor() as usize)
+            .map(|bin| {
+                let bin=bin as f32;
+
+                if bin<=center { (bin-low)/(center-low) } else { (high-bin)/(high-center) }
+                })
+            .collect();
+
+            (first_bin, weights)
+            })
+        .collect();
+
+        FeatureExtractor {
+            fft,
+            window,
+            filters,
+            time_buffer: vec![0.0; FFT_SIZE],
+            spectrum,
+            }
+        }
+
+    /// The cepstral coefficients of the samples of a frame, in dB.
+    fn features(&mut self, samples: &[i16]) -> Vec<f32> {
+        self.time_buffer.fill(0.0);
+        for ((buffer, sample), window) in self.time_buffer.iter_mut().zip(samples).zip(&self.window) {
+            *buffer=*sample as f32/32768.0*window;
+            }
+
+        if self.fft.process(&mut self.time_buffer, &mut self.spectrum).is_err() {
+            return vec![0.0; COEFFICIENTS];
+            }
+
+        let band_levels: Vec<f32>=self.filters.iter()
+        .map(|(first_bin, weights)| {
+            let energy: f32=weights.iter().enumerate()
+            .map(|(i, weight)| weight*self.spectrum[first_bin+i].norm_sqr())
+            .sum();
+
+            10.0*energy.max(1e-10).log10()
+            })
+        .collect();
+
+        //The discrete cosine transform decorrelates the bands, its first coefficient being the overall level
+        (1..=COEFFICIENTS)
+        .map(|coefficient| {
+            let sum: f32=band_levels.iter().enumerate()
+            .map(|(band, level)| level*(std::f32::consts::PI*coefficient as f32*(band as f32+0.5)/BANDS as f32).cos())
+            .sum();
+
+            sum*(2.0/BANDS as f32).sqrt()
+            })
+        .collect()
+        }
+    }
+
+/// Groups the frames into segments of speakers, given the features of the frames with speech.
+/// The voice of every window of frames is compared with the voices heard so far, becoming a new speaker if it's unlike all of them. The windows are then assigned to the closest of the found voices, smoothing out single windows of another speaker.
+fn segment_speakers(features: &[Option<Vec<f32>>]) -> Vec<SpeakerSegment> {
+    //The mean features of windows with enough speech
+    let windows: Vec<Option<Vec<f32>>>=features.chunks(WINDOW_FRAMES)
+    .map(|window| {
+        let voiced: Vec<&Vec<f32>>=window.iter().flatten().collect();
+
+        if voiced.len()<MIN_VOICED_FRAMES {
+            return None;
+            }
+
+        Some((0..COEFFICIENTS)
+            .map(|coefficient| voiced.iter().map(|features| features[coefficient]).sum::<f32>()/voiced.len() as f32)
+            .collect())
+        })
+    .collect();
+
+    //The voices as sums of features and window counts
+    let mut voices: Vec<(Vec<f32>, usize)>=Vec::new();
+    for window in windows.iter().flatten() {
+        let closest=closest_voice(&voices, window);
+
+        match closest {
+            Some((voice, distance)) if distance<SPEAKER_DISTANCE => {
+                let (sum, count)=&mut voices[voice];
+                for (sum, value) in sum.iter_mut().zip(window) {
+                    *sum+=value;
+                    }
+                *count+=1;
+                },
+            _ => voices.push((window.clone(), 1)),
+            }
+        }
+
+    let mut labels: Vec<Option<usize>>=windows.iter()
+    .map(|window| window.as_ref().and_then(|window| closest_voice(&voices, window)).map(|(voice, _)| voice))
+    .collect();
+
+    //A single window between two of the same other voice is taken for a mistake
+    let voiced: Vec<usize>=(0..labels.len()).filter(|window| labels[*window].is_some()).collect();
+    for i in 1..voiced.len().saturating_sub(1) {
+        let (previous, current, next)=(labels[voiced[i-1]], labels[voiced[i]], labels[voiced[i+1]]);
+
+        if previous==next && current!=previous {
+            labels[voiced[i]]=previous;
+            }
+        }
+
+    //Speakers are numbered in the order they're first heard
+    let mut numbers: Vec<usize>=Vec::new();
+    let mut segments: Vec<SpeakerSegment>=Vec::new();
+    let mut last_voiced_window: Option<usize>=None;
+
+    for (window, label) in labels.iter().enumerate() {
+        let Some(voice)=label else {
+            continue;
+            };
+
+        let speaker=match numbers.iter().position(|known| known==voice) {
+            Some(index) => index+1,
+            None => {
+                numbers.push(*voice);
+                numbers.len()
+                },
+            };
+
+        let start_frame=window*WINDOW_FRAMES;
+        let end_frame=std::cmp::min(start_frame+WINDOW_FRAMES, features.len());
+
+        match segments.last_mut() {
+            Some(last) if last.speaker==speaker && last_voiced_window.map(|last| window-last<=MAX_PAUSE_WINDOWS).unwrap_or(false) => last.end_frame=end_frame,
+            _ => segments.push(SpeakerSegment::new(start_frame, end_frame, speaker)),
+            }
+
+        last_voiced_window=Some(window);
+        }
+
+    segments
+    }
+
+/// The index of the voice closest to the features and their distance, the root mean square of the differences of the coefficients.
+fn closest_voice(voices: &[(Vec<f32>, usize)], features: &[f32]) -> Option<(usize, f32)> {
+    voices.iter().enumerate()
+    .map(|(index, (sum, count))| {
+        let square_sum: f32=sum.iter().zip(features)
+        .map(|(sum, value)| (sum/ *count as f32-value).powi(2))
+        .sum();
+
+        (index, (square_sum/COEFFICIENTS as f32).sqrt())
+        })
+    .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Deterministic noise from -1 to 1.
+    fn noise(state: &mut u32) -> f32 {
+        *state=state.wrapping_mul(1664525).wrapping_add(1013904223);
+
+        *state as f32/u32::MAX as f32*2.0-1.0
+        }
+
+    /// A frame of a vowel-like sound with the given pitch and a resonance around the given frequency, changing from frame to frame like the vowels do, plus some noise.
+    fn voice_frame(frame: usize, pitch: f32, resonance: f32, state: &mut u32) -> Vec<i16> {
+        let resonance=resonance*(1.0+0.3*noise(state));
+
+        (0..FRAME_SIZE)
+        .map(|i| {
+            let t=(frame*FRAME_SIZE+i) as f32/SAMPLING_RATE as f32;
+            let mut value=0.0;
+
+            let mut harmonic=pitch;
+            while harmonic<HIGHEST_FREQUENCY {
+                //The harmonics near the resonance are the loudest
+                let amplitude=1.0/(1.0+((harmonic-resonance)/300.0).powi(2));
+                value+=amplitude*(2.0*std::f32::consts::PI*harmonic*t).sin();
+                harmonic+=pitch;
+                }
+
+            ((value*0.1+noise(state)*0.01)*32767.0).clamp(-32768.0, 32767.0) as i16
+            })
+        .collect()
+        }
+
+    #[test]
+    fn speaker_segmentation_test() {
+        let mut extractor=FeatureExtractor::new();
+        let mut state: u32=12345;
+
+        //A lecturer, a question, a pause and the answer, then another question
+        let turns: &[(usize, Option<(f32, f32)>)]=&[
+            (500, Some((120.0, 700.0))),
+            (200, Some((180.0, 1200.0))),
+            (100, None),
+            (500, Some((120.0, 700.0))),
+            (150, Some((180.0, 1200.0))),
+            ];
+
+        let mut features: Vec<Option<Vec<f32>>>=Vec::new();
+        for (frames, voice) in turns {
+            for _ in 0..*frames {
+                let frame=features.len();
+
+                features.push(voice.map(|(pitch, resonance)| extractor.features(&voice_frame(frame, pitch, resonance, &mut state))));
+                }
+            }
+
+        let segments=segment_speakers(&features);
+        assert_eq!(segments, vec![
+            SpeakerSegment::new(0, 500, 1),
+            SpeakerSegment::new(500, 700, 2),
+            SpeakerSegment::new(800, 1300, 1),
+            SpeakerSegment::new(1300, 1450, 2),
+            ]);
+        assert_eq!(segments[1].label(), "Speaker 2");
+        }
+
+    #[test]
+    fn diarization_test() {
+        let mut diarization=Diarization::new();
+        diarization.set_segments(vec![
+            SpeakerSegment::new(0, 500, 1),
+            SpeakerSegment::new(500, 700, 2),
+            SpeakerSegment::new(800, 1300, 1),
+            SpeakerSegment::new(1300, 1450, 3),
+            ]);
+
+        assert_eq!(diarization.main_speaker(), Some(1));
+        assert_eq!(diarization.segment_at(600).map(|segment| *segment.speaker()), Some(2));
+        assert_eq!(diarization.segment_at(750), None);
+        assert_eq!(diarization.next_question(0).map(|segment| *segment.start_frame()), Some(500));
+        assert_eq!(diarization.next_question(500).map(|segment| *segment.start_frame()), Some(1300));
+        assert_eq!(diarization.next_question(1300), None);
+
+        //Edits cut and shift the segments
+        diarization.remove_range(400, 800);
+        assert_eq!(diarization.segments(), &[
+            SpeakerSegment::new(0, 400, 1),
+            SpeakerSegment::new(400, 900, 1),
+            SpeakerSegment::new(900, 1050, 3),
+            ]);
+        diarization.insert(200, 100);
+        assert_eq!(diarization.segments(), &[
+            SpeakerSegment::new(0, 200, 1),
+            SpeakerSegment::new(300, 500, 1),
+            SpeakerSegment::new(500, 1000, 1),
+            SpeakerSegment::new(1000, 1150, 3),
+            ]);
+        }
+    }
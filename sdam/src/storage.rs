@@ -0,0 +1,316 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(not(windows))]
+use memmap2::Mmap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{Diarization, Envelope, MarkManager, MarkSuggestions, Notes, SdamFileModel, Transcript};
+
+// The indexed file layout is:
+// magic, version (u32), metadata length (u64), metadata (MessagePack), frame count (u64), frame count + 1 data offsets (u64), frame data
+// All integers are little-endian, offsets are relative to the start of frame data.
+// Keeping the metadata in front and the frames indexed lets documents open without reading the audio.
+const MAGIC: &[u8; 4]=b"SDAM";
+const VERSION: u32=2;
+
+/// Everything stored in a document except for the audio.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Metadata {
+    pub(crate) marks: MarkManager,
+    pub(crate) notes: Notes,
+    #[serde(default)]
+    pub(crate) envelope: Envelope,
+    /// The loudness in dBFS the playback of the document is normalized to, if any.
+    #[serde(default)]
+    pub(crate) loudness_target: Option<f32>,
+    #[serde(default)]
+    pub(crate) noise_suppression: bool,
+    #[serde(default)]
+    pub(crate) transcript: Transcript,
+    #[serde(default)]
+    pub(crate) suggestions: MarkSuggestions,
+    #[serde(default)]
+    pub(crate) speakers: Diarization,
+    }
+impl Metadata {
+
+    pub(crate) fn new(marks: MarkManager, notes: Notes) -> Metadata {
+        Metadata {
+            marks,
+            notes,
+            envelope: Envelope::new(),
+            loudness_target: None,
+            noise_suppression: false,
+            transcript: Transcript::new(),
+            suggestions: MarkSuggestions::new(),
+            speakers: Diarization::new(),
+            }
+        }
+
+    /// Cuts the data following the audio down to the given range of frames, rebased to its start, for a clip of a document of the given length. The marks and notes are left to the caller.
+    pub(crate) fn clip(&mut self, start_frame: usize, end_frame: usize, frame_count: usize) {
+        for (start, end) in [(end_frame, frame_count), (0, start_frame)] {
+            if start<end {
+                self.envelope.remove_range(start, end);
+                self.transcript.remove_range(start, end);
+                self.suggestions.remove_range(start, end);
+                self.speakers.remove_range(start, end);
+                }
+            }
+        }
+    /// Appends the data following the audio of another document joined at the given frame. The marks and notes are left to the caller, the playback settings of this document are kept.
+    pub(crate) fn append(&mut self, other: Metadata, frame_offset: usize) {
+        self.envelope.append(other.envelope, frame_offset);
+        self.transcript.append(other.transcript, frame_offset);
+        self.suggestions.append(other.suggestions, frame_offset);
+        self.speakers.append(other.speakers, frame_offset);
+        }
+    }
+
+/// Documents saved by older versions, stored as a single MessagePack structure.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct LegacyDocument {
+    audio: Vec<Vec<u8>>,
+    marks: MarkManager,
+    text: String,
+    /// Anchored version of the text. Missing in files saved by the oldest versions, in which case the notes are reconstructed from the plain text.
+    #[serde(default)]
+    notes: Option<Notes>,
+    }
+impl LegacyDocument {
+
+    pub(crate) fn into_model(self) -> SdamFileModel {
+        let notes=match self.notes {
+            Some(notes) if notes.text()==self.text => notes,
+            _ => Notes::from_text(&self.text),
+            };
+
+        SdamFileModel::new(self.audio, self.marks, notes)
+        }
+    }
+
+/// Contents of an indexed document file. They're mapped on Unix, where saving can move a new file over a mapped one.
+/// Windows refuses to replace a mapped file, and the frames may be still shared with the edit history or background jobs when saving, so the map couldn't be released in time. The file is read into memory there instead.
+#[cfg(not(windows))]
+type FileData=Mmap;
+#[cfg(windows)]
+type FileData=Vec<u8>;
+
+/// Frames of an indexed document file, read on demand from a memory map.
+pub(crate) struct MappedFrames {
+    data: FileData,
+    frame_count: usize,
+    index_start: usize,
+    data_start: usize,
+    }
+impl MappedFrames {
+
+    pub fn len(&self) -> usize {
+        self.frame_count
+        }
+
+    /// Returns the data of the given frame. Frames which don't fit into the file, e.g. because it was corrupted, are returned empty, what the decoder handles as a lost packet.
+    pub fn frame_data(&self, id: usize) -> &[u8] {
+        if id>=self.frame_count {
+            return &[];
+            }
+
+        let start=self.data_start as u64+self.read_offset(id);
+        let end=self.data_start as u64+self.read_offset(id+1);
+
+        if start>end || end>self.data.len() as u64 {
+            return &[];
+            }
+
+        &self.data[start as usize..end as usize]
+        }
+
+    fn read_offset(&self, index: usize) -> u64 {
+        let position=self.index_start+8*index;
+
+        u64::from_le_bytes(self.data[position..position+8].try_into().unwrap())
+        }
+    }
+impl std::fmt::Debug for MappedFrames {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedFrames")
+        .field("frame_count", &self.frame_count)
+        .finish()
+        }
+    }
+
+pub(crate) enum StoredDocument {
+    /// Documents saved by older versions, stored as a single MessagePack structure, which needs to be loaded in full.
+    Legacy(SdamFileModel),
+    Indexed {metadata: Metadata, frames: Arc<MappedFrames>},
+    }
+
+pub(crate) fn open(path: &Path) -> Result<StoredDocument, anyhow::Error> {
+    let mut file=File::open(path)?;
+
+    let mut magic=[0_u8; 4];
+    if file.read_exact(&mut magic).is_err() || &magic!=MAGIC {
+        let mut serialized: Vec<u8>=Vec::new();
+        file=File::open(path)?;
+        file.read_to_end(&mut serialized)?;
+
+        let document: LegacyDocument=rmp_serde::from_slice(&serialized)?;
+
+        return Ok(StoredDocument::Legacy(document.into_model()));
+        }
+
+    // Safety: the map is read-only and this program never modifies a document in place, saving writes a new file moved over the old one, which keeps the mapped data alive on Unix.
+    // Should another program truncate the file while it's open, reading the frames past the new end raises SIGBUS and ends the program, which can't be guarded against without giving up the map.
+    #[cfg(not(windows))]
+    let data=unsafe { Mmap::map(&file)? };
+    #[cfg(windows)]
+    let data=fs::read(path)?;
+
+    let mut cursor=4;
+    let version=u32::from_le_bytes(read_bytes(&data, &mut cursor, 4)?.try_into().unwrap());
+    if version!=VERSION {
+        anyhow::bail!("Unsupported document version {version}");
+        }
+
+    let metadata_len=u64::from_le_bytes(read_bytes(&data, &mut cursor, 8)?.try_into().unwrap()) as usize;
+    let metadata: Metadata=rmp_serde::from_slice(read_bytes(&data, &mut cursor, metadata_len)?)?;
+
+    let frame_count=u64::from_le_bytes(read_bytes(&data, &mut cursor, 8)?.try_into().unwrap()) as usize;
+    let index_start=cursor;
+    read_bytes(&data, &mut cursor, frame_count.checked_add(1).and_then(|count| count.checked_mul(8)).unwrap_or(usize::MAX))?;
+    let data_start=cursor;
+
+    let frames=MappedFrames {
+        data,
+        frame_count,
+        index_start,
+        data_start,
+        };
+
+    if data_start as u64+frames.read_offset(frame_count)>frames.data.len() as u64 {
+        anyhow::bail!("The document is truncated");
+        }
+
+    Ok(StoredDocument::Indexed {metadata, frames: Arc::new(frames)})
+    }
+
+/// Writes a document in the indexed format. The data is written to a temporary file first and moved over the target afterwards, so the document being replaced can be still mapped while saving, see FileData.
+pub(crate) fn write<'a>(path: &Path, metadata: &Metadata, frame_count: usize, frame_data: impl Fn(usize) -> &'a [u8]) -> Result<(), anyhow::Error> {
+    let mut temporary_name=path.file_name().unwrap_or_default().to_os_string();
+    temporary_name.push(".tmp");
+    let temporary_path=path.with_file_name(temporary_name);
+
+    let result=(|| {
+        let mut writer=BufWriter::new(File::create(&temporary_path)?);
+
+        let serialized_metadata=rmp_serde::to_vec(metadata)?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(serialized_metadata.len() as u64).to_le_bytes())?;
+        writer.write_all(&serialized_metadata)?;
+        writer.write_all(&(frame_count as u64).to_le_bytes())?;
+
+        let mut offset: u64=0;
+        writer.write_all(&offset.to_le_bytes())?;
+        for id in 0..frame_count {
+            offset+=frame_data(id).len() as u64;
+            writer.write_all(&offset.to_le_bytes())?;
+            }
+
+        for id in 0..frame_count {
+            writer.write_all(frame_data(id))?;
+            }
+
+        writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+
+        fs::rename(&temporary_path, path)?;
+
+        Ok(())
+        })();
+
+    if result.is_err() {
+        let _=fs::remove_file(&temporary_path);
+        }
+
+    result
+    }
+
+fn read_bytes<'a>(data: &'a [u8], cursor: &mut usize, len: usize) -> Result<&'a [u8], anyhow::Error> {
+    let end=cursor.checked_add(len)
+    .filter(|end| *end<=data.len())
+    .ok_or_else(|| anyhow::anyhow!("The document is truncated"))?;
+
+    let result=&data[*cursor..end];
+    *cursor=end;
+
+    Ok(result)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::Mark;
+
+    #[test]
+    fn indexed_document_test() {
+        let mut marks=MarkManager::new();
+        marks.add(Mark::new(1, 1, None));
+        let metadata=Metadata::new(marks, Notes::from_text("Notes"));
+        let frames: Vec<Vec<u8>>=vec![vec![1, 2], vec![], vec![3, 4, 5]];
+
+        let path=std::env::temp_dir().join("sdam_indexed_document_test.sdam");
+        write(&path, &metadata, frames.len(), |id| &frames[id][..]).unwrap();
+
+        match open(&path).unwrap() {
+            StoredDocument::Indexed {metadata, frames} => {
+                assert_eq!(metadata.marks.get_mark_list().len(), 1);
+                assert_eq!(metadata.notes.text(), "Notes");
+                assert_eq!(frames.len(), 3);
+                assert_eq!(frames.frame_data(0), &[1, 2]);
+                assert_eq!(frames.frame_data(1), &[] as &[u8]);
+                assert_eq!(frames.frame_data(2), &[3, 4, 5]);
+                assert_eq!(frames.frame_data(3), &[] as &[u8]);
+                },
+            StoredDocument::Legacy(_) => panic!("Indexed document opened as legacy"),
+            }
+
+        //Cutting off the last frame's data must be detected
+        let data=fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len()-1]).unwrap();
+        assert!(open(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+        }
+
+    #[test]
+    fn legacy_document_test() {
+        let notes=Notes::from_text("Legacy");
+        let document=LegacyDocument {
+            audio: vec![vec![7]],
+            marks: MarkManager::new(),
+            text: notes.text(),
+            notes: Some(notes),
+            };
+
+        let path=std::env::temp_dir().join("sdam_legacy_document_test.sdam");
+        fs::write(&path, rmp_serde::to_vec(&document).unwrap()).unwrap();
+
+        match open(&path).unwrap() {
+            StoredDocument::Legacy(model) => {
+                assert_eq!(model.audio(), &vec![vec![7]]);
+                assert_eq!(model.notes().text(), "Legacy");
+                },
+            StoredDocument::Indexed {..} => panic!("Legacy document opened as indexed"),
+            }
+
+        fs::remove_file(&path).unwrap();
+        }
+    }
@@ -0,0 +1,288 @@
+use std::time::Duration;
+
+use derive_getters::Getters;
+
+use serde::{Serialize, Deserialize};
+
+use crate::envelope::Envelope;
+use crate::search::{self, SearchOptions};
+use crate::transcription::TranscriptSegment;
+use crate::{frame_offset_to_duration, Mark};
+
+/// The shortest pause, in frames, suggesting a new topic, 2 seconds.
+const MIN_PAUSE_FRAMES: usize=50;
+/// Frames of the window the loudness of speech is compared in, 1 second.
+const EMPHASIS_WINDOW_FRAMES: usize=25;
+/// How much louder than the speech of the whole document, in dB, an emphasis is.
+const EMPHASIS_THRESHOLD: f32=6.0;
+/// The least distance, in frames, between suggestions of the same kind, and of suggestions from marks, 10 seconds.
+const MIN_SUGGESTION_DISTANCE: usize=250;
+/// Phrases by which lecturers point out what matters.
+const CUE_PHRASES: &[&str]=&[
+    "this is important",
+    "it is important",
+    "it's important",
+    "for the exam",
+    "on the exam",
+    "in the exam",
+    "remember this",
+    "don't forget",
+    "do not forget",
+    "write this down",
+    "keep in mind",
+    "the key point",
+    ];
+
+/// What a mark was suggested for.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SuggestionReason {
+    /// A long pause, likely a boundary of topics. The suggestion is where the speech continues.
+    Pause,
+    /// Speech notably louder than usual.
+    Emphasis,
+    /// A cue phrase in the transcript, such as "this is important".
+    Phrase(String),
+    }
+impl SuggestionReason {
+
+    fn same_kind(&self, other: &SuggestionReason) -> bool {
+        std::mem::discriminant(self)==std::mem::discriminant(other)
+        }
+    }
+
+/// A mark proposed by the analysis of the recording, waiting to be accepted or dismissed by the user.
+#[derive(Clone, Debug, PartialEq, Getters, Serialize, Deserialize)]
+pub struct MarkSuggestion {
+    id: u64,
+    frame_offset: usize,
+    reason: SuggestionReason,
+    /// Label for the mark, the transcript segment for phrases.
+    label: Option<String>,
+    }
+impl MarkSuggestion {
+
+    pub fn time(&self) -> Duration {
+        frame_offset_to_duration(self.frame_offset)
+        }
+    }
+
+/// Suggested marks of a document, together with the dismissed ones, so they're not suggested again.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MarkSuggestions {
+    suggestions: Vec<MarkSuggestion>,
+    dismissed: Vec<(usize, SuggestionReason)>,
+    next_id: u64,
+    }
+impl MarkSuggestions {
+
+    pub(crate) fn new() -> MarkSuggestions {
+        MarkSuggestions {
+            suggestions: Vec::new(),
+            dismissed: Vec::new(),
+            next_id: 0,
+            }
+        }
+
+    pub(crate) fn list(&self) -> &[MarkSuggestion] {
+        &self.suggestions
+        }
+
+    /// Analyzes the recording and adds new suggestions. Places close to marks, pending suggestions or dismissed ones of the same kind are skipped. Returns whether anything was added.
+    pub(crate) fn update(&mut self, envelope: &Envelope, transcript: &[TranscriptSegment], marks: &[Mark]) -> bool {
+        let near=|a: usize, b: usize| a.abs_diff(b)<MIN_SUGGESTION_DISTANCE;
+        let mut added=false;
+
+        for (frame_offset, reason, label) in analyze(envelope, transcript) {
+            let known=marks.iter().any(|mark| near(*mark.frame_offset(), frame_offset))
+            || self.suggestions.iter().any(|suggestion| suggestion.reason.same_kind(&reason) && near(suggestion.frame_offset, frame_offset))
+            || self.dismissed.iter().any(|(dismissed_frame, dismissed_reason)| dismissed_reason.same_kind(&reason) && near(*dismissed_frame, frame_offset));
+
+            if known {
+                continue;
+                }
+
+            self.suggestions.push(MarkSuggestion {
+                id: self.next_id,
+                frame_offset,
+                reason,
+                label,
+                });
+            self.next_id+=1;
+            added=true;
+            }
+
+        self.suggestions.sort_by_key(|suggestion| suggestion.frame_offset);
+
+        added
+        }
+
+    /// Removes the suggestion to turn it into a mark.
+    pub(crate) fn take(&mut self, id: u64) -> Option<MarkSuggestion> {
+        let index=self.suggestions.iter().position(|suggestion| suggestion.id==id)?;
+
+        Some(self.suggestions.remove(index))
+        }
+    pub(crate) fn dismiss(&mut self, id: u64) -> bool {
+        match self.take(id) {
+            Some(suggestion) => {
+                self.dismissed.push((suggestion.frame_offset, suggestion.reason));
+                true
+                },
+            None => false,
+            }
+        }
+
+    /// Follows a removal of the given frames, dropping suggestions in them.
+    pub(crate) fn remove_range(&mut self, start_frame: usize, end_frame: usize) {
+        let inside=|frame: usize| frame>=start_frame && frame<end_frame;
+        let shift=|frame: usize| if frame>=end_frame { frame-(end_frame-start_frame) } else { frame };
+
+        self.suggestions.retain(|suggestion| !inside(suggestion.frame_offset));
+        self.dismissed.retain(|(frame, _)| !inside(*frame));
+
+        for suggestion in &mut self.suggestions {
+            suggestion.frame_offset=shift(suggestion.frame_offset);
+            }
+        for (frame, _) in &mut self.dismissed {
+            *frame=shift(*frame);
+            }
+        }
+    /// Follows an insertion of frames at the given one.
+    pub(crate) fn insert(&mut self, start_frame: usize, frames: usize) {
+        for suggestion in &mut self.suggestions {
+            if suggestion.frame_offset>=start_frame {
+                suggestion.frame_offset+=frames;
+                }
+            }
+        for (frame, _) in &mut self.dismissed {
+            if *frame>=start_frame {
+                *frame+=frames;
+                }
+            }
+        }
+    /// Appends the suggestions of another document joined at the given frame. They get new ids, following the existing ones.
+    pub(crate) fn append(&mut self, other: MarkSuggestions, frame_offset: usize) {
+        for mut suggestion in other.suggestions {
+            suggestion.id=self.next_id;
+            suggestion.frame_offset+=frame_offset;
+            self.suggestions.push(suggestion);
+            self.next_id+=1;
+            }
+        for (frame, reason) in other.dismissed {
+            self.dismissed.push((frame+frame_offset, reason));
+            }
+        }
+    }
+
+/// Finds places worth a mark in the recording, returning their frames, reasons and labels.
+fn analyze(envelope: &Envelope, transcript: &[TranscriptSegment]) -> Vec<(usize, SuggestionReason, Option<String>)> {
+    let mut found: Vec<(usize, SuggestionReason, Option<String>)>=Vec::new();
+
+    //Pauses, except for the silence before the recording gets going
+    let mut pause_start: Option<usize>=None;
+    for frame in 0..envelope.len() {
+        if envelope.is_silent(frame) {
+            pause_start.get_or_insert(frame);
+            continue;
+            }
+
+        if let Some(start)=pause_start.take() {
+            if start>0 && frame-start>=MIN_PAUSE_FRAMES {
+                found.push((frame, SuggestionReason::Pause, None));
+                }
+            }
+        }
+
+    //Emphasis, in steps of half a window
+    if let Some(speech_loudness)=envelope.integrated_loudness() {
+        let mut last_emphasis: Option<usize>=None;
+        let mut frame=0;
+
+        while frame+EMPHASIS_WINDOW_FRAMES<=envelope.len() {
+            let loud=envelope.loudness(frame, frame+EMPHASIS_WINDOW_FRAMES)
+            .map(|loudness| loudness>speech_loudness+EMPHASIS_THRESHOLD)
+            .unwrap_or(false);
+
+            if loud && last_emphasis.map(|last| frame-last>=MIN_SUGGESTION_DISTANCE).unwrap_or(true) {
+                found.push((frame, SuggestionReason::Emphasis, None));
+                last_emphasis=Some(frame);
+                }
+
+            frame+=EMPHASIS_WINDOW_FRAMES/2;
+            }
+        }
+
+    //Cue phrases
+    for phrase in CUE_PHRASES {
+        for hit in search::search(transcript, &[], phrase, &SearchOptions::new().transcript_only()) {
+            found.push((*hit.frame_offset(), SuggestionReason::Phrase(phrase.to_string()), Some(hit.text().clone())));
+            }
+        }
+
+    found.sort_by_key(|(frame, _, _)| *frame);
+
+    found
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// A minute of speech at -20 dBFS, with a pause of 3 seconds at 20 seconds and an emphasis of 2 seconds at 40 seconds.
+    fn test_envelope() -> Envelope {
+        let rms: Vec<u16>=(0..1500)
+        .map(|frame| match frame {
+            500..=574 => 0,
+            1000..=1049 => 16000,
+            _ => 3277,
+            })
+        .collect();
+
+        Envelope::from_rms(rms)
+        }
+
+    #[test]
+    fn analysis_test() {
+        let transcript=vec![
+            TranscriptSegment::new(1200, 1300, "And this is important for the exam."),
+            ];
+
+        let found=analyze(&test_envelope(), &transcript);
+        assert_eq!(found, vec![
+            (575, SuggestionReason::Pause, None),
+            (984, SuggestionReason::Emphasis, None),
+            (1211, SuggestionReason::Phrase("this is important".to_string()), Some("And this is important for the exam.".to_string())),
+            (1262, SuggestionReason::Phrase("for the exam".to_string()), Some("And this is important for the exam.".to_string())),
+            ]);
+        }
+
+    #[test]
+    fn suggestions_test() {
+        let envelope=test_envelope();
+        let mut suggestions=MarkSuggestions::new();
+
+        //Places near marks aren't suggested
+        let marks=vec![Mark::new(990, 1, None)];
+        assert!(suggestions.update(&envelope, &[], &marks));
+        assert_eq!(suggestions.list().len(), 1);
+        let pause_id=*suggestions.list()[0].id();
+
+        //Neither are pending or dismissed ones again
+        assert!(!suggestions.update(&envelope, &[], &marks));
+        assert!(suggestions.dismiss(pause_id));
+        assert!(!suggestions.dismiss(pause_id));
+        assert!(!suggestions.update(&envelope, &[], &marks));
+        assert!(suggestions.list().is_empty());
+
+        assert!(suggestions.update(&envelope, &[], &[]));
+        let emphasis=suggestions.take(*suggestions.list()[0].id()).unwrap();
+        assert_eq!(*emphasis.reason(), SuggestionReason::Emphasis);
+
+        //Dismissed places follow audio edits
+        suggestions.remove_range(0, 100);
+        assert_eq!(suggestions.dismissed[0].0, 475);
+        suggestions.insert(0, 100);
+        assert_eq!(suggestions.dismissed[0].0, 575);
+        }
+    }
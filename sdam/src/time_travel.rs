@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use derive_getters::Getters;
+
+use crate::FRAME_DURATION;
+
+/// How close to the live edge, in frames, the playback counts as live.
+const LIVE_TOLERANCE_FRAMES: usize=2;
+/// How far behind the live edge, in frames, the playback has to fall to stop counting as live again.
+const LIVE_DEPARTURE_FRAMES: usize=25;
+/// Speed-up per second of delay the catch-up aims for, up to the maximum rate.
+const CATCH_UP_GAIN: f64=0.05;
+/// The most the catch-up rate changes per frame, so the speed-up is gradual.
+const CATCH_UP_RATE_STEP: f64=0.01;
+/// Frames of every pause kept when skipping silence, so speech doesn't run together.
+const KEPT_SILENT_FRAMES: usize=5;
+
+/// How the playback catches up with the live recording after falling behind it.
+#[derive(Clone, Copy, Debug, Getters)]
+pub struct CatchUpPolicy {
+    /// The fastest rate used to catch up.
+    max_rate: f64,
+    /// Whether pauses longer than a moment are skipped while catching up.
+    skip_silence: bool,
+    }
+impl CatchUpPolicy {
+
+    pub fn new(max_rate: f64) -> CatchUpPolicy {
+        CatchUpPolicy {
+            max_rate,
+            skip_silence: false,
+            }
+        }
+
+    pub fn with_silence_skipping(self) -> CatchUpPolicy {
+        CatchUpPolicy {
+            skip_silence: true,
+            ..self
+            }
+        }
+    }
+
+#[derive(Clone, Copy, Debug, Getters)]
+pub struct TimeTravelOptions {
+    /// How far behind the recording the playback stays at most, so it never runs out of audio.
+    live_latency: Duration,
+    catch_up: Option<CatchUpPolicy>,
+    }
+impl TimeTravelOptions {
+
+    pub fn new() -> TimeTravelOptions {
+        TimeTravelOptions {
+            live_latency: Duration::from_millis(3*FRAME_DURATION as u64),
+            catch_up: None,
+            }
+        }
+
+    pub fn with_live_latency(self, live_latency: Duration) -> TimeTravelOptions {
+        TimeTravelOptions {
+            live_latency,
+            ..self
+            }
+        }
+    pub fn with_catch_up(self, catch_up: CatchUpPolicy) -> TimeTravelOptions {
+        TimeTravelOptions {
+            catch_up: Some(catch_up),
+            ..self
+            }
+        }
+    }
+impl Default for TimeTravelOptions {
+
+    fn default() -> TimeTravelOptions {
+        TimeTravelOptions::new()
+        }
+    }
+
+/// State of the time travel mode, in which the playback follows the recording running in parallel.
+pub(crate) struct TimeTravel {
+    options: TimeTravelOptions,
+    /// Whether the recording was started for the time travel, and should be thus stopped with it.
+    pub(crate) started_recording: bool,
+    catch_up_rate: f64,
+    silent_frames: usize,
+    at_live: bool,
+    }
+impl TimeTravel {
+
+    pub(crate) fn new(options: TimeTravelOptions, started_recording: bool) -> TimeTravel {
+        TimeTravel {
+            options,
+            started_recording,
+            catch_up_rate: 1.0,
+            silent_frames: 0,
+            at_live: true,
+            }
+        }
+
+    pub(crate) fn catch_up_rate(&self) -> f64 {
+        self.catch_up_rate
+        }
+    /// The live latency in whole frames, rounded up.
+    pub(crate) fn latency_frames(&self) -> usize {
+        (self.options.live_latency.as_millis() as usize).div_ceil(FRAME_DURATION)
+        }
+
+    /// Adjusts the catch-up rate to the given distance from the end of the audio, in frames, and returns it.
+    pub(crate) fn update_rate(&mut self, lag_frames: usize) -> f64 {
+        let target_rate=match &self.options.catch_up {
+            Some(catch_up) if lag_frames>self.latency_frames()+LIVE_TOLERANCE_FRAMES => {
+                let delay=((lag_frames-self.latency_frames())*FRAME_DURATION) as f64/1000.0;
+
+                f64::min(1.0+delay*CATCH_UP_GAIN, f64::max(catch_up.max_rate, 1.0))
+                },
+            _ => 1.0,
+            };
+
+        self.catch_up_rate=if target_rate>self.catch_up_rate {
+            f64::min(self.catch_up_rate+CATCH_UP_RATE_STEP, target_rate)
+            }
+        else {
+            f64::max(self.catch_up_rate-CATCH_UP_RATE_STEP, target_rate)
+            };
+
+        self.catch_up_rate
+        }
+    /// Decides whether a frame at the given distance from the end of the audio is skipped. Only silent frames are, once a pause is long enough, and only while catching up.
+    pub(crate) fn skips(&mut self, silent: bool, lag_frames: usize) -> bool {
+        if !silent {
+            self.silent_frames=0;
+            return false;
+            }
+
+        self.silent_frames+=1;
+
+        let skip_silence=self.options.catch_up.map(|catch_up| catch_up.skip_silence).unwrap_or(false);
+
+        skip_silence && lag_frames>self.latency_frames()+LIVE_TOLERANCE_FRAMES && self.silent_frames>KEPT_SILENT_FRAMES
+        }
+    pub(crate) fn is_live(&self) -> bool {
+        self.at_live
+        }
+    /// Leaves the live edge right away when the playback is sought back from it, rather than waiting for the heard position to fall behind.
+    pub(crate) fn seek(&mut self, lag_frames: usize) {
+        if lag_frames>self.latency_frames()+LIVE_TOLERANCE_FRAMES {
+            self.at_live=false;
+            }
+        }
+    /// Tracks whether the playback is live, given the distance of the heard position from the end of the audio. Returns true when the playback has just rejoined the live edge.
+    pub(crate) fn update_live(&mut self, lag_frames: usize) -> bool {
+        if self.at_live {
+            if lag_frames>self.latency_frames()+LIVE_DEPARTURE_FRAMES {
+                self.at_live=false;
+                }
+
+            return false;
+            }
+
+        if lag_frames<=self.latency_frames()+LIVE_TOLERANCE_FRAMES {
+            self.at_live=true;
+            return true;
+            }
+
+        false
+        }
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn catch_up_test() {
+        let options=TimeTravelOptions::new()
+        .with_live_latency(Duration::from_millis(100))
+        .with_catch_up(CatchUpPolicy::new(1.5).with_silence_skipping());
+        let mut time_travel=TimeTravel::new(options, false);
+
+        assert_eq!(time_travel.latency_frames(), 3);
+
+        //Near the live edge, the playback runs at the normal rate
+        assert_eq!(time_travel.update_rate(5), 1.0);
+
+        //Far behind, the rate rises gradually up to the maximum
+        let rate=time_travel.update_rate(1000);
+        assert!(rate>1.0 && rate<1.02);
+        for _ in 0..100 {
+            time_travel.update_rate(1000);
+            }
+        assert_eq!(time_travel.update_rate(1000), 1.5);
+
+        //And falls gradually after catching up
+        let rate=time_travel.update_rate(3);
+        assert!(rate<1.5 && rate>1.4);
+
+        //Pauses are shortened only while behind
+        let skipped: Vec<bool>=(0..8).map(|_| time_travel.skips(true, 100)).collect();
+        assert_eq!(skipped, vec![false, false, false, false, false, true, true, true]);
+        assert!(!time_travel.skips(true, 4));
+        assert!(!time_travel.skips(false, 100));
+        assert!(!time_travel.skips(true, 100));
+        }
+
+    #[test]
+    fn rejoin_live_test() {
+        let mut time_travel=TimeTravel::new(TimeTravelOptions::new(), true);
+
+        assert!(!time_travel.update_live(10));
+        assert!(!time_travel.update_live(100));
+        assert!(!time_travel.update_live(20));
+        assert!(time_travel.update_live(5));
+        assert!(!time_travel.update_live(4));
+        assert!(time_travel.is_live());
+
+        time_travel.seek(10);
+        assert!(!time_travel.is_live());
+        assert!(time_travel.update_live(3));
+        }
+    }
@@ -0,0 +1,567 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use derive_getters::Getters;
+
+use serde::{Serialize, Deserialize};
+
+use opus::Decoder;
+
+use crate::envelope::Envelope;
+use crate::{duration_to_frame_offset, frame_offset_to_duration, AudioContainer, FRAME_SIZE, SAMPLING_RATE};
+
+/// The shortest audio, in frames, transcribed at once while recording, 10 seconds. Shorter chunks give the engine too little context.
+const MIN_CHUNK_FRAMES: usize=250;
+/// The longest audio, in frames, transcribed at once, 30 seconds, what Whisper models process in one pass.
+const MAX_CHUNK_FRAMES: usize=750;
+/// The sampling rate Whisper models expect.
+const WHISPER_SAMPLING_RATE: u32=16000;
+/// How many names are tried for the temporary input file of Whisper before giving up.
+const INPUT_FILE_ATTEMPTS: usize=100;
+
+/// Numbers the temporary input files of Whisper.
+static INPUT_COUNTER: AtomicU64=AtomicU64::new(0);
+
+/// Text spoken over a range of frames of the document.
+#[derive(Clone, Debug, PartialEq, Getters, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    start_frame: usize,
+    end_frame: usize,
+    text: String,
+    }
+impl TranscriptSegment {
+
+    pub fn new(start_frame: usize, end_frame: usize, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start_frame,
+            end_frame,
+            text: text.to_string(),
+            }
+        }
+
+    pub fn start(&self) -> Duration {
+        frame_offset_to_duration(self.start_frame)
+        }
+    pub fn end(&self) -> Duration {
+        frame_offset_to_duration(self.end_frame)
+        }
+    }
+
+/// Text recognized by a transcription engine, timed relative to the start of the audio given to it.
+#[derive(Clone, Debug, PartialEq, Getters)]
+pub struct RecognizedText {
+    start: Duration,
+    end: Duration,
+    text: String,
+    }
+impl RecognizedText {
+
+    pub fn new(start: Duration, end: Duration, text: &str) -> RecognizedText {
+        RecognizedText {
+            start,
+            end,
+            text: text.to_string(),
+            }
+        }
+    }
+
+/// A speech recognizer running locally. It's called from a background thread, with chunks of the audio of up to 30 seconds.
+pub trait TranscriptionEngine: Send {
+
+    /// Recognizes the speech in the given mono samples at 48 kHz.
+    fn transcribe(&mut self, samples: &[i16]) -> Result<Vec<RecognizedText>, anyhow::Error>;
+    }
+
+/// Transcription engine running the command line tool of whisper.cpp with a model file.
+pub struct WhisperCli {
+    executable: PathBuf,
+    model: PathBuf,
+    language: Option<String>,
+    threads: Option<usize>,
+    }
+impl WhisperCli {
+
+    pub fn new(executable: &Path, model: &Path) -> WhisperCli {
+        WhisperCli {
+            executable: executable.to_path_buf(),
+            model: model.to_path_buf(),
+            language: None,
+            threads: None,
+            }
+        }
+
+    /// Sets the spoken language as a code like "en", instead of detecting it.
+    pub fn with_language(self, language: &str) -> WhisperCli {
+        WhisperCli {
+            language: Some(language.to_string()),
+            ..self
+            }
+        }
+    pub fn with_threads(self, threads: usize) -> WhisperCli {
+        WhisperCli {
+            threads: Some(threads),
+            ..self
+            }
+        }
+
+    /// Creates a new file for the input in the temporary directory. Existing files are never opened, so other users can't make the audio be written over a file of theirs choosing, e.g. through a symbolic link, nor read it.
+    fn create_input_file() -> Result<(PathBuf, File), anyhow::Error> {
+        for _ in 0..INPUT_FILE_ATTEMPTS {
+            let path=std::env::temp_dir().join(format!("sdam_transcription_{}_{}.wav", std::process::id(), INPUT_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+            let mut options=OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            match options.open(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(error) if error.kind()==ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error.into()),
+                }
+            }
+
+        anyhow::bail!("Can't create a temporary file for the transcription")
+        }
+    fn write_input(file: File, samples: &[i16]) -> Result<(), anyhow::Error> {
+        let spec=hound::WavSpec {
+            channels: 1,
+            sample_rate: WHISPER_SAMPLING_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+            };
+        let mut writer=hound::WavWriter::new(BufWriter::new(file), spec)?;
+
+        //Averaging the samples is a sufficient low-pass filter for speech
+        let factor=(SAMPLING_RATE/WHISPER_SAMPLING_RATE) as usize;
+        for chunk in samples.chunks(factor) {
+            let sum: i32=chunk.iter().map(|sample| *sample as i32).sum();
+
+            writer.write_sample((sum/chunk.len() as i32) as i16)?;
+            }
+
+        writer.finalize()?;
+
+        Ok(())
+        }
+    }
+impl TranscriptionEngine for WhisperCli {
+
+    fn transcribe(&mut self, samples: &[i16]) -> Result<Vec<RecognizedText>, anyhow::Error> {
+        let (input_path, input_file)=Self::create_input_file()?;
+        if let Err(error)=Self::write_input(input_file, samples) {
+            let _=std::fs::remove_file(&input_path);
+            return Err(error);
+            }
+
+        let mut command=Command::new(&self.executable);
+        command.arg("-m").arg(&self.model)
+        .arg("-f").arg(&input_path)
+        .arg("-l").arg(self.language.as_deref().unwrap_or("auto"));
+        if let Some(threads)=self.threads {
+            command.arg("-t").arg(threads.to_string());
+            }
+
+        let output=command.output();
+        let _=std::fs::remove_file(&input_path);
+        let output=output?;
+
+        if !output.status.success() {
+            anyhow::bail!("Whisper failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+
+        Ok(parse_whisper_output(&String::from_utf8_lossy(&output.stdout)))
+        }
+    }
+
+/// Parses the lines "[00:00:01.000 --> 00:00:03.500]  Text" printed by whisper.cpp. Other lines are ignored.
+fn parse_whisper_output(output: &str) -> Vec<RecognizedText> {
+    fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+        let (hours_minutes, seconds)=timestamp.trim().rsplit_once(':')?;
+        let (hours, minutes)=hours_minutes.split_once(':')?;
+        let seconds=hours.parse::<f64>().ok()?*3600.0+minutes.parse::<f64>().ok()?*60.0+seconds.parse::<f64>().ok()?;
+
+        Some(Duration::from_secs_f64(seconds))
+        }
+
+    output.lines()
+    .filter_map(|line| {
+        let line=line.trim().strip_prefix('[')?;
+        let (timestamps, text)=line.split_once(']')?;
+        let (start, end)=timestamps.split_once("-->")?;
+        let text=text.trim();
+
+        if text.is_empty() {
+            return None;
+            }
+
+        Some(RecognizedText::new(parse_timestamp(start)?, parse_timestamp(end)?, text))
+        })
+    .collect()
+    }
+
+/// The transcript of a document, together with the parts of the audio it covers. Parts not covered, such as new recordings or pasted audio, are transcribed when an engine is available.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Transcript {
+    segments: Vec<TranscriptSegment>,
+    /// Sorted, disjoint ranges of transcribed frames.
+    covered: Vec<(usize, usize)>,
+    }
+impl Transcript {
+
+    pub(crate) fn new() -> Transcript {
+        Transcript {
+            segments: Vec::new(),
+            covered: Vec::new(),
+            }
+        }
+
+    pub(crate) fn segments(&self) -> &[TranscriptSegment] {
+        &self.segments
+        }
+    /// Segments overlapping the given range of frames.
+    pub(crate) fn segments_between(&self, start_frame: usize, end_frame: usize) -> Vec<TranscriptSegment> {
+        self.segments.iter()
+        .filter(|segment| segment.end_frame>start_frame && segment.start_frame<end_frame)
+        .cloned()
+        .collect()
+        }
+
+    /// Chooses the next range of frames to transcribe. The chunks end in pauses where possible.
+    /// While the audio is growing, the end of it is only transcribed once it's long enough, since it may be in the middle of a sentence.
+    pub(crate) fn next_chunk(&self, audio_len: usize, envelope: &Envelope, growing: bool) -> Option<(usize, usize)> {
+        let (gap_start, gap_end)=self.first_gap(audio_len)?;
+        let open_ended=growing && gap_end==audio_len;
+
+        let latest_end=std::cmp::min(gap_end, gap_start+MAX_CHUNK_FRAMES);
+        let pause=(gap_start+MIN_CHUNK_FRAMES..latest_end).rev()
+        .find(|frame| envelope.is_silent(*frame));
+
+        match pause {
+            Some(pause) => Some((gap_start, pause+1)),
+            None if !open_ended || gap_end-gap_start>=MAX_CHUNK_FRAMES => Some((gap_start, latest_end)),
+            None => None,
+            }
+        }
+
+    /// Adds the text recognized in the given range of frames.
+    pub(crate) fn add(&mut self, start_frame: usize, end_frame: usize, recognized: Vec<RecognizedText>) {
+        if start_frame>=end_frame {
+            return;
+            }
+
+        for text in recognized {
+            //Every segment spans at least one frame, so it can be found by its position
+            let segment_start=std::cmp::min(start_frame+duration_to_frame_offset(text.start), end_frame-1);
+            let segment_end=(start_frame+duration_to_frame_offset(text.end)).clamp(segment_start+1, end_frame);
+            let segment=TranscriptSegment::new(segment_start, segment_end, &text.text);
+
+            let index=self.segments.partition_point(|existing| existing.start_frame<=segment.start_frame);
+            self.segments.insert(index, segment);
+            }
+
+        self.cover(start_frame, end_frame);
+        }
+    /// Follows a removal of the given frames. Segments overlapping the removed range are dropped, and what remains of their audio is transcribed again.
+    pub(crate) fn remove_range(&mut self, start_frame: usize, end_frame: usize) {
+        let removed_frames=end_frame-start_frame;
+        let (mut uncovered_start, mut uncovered_end)=(start_frame, end_frame);
+
+        self.segments.retain(|segment| {
+            let overlapping=segment.end_frame>start_frame && segment.start_frame<end_frame;
+
+            if overlapping {
+                uncovered_start=std::cmp::min(uncovered_start, segment.start_frame);
+                uncovered_end=std::cmp::max(uncovered_end, segment.end_frame);
+                }
+
+            !overlapping
+            });
+        self.uncover(uncovered_start, uncovered_end);
+
+        let shift=|frame: usize| if frame>=end_frame { frame-removed_frames } else { std::cmp::min(frame, start_frame) };
+        for segment in &mut self.segments {
+            segment.start_frame=shift(segment.start_frame);
+            segment.end_frame=shift(segment.end_frame);
+            }
+        self.covered=self.covered.iter()
+        .map(|(start, end)| (shift(*start), shift(*end)))
+        .filter(|(start, end)| start<end)
+        .collect();
+        }
+    /// Follows an insertion of frames at the given one. The inserted audio is left to be transcribed, as is the audio of a segment it splits.
+    pub(crate) fn insert(&mut self, start_frame: usize, frames: usize) {
+        let split_segments: Vec<(usize, usize)>=self.segments.iter()
+        .filter(|segment| segment.start_frame<start_frame && segment.end_frame>start_frame)
+        .map(|segment| (segment.start_frame, segment.end_frame))
+        .collect();
+
+        self.segments.retain(|segment| !(segment.start_frame<start_frame && segment.end_frame>start_frame));
+        for (segment_start, segment_end) in split_segments {
+            self.uncover(segment_start, segment_end);
+            }
+
+        for segment in &mut self.segments {
+            if segment.start_frame>=start_frame {
+                segment.start_frame+=frames;
+                segment.end_frame+=frames;
+                }
+            }
+
+        let mut covered: Vec<(usize, usize)>=Vec::new();
+        for (start, end) in &self.covered {
+            if *end<=start_frame {
+                covered.push((*start, *end));
+                }
+            else if *start>=start_frame {
+                covered.push((start+frames, end+frames));
+                }
+            else {
+                covered.push((*start, start_frame));
+                covered.push((start_frame+frames, end+frames));
+                }
+            }
+        self.covered=covered;
+        }
+
+    /// Appends the transcript of another document joined at the given frame.
+    pub(crate) fn append(&mut self, other: Transcript, frame_offset: usize) {
+        for mut segment in other.segments {
+            segment.start_frame+=frame_offset;
+            segment.end_frame+=frame_offset;
+            self.segments.push(segment);
+            }
+        for (start, end) in other.covered {
+            self.cover(start+frame_offset, end+frame_offset);
+            }
+        }
+
+    fn first_gap(&self, audio_len: usize) -> Option<(usize, usize)> {
+        let mut gap_start=0;
+
+        for (start, end) in &self.covered {
+            if *start>gap_start {
+                return Some((gap_start, std::cmp::min(*start, audio_len)));
+                }
+
+            gap_start=std::cmp::max(gap_start, *end);
+            }
+
+        if gap_start<audio_len {
+            Some((gap_start, audio_len))
+            }
+        else {
+            None
+            }
+        }
+    fn cover(&mut self, start_frame: usize, end_frame: usize) {
+        let (mut start, mut end)=(start_frame, end_frame);
+
+        //Ranges touching the new one are merged into it
+        self.covered.retain(|(covered_start, covered_end)| {
+            if *covered_end<start || *covered_start>end {
+                return true;
+                }
+
+            start=std::cmp::min(start, *covered_start);
+            end=std::cmp::max(end, *covered_end);
+
+            false
+            });
+
+        let index=self.covered.partition_point(|(covered_start, _)| *covered_start<start);
+        self.covered.insert(index, (start, end));
+        }
+    fn uncover(&mut self, start_frame: usize, end_frame: usize) {
+        let mut covered: Vec<(usize, usize)>=Vec::new();
+
+        for (start, end) in &self.covered {
+            if *start<start_frame {
+                covered.push((*start, std::cmp::min(*end, start_frame)));
+                }
+            if *end>end_frame {
+                covered.push((std::cmp::max(*start, end_frame), *end));
+                }
+            }
+
+        self.covered=covered;
+        }
+    }
+
+/// Audio to be transcribed by the background thread.
+struct TranscriptionJob {
+    revision: u64,
+    start_frame: usize,
+    end_frame: usize,
+    /// The frames, preceded by one more frame warming up the decoder, if there's any.
+    frames: AudioContainer,
+    warm_up: bool,
+    }
+
+/// Result of transcribing a range of frames, sent back to the audio handler.
+#[derive(Message)]
+#[rtype(result="()")]
+pub(crate) struct TranscriptionDone {
+    /// Revision of the audio the job was created for. Results for audio edited since are discarded.
+    pub(crate) revision: u64,
+    pub(crate) start_frame: usize,
+    pub(crate) end_frame: usize,
+    pub(crate) result: Result<Vec<RecognizedText>, anyhow::Error>,
+    }
+
+/// Runs the transcription engine on a background thread, so the slow recognition doesn't hold up recording or playback.
+pub(crate) struct Transcriber {
+    job_sender: mpsc::Sender<TranscriptionJob>,
+    }
+impl Transcriber {
+
+    pub(crate) fn new(mut engine: Box<dyn TranscriptionEngine>, recipient: Recipient<TranscriptionDone>) -> Transcriber {
+        let (job_sender, job_receiver)=mpsc::channel::<TranscriptionJob>();
+
+        //The thread ends when the transcriber, and thus the sender, is dropped
+        std::thread::spawn(move || {
+            for job in job_receiver {
+                let result=decode(&job.frames, job.warm_up)
+                .and_then(|samples| engine.transcribe(&samples));
+
+                recipient.do_send(TranscriptionDone {
+                    revision: job.revision,
+                    start_frame: job.start_frame,
+                    end_frame: job.end_frame,
+                    result,
+                    });
+                }
+            });
+
+        Transcriber {
+            job_sender,
+            }
+        }
+
+    /// Queues the given range of frames for transcription.
+    pub(crate) fn transcribe(&self, audio: &AudioContainer, start_frame: usize, end_frame: usize, revision: u64) {
+        let warm_up=start_frame>0;
+        let frames=audio.slice(if warm_up { start_frame-1 } else { start_frame }, end_frame);
+
+        let _=self.job_sender.send(TranscriptionJob {
+            revision,
+            start_frame,
+            end_frame,
+            frames,
+            warm_up,
+            });
+        }
+    }
+
+/// Decodes the frames into samples, skipping the first frame if it only warms up the decoder.
+fn decode(frames: &AudioContainer, warm_up: bool) -> Result<Vec<i16>, anyhow::Error> {
+    let mut decoder=Decoder::new(SAMPLING_RATE, opus::Channels::Mono)?;
+    let mut decoding_buffer=vec![0_i16; 2*FRAME_SIZE];
+    let mut samples: Vec<i16>=Vec::with_capacity(frames.len()*FRAME_SIZE);
+
+    for id in 0..frames.len() {
+        let decoded_samples=decoder.decode(frames.frame_data(id).unwrap_or(&[]), &mut decoding_buffer, false)?;
+
+        if id>0 || !warm_up {
+            samples.extend_from_slice(&decoding_buffer[..decoded_samples]);
+            }
+        }
+
+    Ok(samples)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn whisper_output_test() {
+        let output="\
+whisper_init_from_file: loading model\n\
+\n\
+[00:00:00.000 --> 00:00:02.500]   Good morning everyone.\n\
+[00:00:02.500 --> 00:01:04.040]   Today we talk about entropy.\n\
+[00:01:04.040 --> 00:01:05.000]  \n";
+
+        let recognized=parse_whisper_output(output);
+        assert_eq!(recognized, vec![
+            RecognizedText::new(Duration::ZERO, Duration::from_millis(2500), "Good morning everyone."),
+            RecognizedText::new(Duration::from_millis(2500), Duration::from_millis(64040), "Today we talk about entropy."),
+            ]);
+        }
+
+    #[test]
+    fn whisper_input_file_test() {
+        //A file already at the next name, e.g. a link planted by another user, is left alone
+        let planted_path=std::env::temp_dir().join(format!("sdam_transcription_{}_{}.wav", std::process::id(), INPUT_COUNTER.load(Ordering::Relaxed)));
+        std::fs::write(&planted_path, "planted").unwrap();
+
+        let (input_path, input_file)=WhisperCli::create_input_file().unwrap();
+        assert_ne!(input_path, planted_path);
+        WhisperCli::write_input(input_file, &vec![1000_i16; FRAME_SIZE]).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&planted_path).unwrap(), "planted");
+        assert_eq!(hound::WavReader::open(&input_path).unwrap().len() as usize, FRAME_SIZE/3);
+
+        for path in [planted_path, input_path] {
+            std::fs::remove_file(&path).unwrap();
+            }
+        }
+
+    #[test]
+    fn transcript_chunks_test() {
+        let mut transcript=Transcript::new();
+        let envelope=Envelope::new();
+
+        //Without pauses, a growing recording is transcribed in full chunks, and the rest once it stops growing
+        assert_eq!(transcript.next_chunk(500, &envelope, true), None);
+        assert_eq!(transcript.next_chunk(1000, &envelope, true), Some((0, MAX_CHUNK_FRAMES)));
+        assert_eq!(transcript.next_chunk(500, &envelope, false), Some((0, 500)));
+
+        transcript.add(0, 500, vec![
+            RecognizedText::new(Duration::ZERO, Duration::from_secs(4), "First"),
+            RecognizedText::new(Duration::from_secs(4), Duration::from_secs(8), "Second"),
+            ]);
+        assert_eq!(transcript.segments[1], TranscriptSegment::new(100, 200, "Second"));
+        assert_eq!(transcript.next_chunk(500, &envelope, false), None);
+        assert_eq!(transcript.next_chunk(600, &envelope, false), Some((500, 600)));
+        assert_eq!(transcript.segments_between(150, 160).len(), 1);
+        }
+
+    #[test]
+    fn transcript_edit_test() {
+        let mut transcript=Transcript::new();
+        let envelope=Envelope::new();
+
+        transcript.add(0, 300, vec![
+            RecognizedText::new(Duration::ZERO, Duration::from_secs(4), "First"),
+            RecognizedText::new(Duration::from_secs(4), Duration::from_secs(8), "Second"),
+            RecognizedText::new(Duration::from_secs(8), Duration::from_secs(12), "Third"),
+            ]);
+
+        //Removing a part of the second segment drops it and leaves its remaining audio to be transcribed again
+        transcript.remove_range(150, 170);
+        assert_eq!(transcript.segments.len(), 2);
+        assert_eq!(transcript.segments[1], TranscriptSegment::new(180, 280, "Third"));
+        assert_eq!(transcript.next_chunk(280, &envelope, false), Some((100, 180)));
+
+        //Insertion at a segment boundary only shifts the following segments
+        transcript.insert(100, 50);
+        assert_eq!(transcript.segments[1], TranscriptSegment::new(230, 330, "Third"));
+        assert_eq!(transcript.next_chunk(330, &envelope, false), Some((100, 230)));
+
+        //Insertion inside a segment drops it too
+        transcript.insert(250, 10);
+        assert_eq!(transcript.segments.len(), 1);
+        transcript.add(100, 340, Vec::new());
+        assert_eq!(transcript.next_chunk(340, &envelope, false), None);
+        }
+    }
@@ -0,0 +1,652 @@
+use std::io;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Clear, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use sdam::{Mark, Sdam, SdamEvent};
+
+use crate::editor::NotesEditor;
+use crate::keys::{self, Action, Focus, KEY_HELP};
+use crate::protocol;
+use crate::time::format_time;
+
+/// How often the status line follows the playback, unless in the screen reader mode.
+const REFRESH_INTERVAL: Duration=Duration::from_millis(250);
+/// How long typing has to pause before the notes are passed to the core, which compares the whole text on every update.
+const NOTES_SYNC_DELAY: Duration=Duration::from_millis(500);
+/// The rate limits of the stepwise rate changes, like in the GUI.
+const MIN_RATE: f64=0.25;
+const MAX_RATE: f64=3.0;
+
+pub struct Options {
+    /// Keeps the screen still unless the user acts, and puts the cursor on the announcements, so screen readers read them.
+    pub screen_reader: bool,
+    }
+
+/// The state of the document shown in the status line.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Status {
+    position: Option<Duration>,
+    duration: Duration,
+    rate: f64,
+    playing: bool,
+    recording: bool,
+    modified: bool,
+    file_name: Option<String>,
+    }
+impl Status {
+
+    fn read(sdam: &mut Sdam) -> Status {
+        Status {
+            position: sdam.position(),
+            duration: sdam.duration(),
+            rate: sdam.rate(),
+            playing: sdam.is_playing(),
+            recording: sdam.is_recording(),
+            modified: sdam.is_modified(),
+            file_name: sdam.file_name(),
+            }
+        }
+
+    fn line(&self) -> String {
+        let mut parts: Vec<String>=vec![
+            (if self.playing { "Playing" } else { "Paused" }).to_string(),
+            format!("{} of {}", format_time(self.position.unwrap_or(Duration::ZERO)), format_time(self.duration)),
+            format!("Rate {}", self.rate),
+            ];
+
+        if self.recording {
+            parts.push("Recording".to_string());
+            }
+
+        let name=self.file_name.clone().unwrap_or_else(|| "Unsaved document".to_string());
+        parts.push(if self.modified { format!("{}, modified", name) } else { name });
+
+        parts.join(" | ")
+        }
+    }
+
+/// What the text typed in the prompt is for.
+#[derive(Clone, Debug, PartialEq)]
+enum PromptPurpose {
+    Command,
+    SavePath,
+    NewMarkLabel {category: usize, frame: usize},
+    MarkLabel {id: u64},
+    }
+
+struct Prompt {
+    title: String,
+    text: String,
+    purpose: PromptPurpose,
+    }
+
+/// The interactive terminal interface, a status line over the mark list and the notes, with a line of announcements below.
+pub struct App<'a> {
+    sdam: &'a mut Sdam,
+    events: mpsc::Receiver<SdamEvent>,
+    options: Options,
+    status: Status,
+    marks: Vec<Mark>,
+    focus: Focus,
+    focused_mark: Option<u64>,
+    notes: NotesEditor,
+    /// When the notes were last edited, if the edits weren't passed to the core yet.
+    notes_edited: Option<Instant>,
+    prompt: Option<Prompt>,
+    announcement: String,
+    show_help: bool,
+    /// Whether quitting with unsaved changes was already warned about.
+    quit_warned: bool,
+    running: bool,
+    }
+impl<'a> App<'a> {
+
+    pub fn new(sdam: &'a mut Sdam, options: Options) -> App<'a> {
+        let events=sdam.subscribe();
+        let status=Status::read(sdam);
+        let marks=sdam.marks();
+        let notes=NotesEditor::new(&sdam.user_text());
+
+        App {
+            sdam,
+            events,
+            options,
+            status,
+            marks,
+            focus: Focus::Playback,
+            focused_mark: None,
+            notes,
+            notes_edited: None,
+            prompt: None,
+            announcement: "Press ? for help".to_string(),
+            show_help: false,
+            quit_warned: false,
+            running: true,
+            }
+        }
+
+    /// Runs the interface until the user quits.
+    pub fn run(mut self) -> io::Result<()> {
+        let mut terminal=ratatui::init();
+        let result=self.event_loop(&mut terminal);
+        ratatui::restore();
+
+        result
+        }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while self.running {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(REFRESH_INTERVAL)? {
+                if let Event::Key(key)=event::read()? {
+                    if key.kind==KeyEventKind::Press {
+                        self.handle_key(key);
+                        self.refresh();
+                        }
+                    }
+                }
+            else if !self.options.screen_reader {
+                self.status=Status::read(self.sdam);
+                }
+
+            if self.notes_edited.is_some_and(|time| time.elapsed()>=NOTES_SYNC_DELAY) {
+                self.sync_notes();
+                self.refresh();
+                }
+
+            //However many changes arrived since the last round, e.g. while recording, they're refreshed once
+            let mut document_changed=false;
+            while let Ok(event)=self.events.try_recv() {
+                match event {
+                    SdamEvent::DocumentChanged {..} => document_changed=true,
+                    event => self.handle_event(event),
+                    }
+                }
+            if document_changed {
+                self.document_changed();
+                }
+            }
+
+        Ok(())
+        }
+
+    fn refresh(&mut self) {
+        self.status=Status::read(self.sdam);
+        self.marks=self.sdam.marks();
+
+        if self.focused_mark.is_some_and(|id| !self.marks.iter().any(|mark| mark.is(id))) {
+            self.focused_mark=None;
+            }
+        }
+    fn reload_notes(&mut self) {
+        self.notes_edited=None;
+        self.notes.set_text(&self.sdam.user_text());
+        }
+    /// Passes the edited notes to the core, if they weren't yet.
+    fn sync_notes(&mut self) {
+        if self.notes_edited.take().is_some() {
+            self.sdam.set_user_text(&self.notes.text());
+            }
+        }
+
+    fn handle_event(&mut self, event: SdamEvent) {
+        match event {
+            SdamEvent::DocumentChanged {..} => self.document_changed(),
+            SdamEvent::Loaded {path} => {
+                self.refresh();
+                self.reload_notes();
+                self.announce(&format!("Loaded {}", path.display()));
+                },
+            SdamEvent::Saved {path} => self.announce(&format!("Saved {}", path.display())),
+            SdamEvent::RejoinedLive => self.announce("Back at live"),
+            SdamEvent::TranscriptionFailed {message} => self.announce(&format!("Transcription failed: {}", message)),
+            SdamEvent::SpeakersDetected {speaker_count} => self.announce(&format!("{} speakers detected", speaker_count)),
+            SdamEvent::SpeakerDetectionFailed {message} => self.announce(&format!("Speaker detection failed: {}", message)),
+            SdamEvent::MarksSuggested {suggestion_count} => self.announce(&format!("{} marks suggested", suggestion_count)),
+            }
+        }
+
+    fn document_changed(&mut self) {
+        self.refresh();
+
+        if self.focus!=Focus::Notes {
+            self.reload_notes();
+            }
+        }
+
+    fn announce(&mut self, text: &str) {
+        self.announcement=text.to_string();
+        }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key);
+            return;
+            }
+
+        if self.show_help {
+            self.show_help=false;
+            self.announce("Help closed");
+            return;
+            }
+
+        if let Some(action)=keys::action(self.focus, key) {
+            if action!=Action::Quit {
+                self.quit_warned=false;
+                }
+            //Everything else, like saving, undoing or quitting, has to see the typed text
+            if !matches!(action, Action::Insert(_) | Action::NewLine | Action::Backspace | Action::Delete) {
+                self.sync_notes();
+                }
+
+            self.execute(action);
+            }
+        }
+    fn handle_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt)=&mut self.prompt else {
+            return;
+            };
+
+        match key.code {
+            KeyCode::Char(character) => prompt.text.push(character),
+            KeyCode::Backspace => {
+                prompt.text.pop();
+                },
+            KeyCode::Esc => {
+                self.prompt=None;
+                self.announce("Cancelled");
+                },
+            KeyCode::Enter => {
+                if let Some(prompt)=self.prompt.take() {
+                    self.submit_prompt(prompt);
+                    }
+                },
+            _ => {},
+            }
+        }
+    fn open_prompt(&mut self, title: &str, text: &str, purpose: PromptPurpose) {
+        self.prompt=Some(Prompt {
+            title: title.to_string(),
+            text: text.to_string(),
+            purpose,
+            });
+        }
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let label=if prompt.text.is_empty() { None } else { Some(prompt.text.clone()) };
+
+        match prompt.purpose {
+            PromptPurpose::Command => {
+                let result=match protocol::parse(&prompt.text) {
+                    Ok(Some(protocol::Command::Quit)) => {
+                        self.execute(Action::Quit);
+                        return;
+                        },
+                    Ok(Some(command)) => protocol::execute(self.sdam, command),
+                    Ok(None) => return,
+                    Err(error) => Err(error),
+                    };
+
+                self.announce(&protocol::response(&result));
+                },
+            PromptPurpose::SavePath => self.save(Some(&prompt.text)),
+            PromptPurpose::NewMarkLabel {category, frame} => {
+                let mark=self.sdam.add_mark(Mark::new(frame, category, label));
+
+                self.focused_mark=mark.id().to_owned();
+                self.announce(&format!("Added {}", describe_mark(&mark)));
+                },
+            PromptPurpose::MarkLabel {id} => {
+                if let Some(mark)=self.sdam.get_mark(id) {
+                    self.sdam.edit_mark(id, Mark::new(*mark.frame_offset(), *mark.category(), label));
+                    self.announce("Label changed");
+                    }
+                },
+            }
+        }
+
+    fn execute(&mut self, action: Action) {
+        match action {
+            Action::TogglePlayback => {
+                self.sdam.toggle_playback();
+
+                let playing=self.sdam.is_playing();
+                self.announce(if playing { "Playing" } else { "Paused" });
+                },
+            Action::Forward(seconds) => {
+                self.sdam.forward(seconds as i32);
+                self.announce_position();
+                },
+            Action::Backward(seconds) => {
+                self.sdam.backward(seconds as i32);
+                self.announce_position();
+                },
+            Action::JumpToStart => {
+                self.sdam.jump_to_start();
+                self.announce_position();
+                },
+            Action::JumpToEnd => {
+                self.sdam.jump_to_end();
+                self.announce_position();
+                },
+            Action::SetRate(rate) => self.set_rate(rate),
+            Action::ChangeRate(delta) => {
+                let rate=(self.sdam.rate()+delta).clamp(MIN_RATE, MAX_RATE);
+                self.set_rate(rate);
+                },
+            Action::StartRecording => {
+                self.sdam.start_recording();
+                self.announce("Recording");
+                },
+            Action::StopRecording => {
+                self.sdam.stop_recording();
+                self.announce("Recording stopped");
+                },
+            Action::AddMark(category) => {
+                if let Some(frame)=self.mark_position() {
+                    let mark=self.sdam.add_mark(Mark::new(frame, category, None));
+
+                    self.focused_mark=mark.id().to_owned();
+                    self.announce(&format!("Added {}", describe_mark(&mark)));
+                    }
+                },
+            Action::AddLabelledMark(category) => {
+                if let Some(frame)=self.mark_position() {
+                    self.open_prompt(&format!("Label of the category {} mark", category), "", PromptPurpose::NewMarkLabel {category, frame});
+                    }
+                },
+            Action::PreviousMark | Action::NextMark | Action::PreviousClosestMark | Action::NextClosestMark => self.jump_to_mark(action),
+            Action::JumpToFocusedMark => {
+                if let Some(mark)=self.focused_mark() {
+                    self.sdam.jump_to_frame(*mark.frame_offset());
+                    self.announce(&describe_mark(&mark));
+                    }
+                },
+            Action::LabelFocusedMark => {
+                if let Some(mark)=self.focused_mark() {
+                    let id=mark.id().unwrap();
+                    self.open_prompt("Label", mark.label().as_deref().unwrap_or(""), PromptPurpose::MarkLabel {id});
+                    }
+                },
+            Action::DeleteFocusedMark => {
+                if let Some(mark)=self.focused_mark() {
+                    self.sdam.delete_mark(mark.id().unwrap());
+                    self.focused_mark=None;
+                    self.announce(&format!("Deleted {}", describe_mark(&mark)));
+                    }
+                },
+            Action::SelectPreviousMark | Action::SelectNextMark => self.select_mark(action==Action::SelectNextMark),
+            Action::AnnounceStatus => {
+                let status=Status::read(self.sdam);
+                self.announce(&status.line());
+                },
+            Action::CommandPrompt => self.open_prompt("Command", "", PromptPurpose::Command),
+            Action::Save => self.save(None),
+            Action::Undo => {
+                let announcement=match self.sdam.undo() {
+                    Some(description) => format!("Undone {}", description),
+                    None => "Nothing to undo".to_string(),
+                    };
+
+                self.reload_notes();
+                self.announce(&announcement);
+                },
+            Action::Redo => {
+                let announcement=match self.sdam.redo() {
+                    Some(description) => format!("Redone {}", description),
+                    None => "Nothing to redo".to_string(),
+                    };
+
+                self.reload_notes();
+                self.announce(&announcement);
+                },
+            Action::Help => {
+                self.show_help=true;
+                self.announce("Help, press any key to close");
+                },
+            Action::NextFocus => self.set_focus(self.focus.next()),
+            Action::PreviousFocus => self.set_focus(self.focus.previous()),
+            Action::LeaveNotes => self.set_focus(Focus::Playback),
+            Action::Quit => {
+                if self.sdam.is_modified() && !self.quit_warned {
+                    self.quit_warned=true;
+                    self.announce("There are unsaved changes, press q again to quit without saving");
+                    }
+                else {
+                    self.running=false;
+                    }
+                },
+            Action::Insert(_) | Action::NewLine | Action::Backspace | Action::Delete => {
+                match action {
+                    Action::Insert(character) => self.notes.insert(character),
+                    Action::NewLine => self.notes.new_line(),
+                    Action::Backspace => self.notes.backspace(),
+                    _ => self.notes.delete(),
+                    }
+
+                self.notes_edited=Some(Instant::now());
+                },
+            Action::CursorLeft => self.notes.left(),
+            Action::CursorRight => self.notes.right(),
+            Action::CursorUp => self.notes.up(),
+            Action::CursorDown => self.notes.down(),
+            Action::CursorHome => self.notes.home(),
+            Action::CursorEnd => self.notes.end(),
+            }
+        }
+
+    fn announce_position(&mut self) {
+        let position=self.sdam.position().unwrap_or(Duration::ZERO);
+        self.announce(&format_time(position));
+        }
+    fn set_rate(&mut self, rate: f64) {
+        self.sdam.set_rate(rate);
+        self.announce(&format!("Rate {}", rate));
+        }
+    fn save(&mut self, path: Option<&str>) {
+        if path.is_none() && self.sdam.file_path().is_none() {
+            self.open_prompt("Save as", "", PromptPurpose::SavePath);
+            return;
+            }
+
+        if let Err(error)=self.sdam.save(path) {
+            self.announce(&format!("Saving failed: {}", error));
+            }
+        }
+    fn set_focus(&mut self, focus: Focus) {
+        self.focus=focus;
+
+        let announcement=match focus {
+            Focus::Notes => format!("Notes, line {}: {}", self.notes.cursor().0+1, self.notes.current_line()),
+            Focus::Marks => match self.focused_mark() {
+                Some(mark) => format!("Marks, {}", describe_mark(&mark)),
+                None => format!("Marks, {} marks", self.marks.len()),
+                },
+            Focus::Playback => focus.name().to_string(),
+            };
+        self.announce(&announcement);
+        }
+
+    /// The frame a new mark goes to, the live edge while recording, like in the GUI.
+    fn mark_position(&mut self) -> Option<usize> {
+        let position=if self.sdam.is_recording() && !self.sdam.is_time_travelling() {
+            Some(self.sdam.audio_len())
+            }
+        else {
+            self.sdam.current_position()
+            };
+
+        if position.is_none() {
+            self.announce("There's nothing to mark yet");
+            }
+
+        position
+        }
+    fn focused_mark(&mut self) -> Option<Mark> {
+        let mark=self.focused_mark.and_then(|id| self.marks.iter().find(|mark| mark.is(id)).cloned());
+
+        if mark.is_none() {
+            self.announce("No mark is focused");
+            }
+
+        mark
+        }
+    /// Jumps to a mark. The next and previous marks follow the focused mark, the closest ones the playback position.
+    fn jump_to_mark(&mut self, action: Action) {
+        let position=self.sdam.current_position().unwrap_or(0);
+        let from=match (action, self.focused_mark.and_then(|id| self.marks.iter().find(|mark| mark.is(id)))) {
+            (Action::PreviousMark | Action::NextMark, Some(mark)) => *mark.frame_offset(),
+            _ => position,
+            };
+
+        let mark=if matches!(action, Action::NextMark | Action::NextClosestMark) {
+            self.sdam.next_closest_mark(from)
+            }
+        else {
+            self.sdam.previous_closest_mark(from)
+            };
+
+        match mark {
+            Some(mark) => {
+                self.sdam.jump_to_frame(*mark.frame_offset());
+                self.focused_mark=mark.id().to_owned();
+                self.announce(&describe_mark(&mark));
+                },
+            None => self.announce("No more marks"),
+            }
+        }
+    fn select_mark(&mut self, next: bool) {
+        if self.marks.is_empty() {
+            self.announce("No marks");
+            return;
+            }
+
+        let index=self.focused_mark.and_then(|id| self.marks.iter().position(|mark| mark.is(id)));
+        let index=match (index, next) {
+            (None, _) => 0,
+            (Some(index), true) => std::cmp::min(index+1, self.marks.len()-1),
+            (Some(index), false) => index.saturating_sub(1),
+            };
+
+        let mark=self.marks[index].clone();
+        self.focused_mark=mark.id().to_owned();
+        self.announce(&describe_mark(&mark));
+        }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status_area, main_area, announcement_area]=Layout::vertical([Constraint::Length(1), Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [marks_area, notes_area]=Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main_area);
+
+        frame.render_widget(Paragraph::new(self.status.line()).style(Style::new().add_modifier(Modifier::REVERSED)), status_area);
+
+        //Mark list
+        let items: Vec<ListItem>=self.marks.iter()
+        .map(|mark| ListItem::new(format!("{} [{}] {}", format_time(mark.time()), mark.category(), mark.label().as_deref().unwrap_or(""))))
+        .collect();
+        let mut list_state=ListState::default().with_selected(self.focused_mark.and_then(|id| self.marks.iter().position(|mark| mark.is(id))));
+        let list=List::new(items)
+        .block(self.pane_block("Marks", Focus::Marks))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+        frame.render_stateful_widget(list, marks_area, &mut list_state);
+
+        //Notes, scrolled to keep the cursor visible
+        let (row, column)=self.notes.cursor();
+        let visible_rows=notes_area.height.saturating_sub(2) as usize;
+        let scroll=(row+1).saturating_sub(visible_rows);
+        let lines: Vec<Line>=self.notes.lines().into_iter().map(Line::from).collect();
+        frame.render_widget(Paragraph::new(lines).block(self.pane_block("Notes", Focus::Notes)).scroll((scroll as u16, 0)), notes_area);
+
+        //Announcements, or the prompt
+        match &self.prompt {
+            Some(prompt) => {
+                let text=format!("{}: {}", prompt.title, prompt.text);
+                let cursor_x=announcement_area.x+text.chars().count() as u16;
+
+                frame.render_widget(Paragraph::new(text), announcement_area);
+                frame.set_cursor_position((cursor_x, announcement_area.y));
+                },
+            None => {
+                frame.render_widget(Paragraph::new(self.announcement.as_str()), announcement_area);
+
+                if self.focus==Focus::Notes && !self.show_help {
+                    frame.set_cursor_position((notes_area.x+1+column as u16, notes_area.y+1+(row-scroll) as u16));
+                    }
+                else if self.options.screen_reader {
+                    frame.set_cursor_position((announcement_area.x, announcement_area.y));
+                    }
+                },
+            }
+
+        if self.show_help {
+            let help_area=centered(frame.area(), 80, KEY_HELP.len() as u16+2);
+            let lines: Vec<Line>=KEY_HELP.iter()
+            .map(|(keys, description)| Line::from(format!("{:<24} {}", keys, description)))
+            .collect();
+
+            frame.render_widget(Clear, help_area);
+            frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Keys")), help_area);
+            }
+        }
+    fn pane_block(&self, title: &str, pane: Focus) -> Block<'static> {
+        let block=Block::bordered().title(title.to_string());
+
+        if self.focus==pane {
+            block.border_style(Style::new().add_modifier(Modifier::BOLD))
+            }
+        else {
+            block.border_style(Style::new().add_modifier(Modifier::DIM))
+            }
+        }
+    }
+
+fn describe_mark(mark: &Mark) -> String {
+    match mark.label() {
+        Some(label) => format!("category {} mark at {}, {}", mark.category(), format_time(mark.time()), label),
+        None => format!("category {} mark at {}", mark.category(), format_time(mark.time())),
+        }
+    }
+
+/// A rectangle of the given size in the middle of the area, shrunk to fit it.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width=std::cmp::min(width, area.width);
+    let height=std::cmp::min(height, area.height);
+
+    Rect::new(area.x+(area.width-width)/2, area.y+(area.height-height)/2, width, height)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn status_line_test() {
+        let mut status=Status {
+            position: Some(Duration::from_secs(75)),
+            duration: Duration::from_secs(3600),
+            rate: 1.25,
+            playing: true,
+            recording: false,
+            modified: false,
+            file_name: Some("lecture.sdam".to_string()),
+            };
+        assert_eq!(status.line(), "Playing | 01:15 of 60:00 | Rate 1.25 | lecture.sdam");
+
+        status.playing=false;
+        status.recording=true;
+        status.modified=true;
+        status.file_name=None;
+        assert_eq!(status.line(), "Paused | 01:15 of 60:00 | Rate 1.25 | Recording | Unsaved document, modified");
+
+        assert_eq!(describe_mark(&Mark::new(1500, 2, Some("Entropy".to_string()))), "category 2 mark at 01:00, Entropy");
+        }
+    }
@@ -0,0 +1,158 @@
+/// Text buffer of the notes editor, with a cursor given as a line and a character in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotesEditor {
+    lines: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+    }
+impl NotesEditor {
+
+    pub fn new(text: &str) -> NotesEditor {
+        let mut editor=NotesEditor {
+            lines: Vec::new(),
+            row: 0,
+            column: 0,
+            };
+        editor.set_text(text);
+
+        editor
+        }
+
+    /// Replaces the text, keeping the cursor where possible.
+    pub fn set_text(&mut self, text: &str) {
+        self.lines=text.split('\n').map(|line| line.chars().collect()).collect();
+        self.row=std::cmp::min(self.row, self.lines.len()-1);
+        self.column=std::cmp::min(self.column, self.lines[self.row].len());
+        }
+    pub fn text(&self) -> String {
+        self.lines.iter()
+        .map(|line| line.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+        }
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.iter().map(|line| line.iter().collect()).collect()
+        }
+    pub fn current_line(&self) -> String {
+        self.lines[self.row].iter().collect()
+        }
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+        }
+
+    pub fn insert(&mut self, character: char) {
+        self.lines[self.row].insert(self.column, character);
+        self.column+=1;
+        }
+    pub fn new_line(&mut self) {
+        let rest=self.lines[self.row].split_off(self.column);
+
+        self.row+=1;
+        self.column=0;
+        self.lines.insert(self.row, rest);
+        }
+    /// Deletes the character before the cursor, joining the line with the previous one at its start.
+    pub fn backspace(&mut self) {
+        if self.column>0 {
+            self.column-=1;
+            self.lines[self.row].remove(self.column);
+            }
+        else if self.row>0 {
+            let line=self.lines.remove(self.row);
+
+            self.row-=1;
+            self.column=self.lines[self.row].len();
+            self.lines[self.row].extend(line);
+            }
+        }
+    /// Deletes the character after the cursor, joining the line with the next one at its end.
+    pub fn delete(&mut self) {
+        if self.column<self.lines[self.row].len() {
+            self.lines[self.row].remove(self.column);
+            }
+        else if self.row+1<self.lines.len() {
+            let line=self.lines.remove(self.row+1);
+
+            self.lines[self.row].extend(line);
+            }
+        }
+
+    pub fn left(&mut self) {
+        if self.column>0 {
+            self.column-=1;
+            }
+        else if self.row>0 {
+            self.row-=1;
+            self.column=self.lines[self.row].len();
+            }
+        }
+    pub fn right(&mut self) {
+        if self.column<self.lines[self.row].len() {
+            self.column+=1;
+            }
+        else if self.row+1<self.lines.len() {
+            self.row+=1;
+            self.column=0;
+            }
+        }
+    pub fn up(&mut self) {
+        if self.row>0 {
+            self.row-=1;
+            self.column=std::cmp::min(self.column, self.lines[self.row].len());
+            }
+        }
+    pub fn down(&mut self) {
+        if self.row+1<self.lines.len() {
+            self.row+=1;
+            self.column=std::cmp::min(self.column, self.lines[self.row].len());
+            }
+        }
+    pub fn home(&mut self) {
+        self.column=0;
+        }
+    pub fn end(&mut self) {
+        self.column=self.lines[self.row].len();
+        }
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn notes_editor_test() {
+        let mut editor=NotesEditor::new("");
+        for character in "Entropy".chars() {
+            editor.insert(character);
+            }
+        editor.new_line();
+        editor.insert('x');
+        assert_eq!(editor.text(), "Entropy\nx");
+        assert_eq!(editor.cursor(), (1, 1));
+
+        //Deleting at the line boundaries joins the lines
+        editor.home();
+        editor.backspace();
+        assert_eq!(editor.text(), "Entropyx");
+        assert_eq!(editor.cursor(), (0, 7));
+        editor.new_line();
+        editor.up();
+        editor.end();
+        editor.delete();
+        assert_eq!(editor.text(), "Entropyx");
+
+        //Moving keeps the cursor inside the lines
+        editor.set_text("Long line\nab");
+        editor.up();
+        editor.end();
+        editor.down();
+        assert_eq!(editor.cursor(), (1, 2));
+        editor.right();
+        assert_eq!(editor.cursor(), (1, 2));
+        editor.home();
+        editor.left();
+        assert_eq!(editor.cursor(), (0, 9));
+        assert_eq!(editor.current_line(), "Long line");
+        }
+    }
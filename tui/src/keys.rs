@@ -0,0 +1,225 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Help lines listed by the ? key.
+pub const KEY_HELP: &[(&str, &str)]=&[
+    ("k, space", "Play / pause"),
+    ("j, J, h", "Back 5 seconds, 10 seconds, 1 minute"),
+    ("l, L, ;", "Forward 5 seconds, 10 seconds, 1 minute"),
+    ("H, Home / End", "Jump to the start / end"),
+    ("u, U, i, O, o, p", "Rate half, decrease, original, increase, double, triple"),
+    ("r, R", "Start / stop recording"),
+    ("1 to 9", "Add a mark of the category"),
+    ("Alt+1 to Alt+9", "Add a labelled mark of the category"),
+    ("m / M", "Previous mark, previous closest mark"),
+    (". / >", "Next mark, next closest mark"),
+    (",", "Jump to the focused mark"),
+    ("<", "Label the focused mark"),
+    ("Up, Down, Enter, Delete", "Select, jump to and delete marks in the mark list"),
+    ("Tab, Shift+Tab", "Move between the playback, the mark list and the notes"),
+    ("Alt+key", "The keys above while writing notes"),
+    ("s", "Announce the status"),
+    (":", "Enter a command, see help for the list"),
+    ("Ctrl+S", "Save"),
+    ("Ctrl+Z, Ctrl+Y", "Undo, redo"),
+    ("?", "This help"),
+    ("q, Ctrl+C", "Quit"),
+    ];
+
+/// The part of the interface receiving the keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Focus {
+    Playback,
+    Marks,
+    Notes,
+    }
+impl Focus {
+
+    pub fn next(self) -> Focus {
+        match self {
+            Focus::Playback => Focus::Marks,
+            Focus::Marks => Focus::Notes,
+            Focus::Notes => Focus::Playback,
+            }
+        }
+    pub fn previous(self) -> Focus {
+        match self {
+            Focus::Playback => Focus::Notes,
+            Focus::Marks => Focus::Playback,
+            Focus::Notes => Focus::Marks,
+            }
+        }
+    pub fn name(self) -> &'static str {
+        match self {
+            Focus::Playback => "Playback",
+            Focus::Marks => "Marks",
+            Focus::Notes => "Notes",
+            }
+        }
+    }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    TogglePlayback,
+    Forward(u64),
+    Backward(u64),
+    JumpToStart,
+    JumpToEnd,
+    SetRate(f64),
+    ChangeRate(f64),
+    StartRecording,
+    StopRecording,
+    AddMark(usize),
+    AddLabelledMark(usize),
+    PreviousMark,
+    PreviousClosestMark,
+    NextMark,
+    NextClosestMark,
+    JumpToFocusedMark,
+    LabelFocusedMark,
+    DeleteFocusedMark,
+    SelectPreviousMark,
+    SelectNextMark,
+    AnnounceStatus,
+    CommandPrompt,
+    Save,
+    Undo,
+    Redo,
+    Help,
+    NextFocus,
+    PreviousFocus,
+    Quit,
+    Insert(char),
+    NewLine,
+    Backspace,
+    Delete,
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+    CursorHome,
+    CursorEnd,
+    LeaveNotes,
+    }
+
+/// The action of the key in the focused part. The playback keys mirror the U I O / J K L / M , . layout of the GUI, without the modifier.
+pub fn action(focus: Focus, key: KeyEvent) -> Option<Action> {
+    let control=key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt=key.modifiers.contains(KeyModifiers::ALT);
+
+    //Keys working everywhere
+    match key.code {
+        KeyCode::Char('c') if control => return Some(Action::Quit),
+        KeyCode::Char('s') if control => return Some(Action::Save),
+        KeyCode::Char('z') if control => return Some(Action::Undo),
+        KeyCode::Char('y') if control => return Some(Action::Redo),
+        KeyCode::Tab => return Some(Action::NextFocus),
+        KeyCode::BackTab => return Some(Action::PreviousFocus),
+        _ => {},
+        }
+
+    if control {
+        return None;
+        }
+
+    if focus==Focus::Notes {
+        let editing=match key.code {
+            KeyCode::Char(character) if !alt => Some(Action::Insert(character)),
+            KeyCode::Enter => Some(Action::NewLine),
+            KeyCode::Backspace => Some(Action::Backspace),
+            KeyCode::Delete => Some(Action::Delete),
+            KeyCode::Left => Some(Action::CursorLeft),
+            KeyCode::Right => Some(Action::CursorRight),
+            KeyCode::Up => Some(Action::CursorUp),
+            KeyCode::Down => Some(Action::CursorDown),
+            KeyCode::Home => Some(Action::CursorHome),
+            KeyCode::End => Some(Action::CursorEnd),
+            KeyCode::Esc => Some(Action::LeaveNotes),
+            _ => None,
+            };
+
+        //The playback keys are reached with Alt while writing
+        if editing.is_some() || !alt {
+            return editing;
+            }
+        }
+    else if focus==Focus::Marks {
+        match key.code {
+            KeyCode::Up => return Some(Action::SelectPreviousMark),
+            KeyCode::Down => return Some(Action::SelectNextMark),
+            KeyCode::Enter => return Some(Action::JumpToFocusedMark),
+            KeyCode::Delete => return Some(Action::DeleteFocusedMark),
+            _ => {},
+            }
+        }
+
+    let action=match key.code {
+        KeyCode::Char(digit @ '1'..='9') => {
+            let category=digit.to_digit(10).unwrap() as usize;
+
+            if alt && focus!=Focus::Notes { Action::AddLabelledMark(category) } else { Action::AddMark(category) }
+            },
+        KeyCode::Char('k') | KeyCode::Char(' ') => Action::TogglePlayback,
+        KeyCode::Char('j') => Action::Backward(5),
+        KeyCode::Char('J') => Action::Backward(10),
+        KeyCode::Char('h') => Action::Backward(60),
+        KeyCode::Char('l') => Action::Forward(5),
+        KeyCode::Char('L') => Action::Forward(10),
+        KeyCode::Char(';') => Action::Forward(60),
+        KeyCode::Char('H') | KeyCode::Home => Action::JumpToStart,
+        KeyCode::End => Action::JumpToEnd,
+        KeyCode::Char('u') => Action::SetRate(0.5),
+        KeyCode::Char('U') => Action::ChangeRate(-0.25),
+        KeyCode::Char('i') => Action::SetRate(1.0),
+        KeyCode::Char('O') => Action::ChangeRate(0.25),
+        KeyCode::Char('o') => Action::SetRate(2.0),
+        KeyCode::Char('p') => Action::SetRate(3.0),
+        KeyCode::Char('r') => Action::StartRecording,
+        KeyCode::Char('R') => Action::StopRecording,
+        KeyCode::Char('m') => Action::PreviousMark,
+        KeyCode::Char('M') => Action::PreviousClosestMark,
+        KeyCode::Char('.') => Action::NextMark,
+        KeyCode::Char('>') => Action::NextClosestMark,
+        KeyCode::Char(',') => Action::JumpToFocusedMark,
+        KeyCode::Char('<') => Action::LabelFocusedMark,
+        KeyCode::Char('s') => Action::AnnounceStatus,
+        KeyCode::Char(':') => Action::CommandPrompt,
+        KeyCode::Char('?') => Action::Help,
+        KeyCode::Char('q') => Action::Quit,
+        _ => return None,
+        };
+
+    Some(action)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+        }
+
+    #[test]
+    fn key_action_test() {
+        let none=KeyModifiers::NONE;
+
+        assert_eq!(action(Focus::Playback, key(KeyCode::Char('k'), none)), Some(Action::TogglePlayback));
+        assert_eq!(action(Focus::Playback, key(KeyCode::Char('J'), KeyModifiers::SHIFT)), Some(Action::Backward(10)));
+        assert_eq!(action(Focus::Playback, key(KeyCode::Char('3'), none)), Some(Action::AddMark(3)));
+        assert_eq!(action(Focus::Playback, key(KeyCode::Char('3'), KeyModifiers::ALT)), Some(Action::AddLabelledMark(3)));
+        assert_eq!(action(Focus::Playback, key(KeyCode::Up, none)), None);
+        assert_eq!(action(Focus::Marks, key(KeyCode::Up, none)), Some(Action::SelectPreviousMark));
+        assert_eq!(action(Focus::Marks, key(KeyCode::Char('.'), none)), Some(Action::NextMark));
+
+        //Writing notes takes the letters, the playback keys need Alt
+        assert_eq!(action(Focus::Notes, key(KeyCode::Char('k'), none)), Some(Action::Insert('k')));
+        assert_eq!(action(Focus::Notes, key(KeyCode::Char('k'), KeyModifiers::ALT)), Some(Action::TogglePlayback));
+        assert_eq!(action(Focus::Notes, key(KeyCode::Char('2'), KeyModifiers::ALT)), Some(Action::AddMark(2)));
+        assert_eq!(action(Focus::Notes, key(KeyCode::Up, none)), Some(Action::CursorUp));
+
+        assert_eq!(action(Focus::Notes, key(KeyCode::Char('s'), KeyModifiers::CONTROL)), Some(Action::Save));
+        assert_eq!(action(Focus::Playback, key(KeyCode::Char('x'), KeyModifiers::CONTROL)), None);
+        assert_eq!(action(Focus::Notes, key(KeyCode::Tab, none)), Some(Action::NextFocus));
+        }
+    }
@@ -1,59 +1,84 @@
+use std::io::{BufRead, IsTerminal};
+
 use sdam::Sdam;
 
+mod app;
+mod editor;
+mod keys;
+mod protocol;
+mod time;
+
+use app::{App, Options};
+use protocol::Command;
+
+/// Runs the interactive interface in a terminal, or the command protocol when driven by a script.
+/// --script forces the protocol, --screen-reader makes the interface friendlier to screen readers, --control ADDRESS starts the control server,
+/// --control-remote lets it listen on addresses reachable from other computers.
 fn main() {
+    let arguments: Vec<String>=std::env::args().skip(1).collect();
+    let script=arguments.iter().any(|argument| argument=="--script");
+    let screen_reader=arguments.iter().any(|argument| argument=="--screen-reader");
+    let control_remote=arguments.iter().any(|argument| argument=="--control-remote");
+    let control_address=match arguments.iter().position(|argument| argument=="--control") {
+        //The next option isn't an address, e.g. in --control --script
+        Some(index) => match arguments.get(index+1).filter(|address| !address.starts_with("--")) {
+            Some(address) => Some(address),
+            None => {
+                eprintln!("Error: --control needs the address to listen on, e.g. --control 127.0.0.1:7810");
+                std::process::exit(2);
+                },
+            },
+        None => None,
+        };
+
     let mut sdam=Sdam::new();
 
-    let stdin=std::io::stdin();
+    //Declared after sdam, so it stops before the document is dropped
+    let _control_server=match control_address {
+        Some(address) => match sdam.start_control_server(address, control_remote) {
+            Ok(server) => {
+                eprintln!("Control server listening on {}", server.address());
+                Some(server)
+                },
+            Err(error) => {
+                eprintln!("Error: can't start the control server: {}", error);
+                return;
+                },
+            },
+        None => None,
+        };
+
+    if !script && std::io::stdin().is_terminal() && std::io::stdout().is_terminal() {
+        if let Err(error)=App::new(&mut sdam, Options {screen_reader}).run() {
+            eprintln!("Error: {}", error);
+            }
 
-    loop {
-        let mut line=String::new();
-        stdin.read_line(&mut line).unwrap();
-        let line=line.trim().to_string();
+        return;
+        }
 
-        if line.contains("=") {
-            let parts: Vec<&str>=line.split("=").collect();
+    run_script(&mut sdam);
+    }
 
-            if parts.len()!=2 {
-                continue;
-                }
+/// Reads commands from the standard input, one per line, and answers every one of them by a line of JSON on the standard output.
+fn run_script(sdam: &mut Sdam) {
+    let stdin=std::io::stdin();
 
-            let property: &str=&parts[0];
-            let value: &str=&parts[1];
+    for line in stdin.lock().lines() {
+        let line=match line {
+            Ok(line) => line,
+            Err(_) => break,
+            };
 
-            match property {
-                "rate" | "r" => {
-                    let rate: f64=value.parse().unwrap();
+        let result=match protocol::parse(&line) {
+            Ok(None) => continue,
+            Ok(Some(Command::Quit)) => {
+                println!("{}", protocol::response(&Ok(serde_json::Value::Null)));
+                break;
+                },
+            Ok(Some(command)) => protocol::execute(sdam, command),
+            Err(error) => Err(error),
+            };
 
-                    sdam.set_rate(rate);
-                    println!("Rate set");
-                    },
-                _ => {},
-                }
-            }
-        else {
-            match &line[..] {
-                "record start" | "r" => sdam.start_recording(),
-                "record stop" | "rs" => sdam.stop_recording(),
-                "play" | "p" => sdam.play(),
-                "toggle" | "t" => sdam.toggle_playback(),
-                "forward" | "f" => sdam.forward(5),
-                "backward" | "b" => sdam.backward(5),
-                "load" | "l" => {
-
-                    match sdam.load("output.sdam") {
-                        Ok(_) => println!("Loaded"),
-                        Err(msg) => eprintln!("Error: {msg}"),
-                        }
-                    },
-                "save" | "s" => {
-                    match sdam.save(Some("output.sdam")) {
-                        Ok(_) => println!("Saved"),
-                        Err(msg) => eprintln!("Error: {msg}"),
-                        }
-                    },
-                "quit" | "q" => break,
-                _ => {},
-                }
-            }
+        println!("{}", protocol::response(&result));
         }
     }
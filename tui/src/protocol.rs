@@ -0,0 +1,482 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use sdam::{Mark, Sdam};
+
+use crate::time::{format_time, parse_time};
+
+/// The seconds skipped by forward and backward without an argument.
+const DEFAULT_SKIP_SECONDS: u64=5;
+
+/// Usages and descriptions of the commands, listed by help.
+pub const COMMANDS: &[(&str, &str)]=&[
+    ("load <path>", "Opens the document at the path"),
+    ("save [<path>]", "Saves the document, to the path if given"),
+    ("path", "The path of the document"),
+    ("record start|stop", "Starts or stops the recording"),
+    ("play", "Starts the playback"),
+    ("pause", "Pauses the playback"),
+    ("toggle", "Toggles the playback"),
+    ("seek <time>", "Jumps to the time, given as seconds, minutes:seconds or hours:minutes:seconds"),
+    ("forward [<seconds>]", "Skips forward, 5 seconds by default"),
+    ("backward [<seconds>]", "Skips backward, 5 seconds by default"),
+    ("rate <rate>", "Sets the playback rate"),
+    ("position", "The playback position"),
+    ("duration", "The duration of the recording"),
+    ("status", "Position, duration, rate and state of the playback and recording"),
+    ("marks", "The marks as a list"),
+    ("mark add <category> [--at <time>] [<label>]", "Adds a mark at the playback position or the given time"),
+    ("mark edit <id> <category> [<label>]", "Changes the category and label of a mark"),
+    ("mark delete <id>", "Deletes a mark"),
+    ("mark next|previous", "Jumps to the next or previous mark"),
+    ("notes", "The notes, both as text and anchored lines"),
+    ("notes set <text>", "Replaces the notes"),
+    ("notes append <line>", "Adds a line to the notes"),
+    ("undo", "Undoes the last edit"),
+    ("redo", "Redoes the last undone edit"),
+    ("help", "Lists the commands"),
+    ("quit", "Ends the session"),
+    ];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Load(String),
+    Save(Option<String>),
+    Path,
+    StartRecording,
+    StopRecording,
+    Play,
+    Pause,
+    Toggle,
+    Seek(Duration),
+    Forward(Duration),
+    Backward(Duration),
+    SetRate(f64),
+    Position,
+    Duration,
+    Status,
+    Marks,
+    AddMark {category: usize, at: Option<Duration>, label: Option<String>},
+    EditMark {id: u64, category: usize, label: Option<String>},
+    DeleteMark(u64),
+    NextMark,
+    PreviousMark,
+    Notes,
+    SetNotes(String),
+    AppendNote(String),
+    Undo,
+    Redo,
+    Help,
+    Quit,
+    }
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all="snake_case")]
+pub enum ErrorCode {
+    /// The line couldn't be split into words, e.g. because of an unterminated quote.
+    ParseError,
+    UnknownCommand,
+    /// An argument is missing, superfluous or has a wrong format.
+    InvalidArgument,
+    /// The mark, file or position the command refers to doesn't exist.
+    NotFound,
+    /// The command was understood, but SDAM failed to carry it out.
+    Failed,
+    }
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProtocolError {
+    code: ErrorCode,
+    message: String,
+    }
+impl ProtocolError {
+
+    pub fn new(code: ErrorCode, message: &str) -> ProtocolError {
+        ProtocolError {
+            code,
+            message: message.to_string(),
+            }
+        }
+    }
+
+/// Parses a line into a command. Empty lines and comments starting with # give None.
+pub fn parse(line: &str) -> Result<Option<Command>, ProtocolError> {
+    let mut words=split_words(line)?;
+
+    if words.is_empty() || words[0].starts_with('#') {
+        return Ok(None);
+        }
+
+    //The older property=value form, such as rate=1.5
+    if let Some((property, value))=words[0].clone().split_once('=') {
+        words.splice(0..1, [property.to_string(), value.to_string()]);
+        }
+
+    let command=words[0].to_lowercase();
+    let arguments=&words[1..];
+
+    let parsed=match (&command[..], arguments) {
+        ("load" | "l", [path]) => Command::Load(path.clone()),
+        ("save" | "s", []) => Command::Save(None),
+        ("save" | "s", [path]) => Command::Save(Some(path.clone())),
+        ("path", []) => Command::Path,
+        ("record", [action]) => match &action[..] {
+            "start" => Command::StartRecording,
+            "stop" => Command::StopRecording,
+            _ => return Err(invalid_argument(&format!("Expected start or stop, got '{}'", action))),
+            },
+        ("r", []) => Command::StartRecording,
+        ("rs", []) => Command::StopRecording,
+        ("play" | "p", []) => Command::Play,
+        ("pause", []) => Command::Pause,
+        ("toggle" | "t", []) => Command::Toggle,
+        ("seek", [time]) => Command::Seek(parse_time(time).map_err(|message| invalid_argument(&message))?),
+        ("forward" | "f", []) => Command::Forward(Duration::from_secs(DEFAULT_SKIP_SECONDS)),
+        ("forward" | "f", [seconds]) => Command::Forward(parse_time(seconds).map_err(|message| invalid_argument(&message))?),
+        ("backward" | "b", []) => Command::Backward(Duration::from_secs(DEFAULT_SKIP_SECONDS)),
+        ("backward" | "b", [seconds]) => Command::Backward(parse_time(seconds).map_err(|message| invalid_argument(&message))?),
+        ("rate" | "r", [rate]) => match rate.parse::<f64>() {
+            Ok(rate) if rate>0.0 && rate.is_finite() => Command::SetRate(rate),
+            _ => return Err(invalid_argument(&format!("'{}' is not a valid rate", rate))),
+            },
+        ("position", []) => Command::Position,
+        ("duration", []) => Command::Duration,
+        ("status", []) => Command::Status,
+        ("marks", []) => Command::Marks,
+        ("mark", [action, mark_arguments @ ..]) => parse_mark_command(action, mark_arguments)?,
+        ("notes", []) => Command::Notes,
+        ("notes", [action, text @ ..]) if action=="set" => Command::SetNotes(text.join(" ")),
+        ("notes", [action, text @ ..]) if action=="append" => Command::AppendNote(text.join(" ")),
+        ("undo", []) => Command::Undo,
+        ("redo", []) => Command::Redo,
+        ("help" | "h" | "?", []) => Command::Help,
+        ("quit" | "q" | "exit", []) => Command::Quit,
+        _ => {
+            return if COMMANDS.iter().any(|(usage, _)| usage.split(' ').next()==Some(&command[..])) {
+                Err(invalid_argument(&format!("Wrong arguments for {}, see help", command)))
+                }
+            else {
+                Err(ProtocolError::new(ErrorCode::UnknownCommand, &format!("Unknown command '{}'", command)))
+                };
+            },
+        };
+
+    Ok(Some(parsed))
+    }
+
+fn parse_mark_command(action: &str, arguments: &[String]) -> Result<Command, ProtocolError> {
+    match (action, arguments) {
+        ("add", [category, rest @ ..]) => {
+            let category=parse_category(category)?;
+
+            let (at, label)=match rest {
+                [option, time, label @ ..] if option=="--at" => (Some(parse_time(time).map_err(|message| invalid_argument(&message))?), label),
+                label => (None, label),
+                };
+
+            Ok(Command::AddMark {category, at, label: join_label(label)})
+            },
+        ("edit", [id, category, label @ ..]) => Ok(Command::EditMark {id: parse_id(id)?, category: parse_category(category)?, label: join_label(label)}),
+        ("delete", [id]) => Ok(Command::DeleteMark(parse_id(id)?)),
+        ("next", []) => Ok(Command::NextMark),
+        ("previous", []) => Ok(Command::PreviousMark),
+        _ => Err(invalid_argument("Expected mark add, edit, delete, next or previous, see help")),
+        }
+    }
+fn parse_category(text: &str) -> Result<usize, ProtocolError> {
+    match text.parse::<usize>() {
+        Ok(category) if category>=1 => Ok(category),
+        _ => Err(invalid_argument(&format!("'{}' is not a valid mark category", text))),
+        }
+    }
+fn parse_id(text: &str) -> Result<u64, ProtocolError> {
+    text.parse::<u64>().map_err(|_| invalid_argument(&format!("'{}' is not a valid mark id", text)))
+    }
+fn join_label(words: &[String]) -> Option<String> {
+    if words.is_empty() {
+        None
+        }
+    else {
+        Some(words.join(" "))
+        }
+    }
+
+/// Splits the line at whitespace, keeping text in double quotes together. Quoted text may contain \", \\ and \n.
+fn split_words(line: &str) -> Result<Vec<String>, ProtocolError> {
+    let mut words: Vec<String>=Vec::new();
+    let mut characters=line.trim().chars();
+
+    while let Some(character)=characters.next() {
+        if character.is_whitespace() {
+            continue;
+            }
+
+        let mut word=String::new();
+
+        if character=='"' {
+            loop {
+                match characters.next() {
+                    Some('"') => break,
+                    Some('\\') => match characters.next() {
+                        Some('n') => word.push('\n'),
+                        Some(escaped @ ('"' | '\\')) => word.push(escaped),
+                        Some(other) => return Err(ProtocolError::new(ErrorCode::ParseError, &format!("Unknown escape \\{}", other))),
+                        None => return Err(ProtocolError::new(ErrorCode::ParseError, "Unterminated quote")),
+                        },
+                    Some(other) => word.push(other),
+                    None => return Err(ProtocolError::new(ErrorCode::ParseError, "Unterminated quote")),
+                    }
+                }
+            }
+        else {
+            word.push(character);
+
+            for character in characters.by_ref() {
+                if character.is_whitespace() {
+                    break;
+                    }
+
+                word.push(character);
+                }
+            }
+
+        words.push(word);
+        }
+
+    Ok(words)
+    }
+
+fn invalid_argument(message: &str) -> ProtocolError {
+    ProtocolError::new(ErrorCode::InvalidArgument, message)
+    }
+
+/// Carries out the command, returning its result as JSON. Commands without a result return null.
+pub fn execute(sdam: &mut Sdam, command: Command) -> Result<Value, ProtocolError> {
+    let failed=|error: anyhow::Error| ProtocolError::new(ErrorCode::Failed, &error.to_string());
+
+    let result=match command {
+        Command::Load(path) => {
+            sdam.load(&path).map_err(failed)?;
+            Value::Null
+            },
+        Command::Save(path) => {
+            sdam.save(path.as_deref()).map_err(failed)?;
+            json!(sdam.file_path())
+            },
+        Command::Path => json!(sdam.file_path()),
+        Command::StartRecording => {
+            sdam.start_recording();
+            Value::Null
+            },
+        Command::StopRecording => {
+            sdam.stop_recording();
+            Value::Null
+            },
+        Command::Play => {
+            sdam.play();
+            Value::Null
+            },
+        Command::Pause => {
+            sdam.pause();
+            Value::Null
+            },
+        Command::Toggle => {
+            sdam.toggle_playback();
+            Value::Null
+            },
+        Command::Seek(time) => {
+            if time>sdam.duration() {
+                return Err(ProtocolError::new(ErrorCode::NotFound, &format!("{} is past the end of the recording", format_time(time))));
+                }
+
+            sdam.seek(time);
+            time_json(sdam.position())
+            },
+        Command::Forward(delta) => {
+            sdam.forward_by(delta);
+            time_json(sdam.position())
+            },
+        Command::Backward(delta) => {
+            sdam.backward_by(delta);
+            time_json(sdam.position())
+            },
+        Command::SetRate(rate) => {
+            sdam.set_rate(rate);
+            json!(rate)
+            },
+        Command::Position => time_json(sdam.position()),
+        Command::Duration => time_json(Some(sdam.duration())),
+        Command::Status => status(sdam),
+        Command::Marks => Value::Array(sdam.marks().iter().map(mark_json).collect()),
+        Command::AddMark {category, at, label} => {
+            let mark=match at.or_else(|| sdam.position()) {
+                Some(time) => Mark::at(time, category, label),
+                None => return Err(ProtocolError::new(ErrorCode::NotFound, "There's no playback position to mark")),
+                };
+
+            mark_json(&sdam.add_mark(mark))
+            },
+        Command::EditMark {id, category, label} => {
+            let mark=existing_mark(sdam.get_mark(id), id)?;
+
+            sdam.edit_mark(id, Mark::new(*mark.frame_offset(), category, label));
+            mark_json(&existing_mark(sdam.get_mark(id), id)?)
+            },
+        Command::DeleteMark(id) => {
+            existing_mark(sdam.get_mark(id), id)?;
+
+            sdam.delete_mark(id);
+            Value::Null
+            },
+        Command::NextMark | Command::PreviousMark => {
+            let frame=sdam.current_position().unwrap_or(0);
+            let mark=if command==Command::NextMark { sdam.next_closest_mark(frame) } else { sdam.previous_closest_mark(frame) };
+            let mark=mark.ok_or_else(|| ProtocolError::new(ErrorCode::NotFound, "There's no mark in that direction"))?;
+
+            sdam.jump_to_frame(*mark.frame_offset());
+            mark_json(&mark)
+            },
+        Command::Notes => {
+            let lines: Vec<Value>=sdam.note_lines().iter()
+            .map(|line| json!({
+                "text": line.text(),
+                "frame_offset": line.frame_offset(),
+                "time": line.frame_offset().map(|frame_offset| format_time(sdam::frame_offset_to_duration(frame_offset))),
+                }))
+            .collect();
+
+            json!({"text": sdam.user_text(), "lines": lines})
+            },
+        Command::SetNotes(text) => {
+            sdam.set_user_text(&text);
+            Value::Null
+            },
+        Command::AppendNote(line) => {
+            let text=sdam.user_text();
+
+            if text.is_empty() {
+                sdam.set_user_text(&line);
+                }
+            else {
+                sdam.set_user_text(&format!("{}\n{}", text, line));
+                }
+
+            Value::Null
+            },
+        Command::Undo => json!(sdam.undo()),
+        Command::Redo => json!(sdam.redo()),
+        Command::Help => Value::Array(COMMANDS.iter().map(|(usage, description)| json!({"usage": usage, "description": description})).collect()),
+        Command::Quit => Value::Null,
+        };
+
+    Ok(result)
+    }
+
+/// Formats the result as a line of JSON, either {"ok": true, "result": ...} or {"ok": false, "error": {"code": ..., "message": ...}}.
+pub fn response(result: &Result<Value, ProtocolError>) -> String {
+    let response=match result {
+        Ok(Value::Null) => json!({"ok": true}),
+        Ok(value) => json!({"ok": true, "result": value}),
+        Err(error) => json!({"ok": false, "error": error}),
+        };
+
+    response.to_string()
+    }
+
+fn status(sdam: &mut Sdam) -> Value {
+    json!({
+        "position": time_json(sdam.position()),
+        "duration": time_json(Some(sdam.duration())),
+        "rate": sdam.rate(),
+        "playing": sdam.is_playing(),
+        "recording": sdam.is_recording(),
+        "modified": sdam.is_modified(),
+        "path": sdam.file_path(),
+        })
+    }
+fn time_json(time: Option<Duration>) -> Value {
+    match time {
+        Some(time) => json!({"millis": time.as_millis() as u64, "text": format_time(time)}),
+        None => Value::Null,
+        }
+    }
+fn mark_json(mark: &Mark) -> Value {
+    json!({
+        "id": mark.id(),
+        "frame_offset": mark.frame_offset(),
+        "time": time_json(Some(mark.time())),
+        "category": mark.category(),
+        "label": mark.label(),
+        })
+    }
+/// The looked up mark, or the not found error for marks which don't exist.
+fn existing_mark(mark: Option<Mark>, id: u64) -> Result<Mark, ProtocolError> {
+    mark.ok_or_else(|| ProtocolError::new(ErrorCode::NotFound, &format!("There's no mark {}", id)))
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(parse("  "), Ok(None));
+        assert_eq!(parse("# a comment"), Ok(None));
+        assert_eq!(parse("load \"My lectures/week 1.sdam\""), Ok(Some(Command::Load("My lectures/week 1.sdam".to_string()))));
+        assert_eq!(parse("save"), Ok(Some(Command::Save(None))));
+        assert_eq!(parse("seek 1:30"), Ok(Some(Command::Seek(Duration::from_secs(90)))));
+        assert_eq!(parse("b"), Ok(Some(Command::Backward(Duration::from_secs(5)))));
+        assert_eq!(parse("rate=1.5"), Ok(Some(Command::SetRate(1.5))));
+        assert_eq!(parse("RATE 0.8"), Ok(Some(Command::SetRate(0.8))));
+        assert_eq!(parse("mark add 2 --at 10 Entropy defined"), Ok(Some(Command::AddMark {
+            category: 2,
+            at: Some(Duration::from_secs(10)),
+            label: Some("Entropy defined".to_string()),
+            })));
+        assert_eq!(parse("mark add 1"), Ok(Some(Command::AddMark {category: 1, at: None, label: None})));
+        assert_eq!(parse("notes set \"First line\\nSecond \\\"line\\\"\""), Ok(Some(Command::SetNotes("First line\nSecond \"line\"".to_string()))));
+        }
+
+    #[test]
+    fn parse_error_test() {
+        let code=|line: &str| parse(line).unwrap_err().code;
+
+        assert_eq!(code("dance"), ErrorCode::UnknownCommand);
+        assert_eq!(code("seek"), ErrorCode::InvalidArgument);
+        assert_eq!(code("seek soon"), ErrorCode::InvalidArgument);
+        assert_eq!(code("rate -1"), ErrorCode::InvalidArgument);
+        assert_eq!(code("mark add 0"), ErrorCode::InvalidArgument);
+        assert_eq!(code("mark remove 3"), ErrorCode::InvalidArgument);
+        assert_eq!(code("record later"), ErrorCode::InvalidArgument);
+        assert_eq!(code("load \"unterminated"), ErrorCode::ParseError);
+        }
+
+    #[test]
+    fn response_test() {
+        assert_eq!(response(&Ok(Value::Null)), r#"{"ok":true}"#);
+        assert_eq!(response(&Ok(json!(1.5))), r#"{"ok":true,"result":1.5}"#);
+        assert_eq!(response(&Err(ProtocolError::new(ErrorCode::NotFound, "There's no mark 3"))), r#"{"error":{"code":"not_found","message":"There's no mark 3"},"ok":false}"#);
+        }
+
+    #[test]
+    fn unknown_mark_test() {
+        assert_eq!(parse("mark delete 99"), Ok(Some(Command::DeleteMark(99))));
+
+        //Sdam::get_mark answers None for marks which don't exist, which must become an error response, not a crash
+        let result=existing_mark(None, 99).map(|mark| mark_json(&mark));
+        assert_eq!(response(&result), r#"{"error":{"code":"not_found","message":"There's no mark 99"},"ok":false}"#);
+
+        let mark=Mark::new(25, 1, None).with_id(99);
+        assert!(existing_mark(Some(mark), 99).is_ok());
+
+        //The whole way through the dispatcher and the audio handler
+        let mut sdam=Sdam::new();
+        for command in [Command::DeleteMark(99), Command::EditMark {id: 99, category: 2, label: None}] {
+            let result=execute(&mut sdam, command);
+            assert_eq!(response(&result), r#"{"error":{"code":"not_found","message":"There's no mark 99"},"ok":false}"#);
+            }
+        }
+    }
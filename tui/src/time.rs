@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+pub use sdam::parse_time;
+
+/// Formats the time as minutes:seconds, like the GUI does.
+pub fn format_time(time: Duration) -> String {
+    let seconds=time.as_secs();
+
+    format!("{:0>2}:{:0>2}", seconds/60, seconds%60)
+    }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn time_test() {
+        assert_eq!(format_time(Duration::from_millis(3723900)), "62:03");
+        assert_eq!(format_time(Duration::ZERO), "00:00");
+        }
+    }